@@ -0,0 +1,52 @@
+//! Compares the table-driven ARM decoder against the nested-match reference decoder, and measures
+//! the throughput of the unconditional ARM space and of THUMB decoding.
+//!
+//! Run with `cargo bench --bench disasm`
+
+use ndsjit::disasm::{disassemble_arm, disassemble_arm_nested, disassemble_thumb, Arch};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const NUM_INSTRUCTIONS: usize = 1 << 20;
+const ROUNDS: usize = 10;
+
+fn bench(name: &str, words: &[u32], decode: fn(u32, Arch) -> bool) -> Duration {
+    // Warm up caches and branch predictors before timing
+    for &word in words {
+        black_box(decode(black_box(word), Arch::ARMv5TE));
+    }
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for &word in words {
+            black_box(decode(black_box(word), Arch::ARMv5TE));
+        }
+    }
+    let elapsed = start.elapsed();
+    let per_instr = elapsed.as_nanos() as f64 / (ROUNDS * words.len()) as f64;
+    println!("{name:>8}: {elapsed:>10.2?} ({per_instr:.1} ns/instruction)");
+    elapsed
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(0);
+    // Conditional words only, the unconditional space is decoded the same way by both
+    let words: Vec<u32> = (0..NUM_INSTRUCTIONS)
+        .map(|_| rng.gen_range(0..0xF000_0000))
+        .collect();
+
+    println!("decoding {} instructions x {ROUNDS}", words.len());
+    let nested = bench("nested", &words, |w, arch| disassemble_arm_nested(w, arch).is_ok());
+    let table = bench("table", &words, |w, arch| disassemble_arm(w, arch).is_ok());
+    println!("speedup: {:.2}x", nested.as_secs_f64() / table.as_secs_f64());
+
+    let unconditional: Vec<u32> = (0..NUM_INSTRUCTIONS)
+        .map(|_| rng.gen_range(0xF000_0000..=u32::MAX))
+        .collect();
+    bench("uncond", &unconditional, |w, arch| disassemble_arm(w, arch).is_ok());
+
+    let halfwords: Vec<u32> = (0..NUM_INSTRUCTIONS)
+        .map(|_| rng.gen_range(0..0x1_0000))
+        .collect();
+    bench("thumb", &halfwords, |w, arch| disassemble_thumb(w as u16, arch).is_ok());
+}
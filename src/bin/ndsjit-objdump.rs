@@ -0,0 +1,190 @@
+//! Disassembles a raw binary, or the ARM9/ARM7 code of a .nds image, in the style of `objdump -d`
+use std::{env, error::Error, fs, process};
+
+use ndsjit::disasm::{Arch, BlockDisassembler, CodeSlice, DisasmError, InstrSet};
+use ndsjit::rom::RomHeader;
+
+const USAGE: &str = "\
+usage: ndsjit-objdump [options] FILE
+
+Disassembles FILE as a raw binary, or the ARM9 code of a .nds image if the name ends in .nds
+
+options:
+    -t, --thumb           decode THUMB instead of ARM instructions
+    --arm9, --arm7        disassemble the ARM9/ARM7 code of a .nds image
+    --raw                 treat FILE as a raw binary, even if it is named .nds
+    --arch v4t|v5te       architecture version, defaults to v4t for the ARM7 and v5te otherwise
+    -s, --start OFFSET    offset into the file or code section to start at, defaults to 0
+    -a, --addr ADDR       load address of the start, defaults to the offset in a raw binary or
+                          the address the code section is loaded at plus the offset
+    -n, --length BYTES    number of bytes to disassemble, defaults to the rest of the input
+    -h, --help            print this message
+
+Numbers are decimal, or hexadecimal with a 0x prefix";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Section {
+    Raw,
+    ARM9,
+    ARM7,
+}
+
+#[derive(Debug)]
+struct Options {
+    path: String,
+    instr_set: InstrSet,
+    section: Option<Section>,
+    arch: Option<Arch>,
+    start: u32,
+    addr: Option<u32>,
+    length: Option<u32>,
+}
+
+fn parse_num(s: &str) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|e| format!("invalid number '{}': {}", s, e))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut path = None;
+    let mut options = Options {
+        path: String::new(),
+        instr_set: InstrSet::ARM,
+        section: None,
+        arch: None,
+        start: 0,
+        addr: None,
+        length: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "-t" | "--thumb" => options.instr_set = InstrSet::THUMB,
+            "--arm9" => options.section = Some(Section::ARM9),
+            "--arm7" => options.section = Some(Section::ARM7),
+            "--raw" => options.section = Some(Section::Raw),
+            "--arch" => {
+                options.arch = Some(match value()?.to_lowercase().as_str() {
+                    "v4t" | "armv4t" => Arch::ARMv4T,
+                    "v5te" | "armv5te" => Arch::ARMv5TE,
+                    other => return Err(format!("unknown architecture '{}'", other)),
+                })
+            }
+            "-s" | "--start" => options.start = parse_num(&value()?)?,
+            "-a" | "--addr" => options.addr = Some(parse_num(&value()?)?),
+            "-n" | "--length" => options.length = Some(parse_num(&value()?)?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    options.path = path.ok_or_else(|| USAGE.to_string())?;
+    Ok(options)
+}
+
+/// Formats the raw encoding the way objdump does, as halfwords for THUMB
+fn fmt_raw(raw: u32, size: u32, instr_set: InstrSet) -> String {
+    match (instr_set, size) {
+        (InstrSet::ARM, _) => format!("{:08x}", raw),
+        (InstrSet::THUMB, 2) => format!("{:04x}     ", raw),
+        (InstrSet::THUMB, _) => format!("{:04x} {:04x}", raw & 0xFFFF, raw >> 16),
+    }
+}
+
+fn run(options: Options) -> Result<(), Box<dyn Error>> {
+    let file = fs::read(&options.path)?;
+    let section = options
+        .section
+        .unwrap_or(match options.path.to_lowercase().ends_with(".nds") {
+            true => Section::ARM9,
+            false => Section::Raw,
+        });
+    let (code, load_addr) = match section {
+        Section::Raw => (&file[..], 0),
+        Section::ARM9 | Section::ARM7 => {
+            let header = RomHeader::parse(&file)?;
+            let code = match section {
+                Section::ARM9 => header.arm9,
+                _ => header.arm7,
+            };
+            println!(
+                "{}: {:?} code at file offset {:#x}, loaded at {:#010x}, entry {:#010x}",
+                header.title, section, code.rom_offset, code.ram_address, code.entry
+            );
+            (&file[code.range()], code.ram_address)
+        }
+    };
+    let arch = options.arch.unwrap_or(match section {
+        Section::ARM7 => Arch::ARMv4T,
+        _ => Arch::ARMv5TE,
+    });
+
+    let start = options.start as usize;
+    let code = code
+        .get(start..)
+        .ok_or_else(|| format!("start offset {:#x} is past the end", start))?;
+    let code = match options.length {
+        Some(length) => &code[..code.len().min(length as usize)],
+        None => code,
+    };
+    let base = options
+        .addr
+        .unwrap_or(load_addr.wrapping_add(options.start));
+    let memory = CodeSlice { base, bytes: code };
+
+    // Disassemble block by block, carrying on after each one (including those which end in an
+    // error) until the end of the input
+    let mut addr = base;
+    loop {
+        let mut block = BlockDisassembler::new(memory, addr, options.instr_set, arch);
+        let instrs: Vec<_> = block.by_ref().collect();
+        if instrs.is_empty() {
+            break;
+        }
+        let ends = instrs
+            .iter()
+            .skip(1)
+            .map(|&(addr, ..)| addr)
+            .chain([block.addr()])
+            .collect::<Vec<_>>();
+        for ((addr, raw, result), end) in instrs.into_iter().zip(ends) {
+            let raw = fmt_raw(raw, end.wrapping_sub(addr), options.instr_set);
+            match result {
+                Ok(instr) => println!("{:8x}:\t{}\t{:#}", addr, raw, instr),
+                // Unpredictable encodings still have a meaning, so show it along with the error
+                Err(err) => match &err {
+                    DisasmError::Unpredictable { decoded, .. } => {
+                        println!("{:8x}:\t{}\t{:#}\t; {}", addr, raw, decoded, err)
+                    }
+                    _ => println!("{:8x}:\t{}\t<{}>", addr, raw, err),
+                },
+            }
+        }
+        addr = block.addr();
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        }
+    };
+    if let Err(err) = run(options) {
+        eprintln!("ndsjit-objdump: {}", err);
+        process::exit(1);
+    }
+}
@@ -1,39 +1,129 @@
 mod arm;
 mod bits;
+mod block;
 mod thumb;
 
-use crate::ir::Instruction;
+use crate::ir::{AddrMode, ExtraOperand, Instruction, Offset, OffsetValue, Op, Operand, Register};
+use crate::operands;
+pub(crate) use arm::PC_LA_ARM;
 use arm::*;
 use bits::{bit, bits};
+pub use block::{disassemble_block, BlockDisassembler, CodeSlice, InstrSet, MemoryReader};
 use std::error::Error;
 use std::fmt::Display;
 
+/// ARM architecture versions implemented by the NDS CPUs. Later versions are supersets of earlier
+/// ones, so they are ordered by version
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Arch {
+    /// ARM7TDMI
+    ARMv4T,
+    /// ARM946E-S
+    ARMv5TE,
+}
+
+/// Architecturally UNPREDICTABLE uses of an otherwise valid encoding
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Unpredictable {
+    /// PC is used as a register operand or destination where it isn't permitted
+    PcOperand,
+    /// Two register fields which must be different are the same, e.g. Rd and Rm of MUL
+    RegisterOverlap,
+    /// A load/store writes back to a base register which is also transferred, or is PC
+    WritebackBase,
+    /// LDM writes back to a base register which is also in the register list, or STM does when it
+    /// isn't the lowest register in the list
+    BaseInList,
+    /// LDM/STM of the User mode registers ("^") with writeback, other than an LDM which loads PC
+    /// and so restores CPSR instead
+    UserWriteback,
+    /// A block data transfer with no registers in the list
+    EmptyRegList,
+    /// LDRD/STRD with an odd first register, or with LR as the first register
+    OddRegisterPair,
+}
+
+impl Display for Unpredictable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            Self::PcOperand => "PC can't be used as one of its registers",
+            Self::RegisterOverlap => "two of its registers must be different",
+            Self::WritebackBase => "it writes back to PC or to the register it transfers",
+            Self::BaseInList => "it writes back to a base register which is in the list",
+            Self::UserWriteback => "it writes back while transferring the User mode registers",
+            Self::EmptyRegList => "its register list is empty",
+            Self::OddRegisterPair => "its first register must be even and not LR",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct DisasmError {
-    description: String,
-    instr: u32,
+pub enum DisasmError {
+    /// The encoding is UNDEFINED in every supported architecture version
+    Undefined { instr: u32 },
+    /// The encoding is only defined in a later architecture version than the one being decoded
+    Unsupported { instr: u32, arch: Arch },
+    /// A valid encoding which the decoder doesn't handle yet
+    Unimplemented { instr: u32 },
+    /// One half of a THUMB BL/BLX pair, which can only be decoded together with the other half
+    Incomplete { instr: u32 },
+    /// The encoding is UNPREDICTABLE. The instruction as it would be decoded otherwise is attached,
+    /// so that callers can still choose to emulate it
+    Unpredictable {
+        instr: u32,
+        reason: Unpredictable,
+        decoded: Box<Instruction>,
+    },
 }
 
 impl DisasmError {
-    pub fn new(description: &str, instr: u32) -> Self {
-        let description = String::from(description);
-        Self { description, instr }
+    pub fn undefined(instr: u32) -> Self {
+        Self::Undefined { instr }
     }
 
-    pub fn undefined(instr: u32) -> Self {
-        Self::new("undefined instruction", instr)
+    /// A valid encoding in later architecture versions, which is undefined in `arch`
+    pub fn unsupported(instr: u32, arch: Arch) -> Self {
+        Self::Unsupported { instr, arch }
+    }
+
+    pub fn unimplemented(instr: u32) -> Self {
+        Self::Unimplemented { instr }
+    }
+
+    pub fn unpredictable(instr: u32, reason: Unpredictable, decoded: Instruction) -> Self {
+        Self::Unpredictable { instr, reason, decoded: Box::new(decoded) }
     }
 
-    /// Sometimes a function doesn't have access to the whole instruction and it needs to be set
-    /// after Error is returned
-    pub fn set_instr(&self, instr: u32) -> Self {
-        Self { description: self.description.clone(), instr }
+    /// The encoding which failed to decode
+    pub fn instr(&self) -> u32 {
+        match self {
+            Self::Undefined { instr }
+            | Self::Unsupported { instr, .. }
+            | Self::Unimplemented { instr }
+            | Self::Incomplete { instr }
+            | Self::Unpredictable { instr, .. } => *instr,
+        }
     }
 }
 
 impl Display for DisasmError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}: {}", self.description, self.instr)
+        match self {
+            Self::Undefined { instr } => write!(f, "undefined instruction: {:#010x}", instr),
+            Self::Unsupported { instr, arch } => {
+                write!(f, "undefined instruction in {:?}: {:#010x}", arch, instr)
+            }
+            Self::Unimplemented { instr } => {
+                write!(f, "unimplemented instruction: {:#010x}", instr)
+            }
+            Self::Incomplete { instr } => {
+                write!(f, "incomplete BL/BLX instruction pair: {:#010x}", instr)
+            }
+            Self::Unpredictable { instr, reason, .. } => {
+                write!(f, "unpredictable instruction {:#010x}: {}", instr, reason)
+            }
+        }
     }
 }
 
@@ -41,44 +131,993 @@ impl Error for DisasmError {}
 
 type DisasmResult<T> = Result<T, DisasmError>;
 
-pub fn disassemble_arm(instr: u32) -> DisasmResult<Instruction> {
+/// Returns the decoded instruction, or an Unpredictable error carrying it for the first of the
+/// `checks` which is true
+fn check_predictable(
+    instr: u32,
+    decoded: Instruction,
+    checks: &[(bool, Unpredictable)],
+) -> DisasmResult<Instruction> {
+    match checks.iter().find(|(unpredictable, _)| *unpredictable) {
+        Some(&(_, reason)) => Err(DisasmError::unpredictable(instr, reason, decoded)),
+        None => Ok(decoded),
+    }
+}
+
+/// Decode an ARM instruction, using the encodings defined in the given architecture version
+pub fn disassemble_arm(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
     match bits(instr, 28..31) {
-        0b1111 => arm_unconditional(instr),
+        0b1111 => arm_unconditional(instr, arch),
+        _ => ARM_DECODE_TABLE[arm_table_index(instr)](instr, arch),
+    }
+}
+
+/// Decode an ARM instruction by walking the encoding tables one level at a time. Gives the same
+/// results as [disassemble_arm], which uses a precomputed dispatch table instead; this is kept as a
+/// reference for testing and benchmarking the table
+#[doc(hidden)]
+pub fn disassemble_arm_nested(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
+    match bits(instr, 28..31) {
+        0b1111 => arm_unconditional(instr, arch),
         _ => match bits(instr, 25..27) {
-            0b000 | 0b001 => arm_data_proc_and_misc(instr),
+            0b000 | 0b001 => arm_data_proc_and_misc(instr, arch),
             0b010 => arm_load_store(instr),
             0b011 => match bit(instr, 4) {
                 0 => arm_load_store(instr),
-                _ => arm_media(instr),
+                _ => arm_media(instr, arch),
             },
-            0b100 => arm_block_data_transfer(instr),
+            0b100 => arm_block_data_transfer(instr, arch),
             0b101 => arm_branch(instr),
-            0b110 | 0b111 => arm_coprocessor(instr),
-            _ => unreachable!(),
+            0b110 | 0b111 => arm_coprocessor(instr, arch),
+            _ => Err(DisasmError::undefined(instr)),
         },
     }
 }
 
-pub fn disassemble_thumb(addr: u32, instr: u16) -> DisasmResult<Instruction> {
-    // TODO - there are 32-bit THUMB encodings as well? How will those be handled? Maybe it's kicked
-    // back out to the binary reader which will return the extra byte? Or we just always send 2 and
-    // only decode the first?
-    todo!();
+/// Decode a 16-bit THUMB instruction.
+///
+/// BL and BLX (immediate) are the only 32-bit instructions in ARMv4T/ARMv5TE THUMB code. They are
+/// encoded as a pair of 16-bit halves, neither of which can be decoded alone. Use
+/// `is_thumb_bl_prefix` to check whether a halfword begins a pair, and if so pass it along with the
+/// following halfword to `disassemble_thumb_bl`.
+pub fn disassemble_thumb(instr: u16, arch: Arch) -> DisasmResult<Instruction> {
+    thumb::thumb(instr, arch)
+}
+
+/// Decode an ARM instruction located at `addr`. Branch targets, literal addresses and ADR are
+/// resolved to absolute addresses
+pub fn disassemble_arm_at(addr: u32, instr: u32, arch: Arch) -> DisasmResult<Instruction> {
+    resolve_pc_relative(disassemble_arm(instr, arch), addr.wrapping_add(arm::PC_LA_ARM))
+}
+
+/// Decode a THUMB instruction located at `addr`, resolving PC-relative operands as for
+/// [disassemble_arm_at]
+pub fn disassemble_thumb_at(addr: u32, instr: u16, arch: Arch) -> DisasmResult<Instruction> {
+    resolve_pc_relative(disassemble_thumb(instr, arch), addr.wrapping_add(thumb::PC_LA_THUMB))
+}
+
+/// Returns true if the halfword is the first half of a BL/BLX (immediate) pair
+pub fn is_thumb_bl_prefix(instr: u16) -> bool {
+    thumb::is_bl_prefix(instr)
+}
+
+/// Decode a BL or BLX (immediate) instruction from its two halfwords, in the order they appear in
+/// memory
+pub fn disassemble_thumb_bl(prefix: u16, suffix: u16, arch: Arch) -> DisasmResult<Instruction> {
+    thumb::thumb_bl_pair(prefix, suffix, arch)
+}
+
+/// Decode a BL or BLX (immediate) pair whose prefix is located at `addr`, with the absolute target
+pub fn disassemble_thumb_bl_at(
+    addr: u32,
+    prefix: u16,
+    suffix: u16,
+    arch: Arch,
+) -> DisasmResult<Instruction> {
+    let pc = addr.wrapping_add(thumb::PC_LA_THUMB);
+    resolve_pc_relative(disassemble_thumb_bl(prefix, suffix, arch), pc)
+}
+
+/// Replaces PC-relative operands with Target operands, given the value read from PC by the
+/// instruction. This also applies to the instruction attached to an Unpredictable error
+fn resolve_pc_relative(result: DisasmResult<Instruction>, pc: u32) -> DisasmResult<Instruction> {
+    let resolve = |mut instr: Instruction| {
+        // BLX (immediate) and the literal addressing forms use the word aligned PC
+        let aligned_pc = pc & !0b11;
+        match (instr.op, instr.operands.as_slice()) {
+            (Op::B | Op::BL, &[Operand::Imm(offset)]) => {
+                instr.operands[0] = Operand::Target(pc.wrapping_add(offset));
+            }
+            (Op::BLX, &[Operand::Imm(offset)]) => {
+                instr.operands[0] = Operand::Target(aligned_pc.wrapping_add(offset));
+            }
+            // A rotated immediate (see decode_rotated_imm) is left as it is
+            _ if instr.extra.is_some() && matches!(instr.op, Op::ADR | Op::ADD | Op::SUB) => {}
+            (Op::ADR, &[rd, Operand::Imm(offset)]) => {
+                instr.operands = operands![rd, Operand::Target(aligned_pc.wrapping_add(offset))];
+            }
+            (Op::ADD | Op::SUB, &[rd, Operand::Reg(Register::PC), Operand::Imm(imm)])
+                if !instr.set_flags =>
+            {
+                let target = match instr.op {
+                    Op::ADD => pc.wrapping_add(imm),
+                    _ => pc.wrapping_sub(imm),
+                };
+                instr.op = Op::ADR;
+                instr.operands = operands![rd, Operand::Target(target)];
+            }
+            _ => {
+                let literal = instr.operands.iter().position(|op| {
+                    matches!(op, Operand::Addr(addr)
+                        if addr.base == Register::PC && addr.mode == AddrMode::Offset)
+                });
+                if let (
+                    Some(i),
+                    Some(ExtraOperand::Offset(Offset { value: OffsetValue::Imm(imm), add })),
+                ) = (literal, instr.extra)
+                {
+                    let target = match add {
+                        true => aligned_pc.wrapping_add(imm),
+                        false => aligned_pc.wrapping_sub(imm),
+                    };
+                    instr.operands[i] = Operand::Target(target);
+                    instr.extra = None;
+                }
+            }
+        }
+        instr
+    };
+    match result {
+        Ok(instr) => Ok(resolve(instr)),
+        Err(DisasmError::Unpredictable { instr, reason, decoded }) => {
+            Err(DisasmError::unpredictable(instr, reason, resolve(*decoded)))
+        }
+        Err(e) => Err(e),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::disassemble_arm;
-    use crate::ir::{Cond::*, Instruction, Op::*, Operand, Register::*};
+    use crate::operands;
+    use itertools::iproduct;
+    use rand::{thread_rng, Rng};
+
+    use super::bits::bits;
+    use super::{
+        disassemble_arm, disassemble_arm_at, disassemble_arm_nested, disassemble_thumb,
+        disassemble_thumb_at, disassemble_thumb_bl, disassemble_thumb_bl_at, is_thumb_bl_prefix,
+        Arch::*, DisasmError, Unpredictable,
+    };
+    use crate::ir::{
+        AddrMode, Address, Cond::*, ImmShift, Instruction, Offset, Op::*, Operand, Operands,
+        RegList, RegSet, Register::*, ShiftOp, StatusReg::*,
+    };
 
     #[test]
     fn test_disasm_data_proc() {
         assert_eq!(
-            disassemble_arm(0x020FC00C).unwrap(),
+            disassemble_arm(0x020FC00C, ARMv4T).unwrap(),
             Instruction {
                 cond: EQ,
                 op: AND,
-                operands: vec![Operand::Reg(R12), Operand::Reg(PC), Operand::Imm(12)],
+                operands: operands![Operand::Reg(R12), Operand::Reg(PC), Operand::Imm(12)],
+                ..Default::default()
+            }
+        );
+        // movs r0, #1, encoded as 4 ROR 2 rather than unrotated
+        assert_eq!(
+            disassemble_arm(0xE3B00104, ARMv4T).unwrap(),
+            Instruction {
+                op: MOV,
+                operands: operands![Operand::Reg(R0), Operand::Imm(4)],
+                extra: Some(ImmShift { op: ShiftOp::ROR, imm: 2 }.into()),
+                set_flags: true,
+                ..Default::default()
+            }
+        );
+        // mov r0, #0x100, encoded as 4 ROR 26 rather than 1 ROR 24
+        assert_eq!(
+            disassemble_arm(0xE3A00D04, ARMv4T).unwrap().extra,
+            Some(ImmShift { op: ShiftOp::ROR, imm: 26 }.into())
+        );
+    }
+
+    #[test]
+    fn test_disasm_block_data_transfer() {
+        // push {r4, lr}
+        assert_eq!(
+            disassemble_arm(0xE92D4010, ARMv4T).unwrap(),
+            Instruction {
+                op: PUSH,
+                operands: operands![Operand::RegList(RegList::new(0x4010))],
+                ..Default::default()
+            }
+        );
+        // popne {r4, pc}
+        assert_eq!(
+            disassemble_arm(0x18BD8010, ARMv4T).unwrap(),
+            Instruction {
+                cond: NE,
+                op: POP,
+                operands: operands![Operand::RegList(RegList::new(0x8010))],
+                ..Default::default()
+            }
+        );
+        // ldmia r0!, {r1-r3}
+        assert_eq!(
+            disassemble_arm(0xE8B0000E, ARMv4T).unwrap(),
+            Instruction {
+                op: LDMIA,
+                operands: operands![Operand::RegWb(R0), Operand::RegList(RegList::new(0b1110))],
+                ..Default::default()
+            }
+        );
+        // stmdb r1, {r0, r2}
+        assert_eq!(
+            disassemble_arm(0xE9010005, ARMv4T).unwrap(),
+            Instruction {
+                op: STMDB,
+                operands: operands![Operand::Reg(R1), Operand::RegList(RegList::new(0b101))],
+                ..Default::default()
+            }
+        );
+        // ldmib r2, {pc}^
+        assert_eq!(
+            disassemble_arm(0xE9D28000, ARMv4T).unwrap(),
+            Instruction {
+                op: LDMIB,
+                operands: operands![
+                    Operand::Reg(R2),
+                    Operand::RegList(RegList { regs: RegSet::new(0x8000), user: true })
+                ],
+                ..Default::default()
+            }
+        );
+        // stmda sp!, {r0} - only a single register, so not a PUSH
+        assert_eq!(
+            disassemble_arm(0xE82D0001, ARMv4T).unwrap(),
+            Instruction {
+                op: STMDA,
+                operands: operands![Operand::RegWb(SP), Operand::RegList(RegList::new(1))],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_disasm_status_reg() {
+        let tests = [
+            // msr CPSR_fc, r0
+            (0xE129F000, MSR, operands![Operand::PsrFields(CPSR, 0b1001), Operand::Reg(R0)]),
+            // msr SPSR_fsxc, r1
+            (0xE16FF001, MSR, operands![Operand::PsrFields(SPSR, 0b1111), Operand::Reg(R1)]),
+            // msr CPSR_c, #31
+            (0xE321F01F, MSR, operands![Operand::PsrFields(CPSR, 0b0001), Operand::Imm(31)]),
+            // mrs r0, spsr
+            (0xE14F0000, MRS, operands![Operand::Reg(R0), Operand::Psr(SPSR)]),
+            // The NOP and WFI hints from ARMv6K write no fields of CPSR before then
+            (0xE320F000, MSR, operands![Operand::PsrFields(CPSR, 0), Operand::Imm(0)]),
+            (0xE320F003, MSR, operands![Operand::PsrFields(CPSR, 0), Operand::Imm(3)]),
+        ];
+        for ((encoding, op, operands), arch) in iproduct!(tests, [ARMv4T, ARMv5TE]) {
+            assert_eq!(
+                disassemble_arm(encoding, arch).unwrap(),
+                Instruction { op, operands, ..Default::default() },
+                "Disassembling {encoding:#010x} for {arch:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_disasm_multiply() {
+        let tests = [
+            // mul r0, r1, r2
+            (0xE0000291, AL, MUL, vec![R0, R1, R2], false),
+            // mlas r3, r4, r5, r6
+            (0xE0336594, AL, MLA, vec![R3, R4, R5, R6], true),
+            // umull r0, r1, r2, r3
+            (0xE0810392, AL, UMULL, vec![R0, R1, R2, R3], false),
+            // umlalne r4, r5, r6, r7
+            (0x10A54796, NE, UMLAL, vec![R4, R5, R6, R7], false),
+            // smulls r8, r9, r10, r11
+            (0xE0D98B9A, AL, SMULL, vec![R8, R9, R10, R11], true),
+            // smlal r1, r0, r3, r2
+            (0xE0E01293, AL, SMLAL, vec![R1, R0, R3, R2], false),
+        ];
+        for (encoding, cond, op, regs, set_flags) in tests {
+            assert_eq!(
+                disassemble_arm(encoding, ARMv4T).unwrap(),
+                Instruction {
+                    cond,
+                    op,
+                    operands: regs.into_iter().map(Operand::Reg).collect(),
+                    set_flags,
+                    ..Default::default()
+                },
+                "Disassembling {encoding:#010x}"
+            );
+        }
+
+        let tests = [
+            // swp r0, r1, [r2]
+            (0xE1020091, AL, SWP, R0, R1, R2),
+            // swpbeq r3, r4, [r5]
+            (0x01453094, EQ, SWPB, R3, R4, R5),
+        ];
+        for (encoding, cond, op, rt, rt2, rn) in tests {
+            let addr = Address { base: rn, mode: AddrMode::Offset };
+            assert_eq!(
+                disassemble_arm(encoding, ARMv4T).unwrap(),
+                Instruction {
+                    cond,
+                    op,
+                    operands: operands![Operand::Reg(rt), Operand::Reg(rt2), Operand::Addr(addr)],
+                    ..Default::default()
+                },
+                "Disassembling {encoding:#010x}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_disasm_v5te() {
+        let tests = [
+            // clz r1, r2
+            (0xE16F1F12, CLZ, operands![Operand::Reg(R1), Operand::Reg(R2)]),
+            // blx r3
+            (0xE12FFF33, BLX, operands![Operand::Reg(R3)]),
+            // qadd r0, r1, r2
+            (0xE1020051, QADD, operands![Operand::Reg(R0), Operand::Reg(R1), Operand::Reg(R2)]),
+            // qdsub r4, r5, r6
+            (0xE1664055, QDSUB, operands![Operand::Reg(R4), Operand::Reg(R5), Operand::Reg(R6)]),
+            // bkpt #0x1234
+            (0xE1212374, BKPT, operands![Operand::Imm(0x1234)]),
+            // smlabt r0, r1, r2, r3
+            (
+                0xE10032C1,
+                SMLABT,
+                operands![
+                    Operand::Reg(R0),
+                    Operand::Reg(R1),
+                    Operand::Reg(R2),
+                    Operand::Reg(R3),
+                ],
+            ),
+            // smulwb r4, r5, r6
+            (0xE12406A5, SMULWB, operands![Operand::Reg(R4), Operand::Reg(R5), Operand::Reg(R6)]),
+            // smlalbb r0, r1, r2, r3
+            (
+                0xE1410382,
+                SMLALBB,
+                operands![
+                    Operand::Reg(R0),
+                    Operand::Reg(R1),
+                    Operand::Reg(R2),
+                    Operand::Reg(R3),
+                ],
+            ),
+            // smultt r7, r8, r9
+            (0xE16709E8, SMULTT, operands![Operand::Reg(R7), Operand::Reg(R8), Operand::Reg(R9)]),
+            // blx #256
+            (0xFA000040, BLX, operands![Operand::Imm(256)]),
+        ];
+        for (encoding, op, operands) in tests {
+            assert_eq!(
+                disassemble_arm(encoding, ARMv5TE).unwrap(),
+                Instruction { op, operands, ..Default::default() },
+                "Disassembling {encoding:#010x}"
+            );
+            assert!(disassemble_arm(encoding, ARMv4T).is_err(), "Disassembling {encoding:#010x}");
+        }
+
+        let tests = [
+            // ldrd r2, r3, [r0, #8]
+            (0xE1C020D8, LDRD, vec![R2, R3], R0, AddrMode::Offset, Offset::imm(8, true)),
+            // strd r4, r5, [r1], -r2
+            (0xE00140F2, STRD, vec![R4, R5], R1, AddrMode::PostIndex, Offset::reg(R2, None, false)),
+            // pld [r0, #-4]
+            (0xF550F004, PLD, vec![], R0, AddrMode::Offset, Offset::imm(4, false)),
+        ];
+        for (encoding, op, regs, base, mode, offset) in tests {
+            let mut operands: Operands = regs.into_iter().map(Operand::Reg).collect();
+            operands.push(Operand::Addr(Address { base, mode }));
+            assert_eq!(
+                disassemble_arm(encoding, ARMv5TE).unwrap(),
+                Instruction {
+                    op,
+                    operands,
+                    extra: Some(offset.into()),
+                    ..Default::default()
+                },
+                "Disassembling {encoding:#010x}"
+            );
+            assert!(disassemble_arm(encoding, ARMv4T).is_err(), "Disassembling {encoding:#010x}");
+        }
+
+        // blx r3
+        assert_eq!(
+            disassemble_thumb(0x4798, ARMv5TE).unwrap(),
+            Instruction {
+                op: BLX,
+                operands: operands![Operand::Reg(R3)],
+                ..Default::default()
+            }
+        );
+        assert!(disassemble_thumb(0x4798, ARMv4T).is_err());
+        // bkpt #0x12
+        assert_eq!(
+            disassemble_thumb(0xBE12, ARMv5TE).unwrap(),
+            Instruction {
+                op: BKPT,
+                operands: operands![Operand::Imm(0x12)],
+                ..Default::default()
+            }
+        );
+        assert!(disassemble_thumb(0xBE12, ARMv4T).is_err());
+        // blx #-4194304
+        assert!(disassemble_thumb_bl(0xF400, 0xE800, ARMv4T).is_err());
+    }
+
+    #[test]
+    fn test_disasm_coprocessor() {
+        use Operand::{Coproc, CoprocOpcode as Opc, CoprocReg as CReg};
+        let tests = [
+            // mcr p15, 0, r0, c1, c0, 0
+            (
+                0xEE010F10,
+                AL,
+                MCR,
+                operands![
+                    Coproc(15),
+                    Opc(0),
+                    Operand::Reg(R0),
+                    CReg(1),
+                    CReg(0),
+                    Opc(0),
+                ],
+            ),
+            // mrcne p15, 0, r1, c9, c1, 1
+            (
+                0x1E191F31,
+                NE,
+                MRC,
+                operands![
+                    Coproc(15),
+                    Opc(0),
+                    Operand::Reg(R1),
+                    CReg(9),
+                    CReg(1),
+                    Opc(1),
+                ],
+            ),
+            // cdp p14, 3, c1, c2, c3, 4
+            (0xEE321E83, AL, CDP, operands![Coproc(14), Opc(3), CReg(1), CReg(2), CReg(3), Opc(4)]),
+            // mcrr p15, 1, r0, r1, c2
+            (
+                0xEC410F12,
+                AL,
+                MCRR,
+                operands![
+                    Coproc(15),
+                    Opc(1),
+                    Operand::Reg(R0),
+                    Operand::Reg(R1),
+                    CReg(2),
+                ],
+            ),
+            // mrrc p6, 2, r2, r3, c4
+            (
+                0xEC532624,
+                AL,
+                MRRC,
+                operands![
+                    Coproc(6),
+                    Opc(2),
+                    Operand::Reg(R2),
+                    Operand::Reg(R3),
+                    CReg(4),
+                ],
+            ),
+            // ldc p2, c3, [r4], {9}
+            (
+                0xEC943209,
+                AL,
+                LDC,
+                operands![
+                    Coproc(2),
+                    CReg(3),
+                    Operand::Addr(Address { base: R4, mode: AddrMode::Offset }),
+                    Operand::CoprocOption(9),
+                ],
+            ),
+            // mcr2 p7, 1, r2, c3, c4, 5
+            (
+                0xFE2327B4,
+                AL,
+                MCR2,
+                operands![
+                    Coproc(7),
+                    Opc(1),
+                    Operand::Reg(R2),
+                    CReg(3),
+                    CReg(4),
+                    Opc(5),
+                ],
+            ),
+            // mrc2 p7, 1, r2, c3, c4, 5
+            (
+                0xFE3327B4,
+                AL,
+                MRC2,
+                operands![
+                    Coproc(7),
+                    Opc(1),
+                    Operand::Reg(R2),
+                    CReg(3),
+                    CReg(4),
+                    Opc(5),
+                ],
+            ),
+            // cdp2 p1, 2, c3, c4, c5, 6
+            (0xFE2431C5, AL, CDP2, operands![Coproc(1), Opc(2), CReg(3), CReg(4), CReg(5), Opc(6)]),
+            // svc #0x123456
+            (0xEF123456, AL, SVC, operands![Operand::Imm(0x123456)]),
+        ];
+        for (encoding, cond, op, operands) in tests {
+            assert_eq!(
+                disassemble_arm(encoding, ARMv5TE).unwrap(),
+                Instruction { cond, op, operands, ..Default::default() },
+                "Disassembling {encoding:#010x}"
+            );
+        }
+
+        let tests = [
+            // ldc p14, c5, [r0, #4]!
+            (0xEDB05E01, LDC, 14, 5, R0, AddrMode::PreIndex, Offset::imm(4, true)),
+            // stcl p1, c2, [r3], #-8
+            (0xEC632102, STCL, 1, 2, R3, AddrMode::PostIndex, Offset::imm(8, false)),
+            // stc p3, c7, [r5, #-1020]
+            (0xED0573FF, STC, 3, 7, R5, AddrMode::Offset, Offset::imm(1020, false)),
+            // ldc2l p5, c1, [r2, #12]
+            (0xFDD21503, LDC2L, 5, 1, R2, AddrMode::Offset, Offset::imm(12, true)),
+            // stc2 p5, c1, [r2], #12
+            (0xFCA21503, STC2, 5, 1, R2, AddrMode::PostIndex, Offset::imm(12, true)),
+        ];
+        for (encoding, op, coproc, crd, base, mode, offset) in tests {
+            assert_eq!(
+                disassemble_arm(encoding, ARMv5TE).unwrap(),
+                Instruction {
+                    op,
+                    operands: operands![
+                        Coproc(coproc),
+                        CReg(crd),
+                        Operand::Addr(Address { base, mode })
+                    ],
+                    extra: Some(offset.into()),
+                    ..Default::default()
+                },
+                "Disassembling {encoding:#010x}"
+            );
+        }
+
+        // The unconditional variants and MCRR/MRRC are undefined on ARMv4T
+        assert!(disassemble_arm(0xEE010F10, ARMv4T).is_ok());
+        assert!(disassemble_arm(0xEC410F12, ARMv4T).is_err());
+        assert!(disassemble_arm(0xFE2327B4, ARMv4T).is_err());
+    }
+
+    #[test]
+    fn test_disasm_unconditional() {
+        // blx #258
+        assert_eq!(
+            disassemble_arm(0xFB000040, ARMv5TE).unwrap(),
+            Instruction {
+                op: BLX,
+                operands: operands![Operand::Imm(258)],
+                ..Default::default()
+            }
+        );
+        // blx #-8
+        assert_eq!(
+            disassemble_arm(0xFAFFFFFE, ARMv5TE).unwrap(),
+            Instruction {
+                op: BLX,
+                operands: operands![Operand::Imm(-8i32 as u32)],
+                ..Default::default()
+            }
+        );
+        // pld [r1, -r2, lsl #3]
+        let shift = ImmShift { op: ShiftOp::LSL, imm: 3 };
+        assert_eq!(
+            disassemble_arm(0xF751F182, ARMv5TE).unwrap(),
+            Instruction {
+                op: PLD,
+                operands: operands![Operand::Addr(Address { base: R1, mode: AddrMode::Offset })],
+                extra: Some(Offset::reg(R2, Some(shift), false).into()),
+                ..Default::default()
+            }
+        );
+        // ARMv6 encodings (setend, cps, srs, rfe, clrex, mcrr2) and unallocated words
+        for encoding in [
+            0xF1010200, 0xF10C0080, 0xF8CD0513, 0xF8BD0A00, 0xF57FF01F, 0xFC410F12, 0xF0000000,
+            0xF7F000F0, 0xFF000000, 0xFFFFFFFF,
+        ] {
+            assert_eq!(
+                disassemble_arm(encoding, ARMv5TE),
+                Err(DisasmError::undefined(encoding)),
+                "Disassembling {encoding:#010x}"
+            );
+            assert!(disassemble_arm(encoding, ARMv4T).is_err(), "Disassembling {encoding:#010x}");
+        }
+    }
+
+    #[test]
+    fn test_disasm_unpredictable() {
+        let tests = [
+            // mul pc, r1, r2
+            (0xE00F0291, Unpredictable::PcOperand),
+            // mul r1, r1, r2
+            (0xE0010291, Unpredictable::RegisterOverlap),
+            // umull r0, r0, r1, r2
+            (0xE0800291, Unpredictable::RegisterOverlap),
+            // ldr r0, [r0, #4]!
+            (0xE5B00004, Unpredictable::WritebackBase),
+            // ldr r0, [r1, pc]
+            (0xE791000F, Unpredictable::PcOperand),
+            // ldmia r0!, {r0, r1}
+            (0xE8B00003, Unpredictable::BaseInList),
+            // ldmia r0, {}
+            (0xE8900000, Unpredictable::EmptyRegList),
+            // stmia r1!, {r0, r1}
+            (0xE8A10003, Unpredictable::BaseInList),
+            // stmdb r0!, {r1, r2}^
+            (0xE9600006, Unpredictable::UserWriteback),
+            // ldrd r1, r2, [r0]
+            (0xE1C010D0, Unpredictable::OddRegisterPair),
+            // ldrd r0, r1, [r0], #8
+            (0xE0C000D8, Unpredictable::WritebackBase),
+            // ldrd r0, r1, [r2, r1]
+            (0xE18200D1, Unpredictable::RegisterOverlap),
+            // strh pc, [r0]
+            (0xE1C0F0B0, Unpredictable::PcOperand),
+            // clz pc, r0
+            (0xE16FFF10, Unpredictable::PcOperand),
+            // mrs pc, cpsr
+            (0xE10FF000, Unpredictable::PcOperand),
+            // add r0, r1, r2, lsl pc
+            (0xE0810F12, Unpredictable::PcOperand),
+        ];
+        for (encoding, expected) in tests {
+            match disassemble_arm(encoding, ARMv5TE) {
+                Err(DisasmError::Unpredictable { instr, reason, .. }) => {
+                    assert_eq!(
+                        (instr, reason),
+                        (encoding, expected),
+                        "Disassembling {encoding:#010x}"
+                    )
+                }
+                result => {
+                    panic!("Disassembling {encoding:#010x}: expected {expected:?}, got {result:?}")
+                }
+            }
+        }
+
+        // The loaded value is written to the base register on ARMv4T
+        assert_eq!(
+            disassemble_arm(0xE8B00003, ARMv4T).unwrap(),
+            Instruction {
+                op: LDMIA,
+                operands: operands![Operand::RegWb(R0), Operand::RegList(RegList::new(0b11))],
+                ..Default::default()
+            }
+        );
+        // The decoded instruction is still available
+        let Err(DisasmError::Unpredictable { decoded, .. }) = disassemble_arm(0xE5B00004, ARMv5TE)
+        else {
+            panic!("ldr r0, [r0, #4]! should be unpredictable");
+        };
+        assert_eq!(decoded.op, LDR);
+        assert_eq!(
+            disassemble_arm(0xE5B00004, ARMv5TE)
+                .unwrap_err()
+                .to_string(),
+            "unpredictable instruction 0xe5b00004: it writes back to PC or to the register it \
+             transfers"
+        );
+        // ldrh r0, [r1, r0], ldrsb r0, [r1, r0] and ldrsh r0, [r1, r0] may reuse Rt as the offset
+        for (encoding, op) in [(0xE19100B0, LDRH), (0xE19100D0, LDRSB), (0xE19100F0, LDRSH)] {
+            assert_eq!(
+                disassemble_arm(encoding, ARMv5TE).map(|instr| instr.op),
+                Ok(op),
+                "Disassembling {encoding:#010x}"
+            );
+        }
+        // pop {}
+        assert!(matches!(
+            disassemble_thumb(0xBC00, ARMv5TE),
+            Err(DisasmError::Unpredictable { reason: Unpredictable::EmptyRegList, .. })
+        ));
+    }
+
+    #[test]
+    fn test_disasm_pc_relative() {
+        let tests = [
+            // b .
+            (0xEAFFFFFE, B, operands![Operand::Target(0x02000000)]),
+            // bl .+0x108
+            (0xEB000040, BL, operands![Operand::Target(0x02000108)]),
+            // blx .+0x10A
+            (0xFB000040, BLX, operands![Operand::Target(0x0200010A)]),
+            // ldr r0, [pc, #-4]
+            (0xE51F0004, LDR, operands![Operand::Reg(R0), Operand::Target(0x02000004)]),
+            // add r0, pc, #16
+            (0xE28F0010, ADR, operands![Operand::Reg(R0), Operand::Target(0x02000018)]),
+            // sub r1, pc, #4
+            (0xE24F1004, ADR, operands![Operand::Reg(R1), Operand::Target(0x02000004)]),
+        ];
+        for (encoding, op, operands) in tests {
+            assert_eq!(
+                disassemble_arm_at(0x02000000, encoding, ARMv5TE).unwrap(),
+                Instruction { op, operands, ..Default::default() },
+                "Disassembling {encoding:#010x}"
+            );
+        }
+        assert_eq!(
+            disassemble_arm_at(0x02000000, 0xEB000040, ARMv5TE)
+                .unwrap()
+                .to_string(),
+            "BLAL 0x02000108"
+        );
+        // Without an address the offset relative to PC is kept
+        assert_eq!(
+            disassemble_arm(0xEAFFFFFE, ARMv5TE).unwrap().operands,
+            operands![Operand::Imm(-8i32 as u32)]
+        );
+
+        let tests = [
+            // b .
+            (0xE7FE, B, operands![Operand::Target(0x02000002)]),
+            // ldr r0, [pc, #12]
+            (0x4803, LDR, operands![Operand::Reg(R0), Operand::Target(0x02000010)]),
+            // adr r0, #4
+            (0xA001, ADR, operands![Operand::Reg(R0), Operand::Target(0x02000008)]),
+        ];
+        for (encoding, op, operands) in tests {
+            assert_eq!(
+                disassemble_thumb_at(0x02000002, encoding, ARMv5TE).unwrap(),
+                Instruction { op, operands, ..Default::default() },
+                "Disassembling {encoding:#06x}"
+            );
+        }
+        // bl .+0x104
+        assert_eq!(
+            disassemble_thumb_bl_at(0x02000000, 0xF000, 0xF880, ARMv5TE).unwrap(),
+            Instruction {
+                op: BL,
+                operands: operands![Operand::Target(0x02000104)],
+                ..Default::default()
+            }
+        );
+        // blx #-4194304, from a halfword aligned address
+        assert_eq!(
+            disassemble_thumb_bl_at(0x02000002, 0xF400, 0xE800, ARMv5TE).unwrap(),
+            Instruction {
+                op: BLX,
+                operands: operands![Operand::Target(0x01C00004)],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_disasm_arm_total() {
+        // Every combination of the bits used to select an encoding (cond, op1 and op2), with the
+        // remaining register/immediate fields cleared, set and randomized. The decoder must return
+        // without panicking for all of them
+        let mut rng = thread_rng();
+        let fills = [0, u32::MAX, rng.gen(), rng.gen()];
+        for selector in 0..=u16::MAX as u32 {
+            let cond = bits(selector, 12..15) << 28;
+            let op1 = bits(selector, 4..11) << 20;
+            let op2 = bits(selector, 0..3) << 4;
+            for fill in fills {
+                let encoding = cond | op1 | op2 | (fill & 0x000FFF0F);
+                for arch in [ARMv4T, ARMv5TE] {
+                    let _ = disassemble_arm(encoding, arch);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_disasm_arm_table() {
+        // The dispatch table must agree with the nested decoder for every selector value
+        let mut rng = thread_rng();
+        let fills = [0, u32::MAX, rng.gen(), rng.gen()];
+        for selector in 0..=u16::MAX as u32 {
+            let cond = bits(selector, 12..15) << 28;
+            let op1 = bits(selector, 4..11) << 20;
+            let op2 = bits(selector, 0..3) << 4;
+            for fill in fills {
+                let encoding = cond | op1 | op2 | (fill & 0x000FFF0F);
+                for arch in [ARMv4T, ARMv5TE] {
+                    assert_eq!(
+                        disassemble_arm(encoding, arch),
+                        disassemble_arm_nested(encoding, arch),
+                        "{encoding:#010x}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_disasm_thumb_total() {
+        for encoding in 0..=u16::MAX {
+            for arch in [ARMv4T, ARMv5TE] {
+                let _ = disassemble_thumb(encoding, arch);
+                let _ = disassemble_thumb_bl(encoding, 0xF800, arch);
+                let _ = disassemble_thumb_bl(0xF000, encoding, arch);
+            }
+        }
+    }
+
+    #[test]
+    fn test_disasm_thumb_data_proc() {
+        let tests = [
+            // lsrs r2, r3, #32
+            (0x081A, LSR, operands![Operand::Reg(R2), Operand::Reg(R3), Operand::Imm(32)], true),
+            // subs r0, r1, #7
+            (0x1FC8, SUB, operands![Operand::Reg(R0), Operand::Reg(R1), Operand::Imm(7)], true),
+            // adds r6, #1
+            (0x3601, ADD, operands![Operand::Reg(R6), Operand::Reg(R6), Operand::Imm(1)], true),
+            // cmp r5, #200
+            (0x2DC8, CMP, operands![Operand::Reg(R5), Operand::Imm(200)], true),
+            // lsls r2, r3
+            (0x409A, LSL, operands![Operand::Reg(R2), Operand::Reg(R2), Operand::Reg(R3)], true),
+            // negs r0, r1
+            (0x4248, RSB, operands![Operand::Reg(R0), Operand::Reg(R1), Operand::Imm(0)], true),
+            // muls r0, r1, r0
+            (0x4348, MUL, operands![Operand::Reg(R0), Operand::Reg(R1), Operand::Reg(R0)], true),
+            // add r8, r1
+            (0x4488, ADD, operands![Operand::Reg(R8), Operand::Reg(R8), Operand::Reg(R1)], false),
+            // mov pc, lr
+            (0x46F7, MOV, operands![Operand::Reg(PC), Operand::Reg(LR)], false),
+            // bx lr
+            (0x4770, BX, operands![Operand::Reg(LR)], false),
+            // add r0, sp, #16
+            (0xA804, ADD, operands![Operand::Reg(R0), Operand::Reg(SP), Operand::Imm(16)], false),
+            // sub sp, #508
+            (0xB0FF, SUB, operands![Operand::Reg(SP), Operand::Reg(SP), Operand::Imm(508)], false),
+        ];
+        for (encoding, op, operands, set_flags) in tests {
+            assert_eq!(
+                disassemble_thumb(encoding, ARMv5TE).unwrap(),
+                Instruction { op, operands, set_flags, ..Default::default() },
+                "Disassembling {encoding:#06x}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_disasm_thumb_load_store() {
+        let tests = [
+            // ldr r0, [pc, #12]
+            (0x4803, LDR, R0, PC, Offset::imm(12, true)),
+            // ldrsh r0, [r1, r2]
+            (0x5E88, LDRSH, R0, R1, Offset::reg(R2, None, true)),
+            // ldr r3, [r4, #124]
+            (0x6FE3, LDR, R3, R4, Offset::imm(124, true)),
+            // strb r3, [r4, #31]
+            (0x77E3, STRB, R3, R4, Offset::imm(31, true)),
+            // ldrh r3, [r4, #62]
+            (0x8FE3, LDRH, R3, R4, Offset::imm(62, true)),
+            // str r0, [sp, #1020]
+            (0x90FF, STR, R0, SP, Offset::imm(1020, true)),
+        ];
+        for (encoding, op, rt, rn, offset) in tests {
+            let addr = Address { base: rn, mode: AddrMode::Offset };
+            assert_eq!(
+                disassemble_thumb(encoding, ARMv5TE).unwrap(),
+                Instruction {
+                    op,
+                    operands: operands![Operand::Reg(rt), Operand::Addr(addr)],
+                    extra: Some(offset.into()),
+                    ..Default::default()
+                },
+                "Disassembling {encoding:#06x}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_disasm_thumb_block_data_transfer() {
+        // push {r0, r4-r7, lr}
+        assert_eq!(
+            disassemble_thumb(0xB5F1, ARMv5TE).unwrap(),
+            Instruction {
+                op: PUSH,
+                operands: operands![Operand::RegList(RegList::new(0x40F1))],
+                ..Default::default()
+            }
+        );
+        // pop {r4, pc}
+        assert_eq!(
+            disassemble_thumb(0xBD10, ARMv5TE).unwrap(),
+            Instruction {
+                op: POP,
+                operands: operands![Operand::RegList(RegList::new(0x8010))],
+                ..Default::default()
+            }
+        );
+        // ldmia r1, {r1, r2}
+        assert_eq!(
+            disassemble_thumb(0xC906, ARMv5TE).unwrap(),
+            Instruction {
+                op: LDMIA,
+                operands: operands![Operand::Reg(R1), Operand::RegList(RegList::new(0b110))],
+                ..Default::default()
+            }
+        );
+        // stmia r0!, {r1, r2}
+        assert_eq!(
+            disassemble_thumb(0xC006, ARMv5TE).unwrap(),
+            Instruction {
+                op: STMIA,
+                operands: operands![Operand::RegWb(R0), Operand::RegList(RegList::new(0b110))],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_disasm_thumb_branch() {
+        // beq #-4
+        assert_eq!(
+            disassemble_thumb(0xD0FE, ARMv5TE).unwrap(),
+            Instruction {
+                cond: EQ,
+                op: B,
+                operands: operands![Operand::Imm(-4i32 as u32)],
+                ..Default::default()
+            }
+        );
+        // b #2044
+        assert_eq!(
+            disassemble_thumb(0xE3FE, ARMv5TE).unwrap(),
+            Instruction {
+                op: B,
+                operands: operands![Operand::Imm(2044)],
+                ..Default::default()
+            }
+        );
+        // svc #12
+        assert_eq!(
+            disassemble_thumb(0xDF0C, ARMv5TE).unwrap(),
+            Instruction {
+                op: SVC,
+                operands: operands![Operand::Imm(12)],
+                ..Default::default()
+            }
+        );
+        // bl #256
+        assert!(is_thumb_bl_prefix(0xF000));
+        assert!(disassemble_thumb(0xF000, ARMv5TE).is_err());
+        assert!(disassemble_thumb(0xF880, ARMv5TE).is_err());
+        assert_eq!(
+            disassemble_thumb_bl(0xF000, 0xF880, ARMv5TE).unwrap(),
+            Instruction {
+                op: BL,
+                operands: operands![Operand::Imm(256)],
+                ..Default::default()
+            }
+        );
+        // blx #-4194304
+        assert_eq!(
+            disassemble_thumb_bl(0xF400, 0xE800, ARMv5TE).unwrap(),
+            Instruction {
+                op: BLX,
+                operands: operands![Operand::Imm(-4194304i32 as u32)],
                 ..Default::default()
             }
         );
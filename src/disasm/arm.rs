@@ -1,15 +1,16 @@
 use super::bits::{bit, bit_match, bits, pick_bits};
-use super::{DisasmError, DisasmResult};
+use super::{check_predictable, Arch, DisasmError, DisasmResult, Unpredictable};
 use crate::ir::{
     AddrMode, Address, Cond, ExtraOperand, ExtraValue, ImmShift, Instruction, Offset, Op, Operand,
-    Register, Shift, ShiftOp,
+    Operands, RegList, RegSet, Register, Shift, ShiftOp, StatusReg,
 };
+use crate::operands;
 
 /// Number of lookahead bytes in ARM mode
-const PC_LA_ARM: u32 = 8;
+pub(crate) const PC_LA_ARM: u32 = 8;
 
 /// Used to decode cond from an integer
-const COND_MAP: [Cond; 15] = [
+pub(super) const COND_MAP: [Cond; 16] = [
     Cond::EQ,
     Cond::NE,
     Cond::CS,
@@ -25,10 +26,11 @@ const COND_MAP: [Cond; 15] = [
     Cond::GT,
     Cond::LE,
     Cond::AL,
+    Cond::NV,
 ];
 
 /// Used to decode register from an integer
-const REG_MAP: [Register; 16] = [
+pub(super) const REG_MAP: [Register; 16] = [
     Register::R0,
     Register::R1,
     Register::R2,
@@ -47,8 +49,74 @@ const REG_MAP: [Register; 16] = [
     Register::PC,
 ];
 
+/// A decoder for one class of conditional ARM instruction
+pub(super) type ArmDecoder = fn(u32, Arch) -> DisasmResult<Instruction>;
+
+/// Dispatch table for all conditional ARM instructions, built at compile time. Every encoding class
+/// is fully determined by bits 27..20 and 7..4, so these 12 bits index straight into the decoder
+/// for the class, skipping the nested matches of `arm_data_proc_and_misc`
+pub(super) static ARM_DECODE_TABLE: [ArmDecoder; 4096] = build_arm_decode_table();
+
+/// Index into ARM_DECODE_TABLE: bits 27..20 followed by bits 7..4
+pub(super) fn arm_table_index(instr: u32) -> usize {
+    ((bits(instr, 20..27) << 4) | bits(instr, 4..7)) as usize
+}
+
+const fn build_arm_decode_table() -> [ArmDecoder; 4096] {
+    let mut table: [ArmDecoder; 4096] = [arm_coprocessor; 4096];
+    let mut index = 0;
+    while index < table.len() {
+        table[index] = arm_class_decoder(index as u32);
+        index += 1;
+    }
+    table
+}
+
+/// Select the decoder for an index into ARM_DECODE_TABLE. Mirrors the tables in A5.1 and A5.2
+const fn arm_class_decoder(index: u32) -> ArmDecoder {
+    let op1 = (index >> 4) & 0b11111;
+    let op2 = index & 0b1111;
+    // op1 == 10xx0: TST/TEQ/CMP/CMN without S, which encode the miscellaneous instructions
+    let misc = op1 & 0b11001 == 0b10000;
+    match index >> 9 {
+        0b000 => {
+            if !misc && op2 & 0b0001 == 0b0000 {
+                |instr, _| arm_data_proc_reg(instr)
+            } else if !misc && op2 & 0b1001 == 0b0001 {
+                |instr, _| arm_data_proc_shift_reg(instr)
+            } else if misc && op2 & 0b1000 == 0b0000 {
+                arm_misc
+            } else if misc && op2 & 0b1001 == 0b1000 {
+                arm_halfword_mult
+            } else if op1 & 0b10000 == 0 && op2 == 0b1001 {
+                |instr, _| arm_mult(instr)
+            } else if op2 == 0b1001 {
+                |instr, _| arm_sync(instr)
+            } else if op2 & 0b1001 == 0b1001 && op1 & 0b00100 == 0 {
+                arm_extra_load_store_reg
+            } else if op2 & 0b1001 == 0b1001 {
+                arm_extra_load_store_imm
+            } else {
+                |instr, _| Err(DisasmError::undefined(instr))
+            }
+        }
+        0b001 => match op1 {
+            _ if !misc => |instr, _| arm_data_proc_imm(instr),
+            0b10000 => arm_load_halfword_imm,
+            0b10100 => arm_load_high_halfword_imm,
+            _ => |instr, _| arm_msr_imm(instr),
+        },
+        0b010 => |instr, _| arm_load_store(instr),
+        0b011 if op2 & 0b0001 == 0 => |instr, _| arm_load_store(instr),
+        0b011 => arm_media,
+        0b100 => arm_block_data_transfer,
+        0b101 => |instr, _| arm_branch(instr),
+        _ => arm_coprocessor,
+    }
+}
+
 /// Decode instructions described in A5.2 - Data-processing and miscellaneous instructions
-pub fn arm_data_proc_and_misc(instr: u32) -> DisasmResult<Instruction> {
+pub fn arm_data_proc_and_misc(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
     let op = bit(instr, 25);
     let op1 = bits(instr, 20..24);
     let op2 = bits(instr, 4..7);
@@ -58,22 +126,24 @@ pub fn arm_data_proc_and_misc(instr: u32) -> DisasmResult<Instruction> {
             (x, y) if !bit_match(x, "10xx0") && bit_match(y, "0xx1") => {
                 arm_data_proc_shift_reg(instr)
             }
-            (x, y) if bit_match(x, "10xx0") && bit_match(y, "0xxx") => arm_misc(instr),
-            (x, y) if bit_match(x, "10xx0") && bit_match(y, "1xx0") => arm_halfword_mult(instr),
+            (x, y) if bit_match(x, "10xx0") && bit_match(y, "0xxx") => arm_misc(instr, arch),
+            (x, y) if bit_match(x, "10xx0") && bit_match(y, "1xx0") => {
+                arm_halfword_mult(instr, arch)
+            }
             (x, y) if bit_match(x, "0xxxx") && y == 0b1001 => arm_mult(instr),
             (x, y) if bit_match(x, "1xxxx") && y == 0b1001 => arm_sync(instr),
             (x, y) if bit_match(y, "1xx1") => match bit(x, 2) {
-                0 => arm_extra_load_store_reg(instr),
-                _ => arm_extra_load_store_imm(instr),
+                0 => arm_extra_load_store_reg(instr, arch),
+                _ => arm_extra_load_store_imm(instr, arch),
             },
             (_, _) => Err(DisasmError::undefined(instr)),
         }
     } else {
         match op1 {
             x if !bit_match(x, "10xx0") => arm_data_proc_imm(instr),
-            0b10000 => arm_load_high_halfword_imm(instr),
-            0b10100 => arm_load_halfword_imm(instr),
-            _ => arm_msr_and_hints(instr),
+            0b10000 => arm_load_halfword_imm(instr, arch),
+            0b10100 => arm_load_high_halfword_imm(instr, arch),
+            _ => arm_msr_imm(instr),
         }
     }
 }
@@ -84,7 +154,7 @@ pub fn arm_data_proc_and_misc(instr: u32) -> DisasmResult<Instruction> {
 ///     register args: op1, op2, imm
 ///     register-shifted args: op1, op2
 ///     immediate args: op1
-pub fn decode_data_proc_op(op1: u32, op2: Option<u32>, imm: Option<u32>) -> DisasmResult<Op> {
+pub fn decode_data_proc_op(op1: u32, op2: Option<u32>, imm: Option<u32>) -> Option<Op> {
     let op = match op1 {
         0b00000 | 0b00001 => Op::AND,
         0b00010 | 0b00011 => Op::EOR,
@@ -107,122 +177,137 @@ pub fn decode_data_proc_op(op1: u32, op2: Option<u32>, imm: Option<u32>) -> Disa
             },
             Some(0b01) => Op::LSR,
             Some(0b10) => Op::ASR,
-            Some(0b11) => match imm {
+            Some(_) => match imm {
                 Some(0) => Op::RRX,
                 _ => Op::ROR,
             },
-            _ => unreachable!(),
         },
         0b11100 | 0b11101 => Op::BIC,
         0b11110 | 0b11111 => Op::MVN,
         _ => {
-            return Err(DisasmError::new("invalid dataproc op", op1));
+            return None;
         }
     };
-    Ok(op)
+    Some(op)
 }
 
 /// Decode table for extra load/store operations (encoded in the data-processing instruction space).
 /// Both op1 and op2 must be 2 bit values
-pub fn decode_extra_load_store_op(op1: u32, op2: u32) -> DisasmResult<Op> {
-    let err = Err(DisasmError::new("could not decode extra load/store op", 0));
-    let (op, imm) = match op2 {
-        0b01 => match op1 {
-            0b00 => (Op::STRH, false),
-            0b01 => (Op::LDRH, false),
-            0b10 => (Op::STRH, true),
-            0b11 => (Op::LDRH, true),
-            _ => unreachable!(),
-        },
-        0b10 => match op1 {
-            0b01 => (Op::LDRSB, false),
-            0b11 => (Op::LDRSB, true),
-            _ => {
-                return err;
-            }
-        },
-        0b11 => match op1 {
-            0b01 => (Op::LDRSH, false),
-            0b11 => (Op::LDRSH, true),
-            _ => {
-                return err;
-            }
-        },
+pub fn decode_extra_load_store_op(op1: u32, op2: u32, arch: Arch) -> Option<Op> {
+    let op = match (op2, op1) {
+        (0b01, 0b00 | 0b10) => Op::STRH,
+        (0b01, 0b01 | 0b11) => Op::LDRH,
+        (0b10, 0b00 | 0b10) if arch >= Arch::ARMv5TE => Op::LDRD,
+        (0b10, 0b01 | 0b11) => Op::LDRSB,
+        (0b11, 0b00 | 0b10) if arch >= Arch::ARMv5TE => Op::STRD,
+        (0b11, 0b01 | 0b11) => Op::LDRSH,
         _ => {
-            return err;
+            return None;
         }
     };
-    Ok(op)
+    Some(op)
 }
 
-pub fn decode_load_store_op(op1: u32) -> DisasmResult<Op> {
-    let op = match op1 {
+pub fn decode_load_store_op(op1: u32) -> Op {
+    match op1 {
         0b00010 | 0b01010 => Op::STRT,
         0b00011 | 0b01011 => Op::LDRT,
         0b00110 | 0b01110 => Op::STRBT,
         0b00111 | 0b01111 => Op::LDRBT,
-        _ => match pick_bits(op1, &vec![0, 2]) {
+        _ => match pick_bits(op1, &[0, 2]) {
             0b00 => Op::STR,
             0b01 => Op::LDR,
             0b10 => Op::STRB,
             _ => Op::LDRB,
         },
-        _ => {
-            return Err(DisasmError::new("Unrecognized load/store op", 0));
-        }
-    };
-    Ok(op)
+    }
 }
 
-fn decode_addressing_mode(p: u32, w: u32) -> DisasmResult<AddrMode> {
-    let err = Err(DisasmError::new("Invalid addressing mode", 0));
-    if p == 0 && w == 1 {
-        return err;
+/// The addressing mode given by the P and W bits. P = 0, W = 1 selects the unprivileged (T)
+/// variants instead, which have no addressing mode of their own
+fn decode_addressing_mode(p: u32, w: u32) -> Option<AddrMode> {
+    match (p, w) {
+        (1, 1) => Some(AddrMode::PreIndex),
+        (1, _) => Some(AddrMode::Offset),
+        (_, 0) => Some(AddrMode::PostIndex),
+        _ => None,
     }
-
-    let index = p == 1;
-    let write_back = p == 0 || w == 1;
-    let mode = match (index, write_back) {
-        (true, true) => AddrMode::PreIndex,
-        (true, false) => AddrMode::Offset,
-        (false, true) => AddrMode::PostIndex,
-        _ => {
-            return err;
-        }
-    };
-    Ok(mode)
 }
 
-pub fn decode_imm_shift(shift_op: u32, imm5: u32) -> Result<ImmShift, DisasmError> {
-    if shift_op >= 4 {
-        return Err(DisasmError::new("shift op must be a 2-bit value", shift_op));
-    }
-    if imm5 >= 32 {
-        return Err(DisasmError::new("imm shift must be a 5-bit value", imm5));
-    }
-    let (op, imm) = match shift_op {
+/// Implements the DecodeImmShift() pseudo-code function. Only the low 2 bits of `shift_op` and the
+/// low 5 bits of `imm5` are used
+pub fn decode_imm_shift(shift_op: u32, imm5: u32) -> ImmShift {
+    let imm5 = imm5 & 0x1F;
+    let (op, imm) = match shift_op & 0b11 {
         0b00 => (ShiftOp::LSL, imm5),
         0b01 => (ShiftOp::LSR, if imm5 == 0 { 32 } else { imm5 }),
         0b10 => (ShiftOp::ASR, if imm5 == 0 { 32 } else { imm5 }),
-        0b11 => {
+        _ => {
             if imm5 == 0 {
                 (ShiftOp::RRX, 1)
             } else {
                 (ShiftOp::ROR, imm5)
             }
         }
-        _ => unreachable!(),
     };
-    Ok(ImmShift { op, imm })
+    ImmShift { op, imm }
+}
+
+/// Decode instructions described in A5.7 - Unconditional instructions. These are only defined from
+/// ARMv5 onward
+pub fn arm_unconditional(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
+    if arch < Arch::ARMv5TE {
+        return Err(DisasmError::unsupported(instr, arch));
+    }
+    let op1 = bits(instr, 20..27);
+    match op1 {
+        x if x & 0b11100000 == 0b10100000 => arm_blx_imm(instr),
+        x if x & 0b11010111 == 0b01010101 && bits(instr, 12..15) == 0b1111 => arm_pld(instr),
+        x if x & 0b11000000 == 0b11000000 => arm_coprocessor(instr, arch),
+        _ => Err(DisasmError::undefined(instr)),
+    }
+}
+
+/// BLX (immediate) always switches to THUMB state, so the H bit provides bit 1 of the halfword
+/// aligned target
+fn arm_blx_imm(instr: u32) -> DisasmResult<Instruction> {
+    let imm = (bits(instr, 0..23) << 2) | (bit(instr, 24) << 1);
+    // Sign extend the 26-bit offset
+    let offset = (((imm << 6) as i32) >> 6) as u32;
+    Ok(Instruction {
+        op: Op::BLX,
+        operands: operands![Operand::Imm(offset)],
+        ..Default::default()
+    })
 }
 
-pub fn arm_unconditional(instr: u32) -> DisasmResult<Instruction> {
-    todo!()
+/// Preload data, with an immediate or scaled register offset
+fn arm_pld(instr: u32) -> DisasmResult<Instruction> {
+    let rn = REG_MAP[bits(instr, 16..19) as usize];
+    let add = bit(instr, 23) == 1;
+    let offset = match bit(instr, 25) {
+        0 => Offset::imm(bits(instr, 0..11), add),
+        _ => {
+            if bit(instr, 4) == 1 {
+                return Err(DisasmError::undefined(instr));
+            }
+            let rm = REG_MAP[bits(instr, 0..3) as usize];
+            let shift = decode_imm_shift(bits(instr, 5..6), bits(instr, 7..11));
+            let shift = if shift.imm == 0 { None } else { Some(shift) };
+            Offset::reg(rm, shift, add)
+        }
+    };
+    Ok(Instruction {
+        op: Op::PLD,
+        operands: operands![Operand::Addr(Address { base: rn, mode: AddrMode::Offset })],
+        extra: Some(offset.into()),
+        ..Default::default()
+    })
 }
 
 pub fn arm_load_store(instr: u32) -> DisasmResult<Instruction> {
     let op1 = bits(instr, 20..24);
-    let op = decode_load_store_op(op1)?;
+    let op = decode_load_store_op(op1);
     let rn = REG_MAP[bits(instr, 16..19) as usize];
     let rt = REG_MAP[bits(instr, 12..15) as usize];
 
@@ -237,20 +322,20 @@ pub fn arm_load_store(instr: u32) -> DisasmResult<Instruction> {
             1 => AddrMode::PostIndex,
             _ => AddrMode::PreIndex,
         },
-        _ => decode_addressing_mode(p, w)?,
+        _ => decode_addressing_mode(p, w).ok_or(DisasmError::undefined(instr))?,
     };
 
     let mut instruction = Instruction {
         op,
         cond: COND_MAP[bits(instr, 28..31) as usize],
-        operands: vec![Operand::Reg(rt)],
+        operands: operands![Operand::Reg(rt)],
         ..Default::default()
     };
 
     let addr = Address { base: rn, mode };
+    let rm = REG_MAP[bits(instr, 0..3) as usize];
     let offset = if a == 1 {
-        let rm = REG_MAP[bits(instr, 0..3) as usize];
-        let shift = decode_imm_shift(bits(instr, 5..6), bits(instr, 7..11))?;
+        let shift = decode_imm_shift(bits(instr, 5..6), bits(instr, 7..11));
         let shift = if shift.imm == 0 { None } else { Some(shift) };
         Offset::reg(rm, shift, add)
     } else {
@@ -258,36 +343,252 @@ pub fn arm_load_store(instr: u32) -> DisasmResult<Instruction> {
     };
     instruction.operands.push(Operand::Addr(addr));
     instruction.extra = Some(offset.into());
-    Ok(instruction)
+
+    let writeback = mode != AddrMode::Offset;
+    check_predictable(
+        instr,
+        instruction,
+        &[
+            (a == 1 && rm == Register::PC, Unpredictable::PcOperand),
+            (writeback && (rn == Register::PC || rn == rt), Unpredictable::WritebackBase),
+        ],
+    )
 }
 
-pub fn arm_media(instr: u32) -> DisasmResult<Instruction> {
-    Err(DisasmError::new("media instructions undefined for ARMv4", instr))
+pub fn arm_media(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
+    Err(DisasmError::unsupported(instr, arch))
 }
 
+/// B and BL, with the offset relative to PC
 pub fn arm_branch(instr: u32) -> DisasmResult<Instruction> {
     let op = match bits(instr, 24..25) {
         0b10 => Op::B,
         0b11 => Op::BL,
         _ => {
-            return Err(DisasmError::new("invalid branch instruction", instr));
+            return Err(DisasmError::undefined(instr));
         }
     };
+    // Sign extend the 26-bit offset
+    let offset = (((bits(instr, 0..23) << 8) as i32) >> 6) as u32;
     Ok(Instruction {
         cond: COND_MAP[bits(instr, 28..31) as usize],
-        op: op,
-        operands: vec![Operand::Imm(bits(instr, 0..23))],
+        op,
+        operands: operands![Operand::Imm(offset)],
         extra: None,
         set_flags: false,
     })
 }
 
-pub fn arm_coprocessor(instr: u32) -> DisasmResult<Instruction> {
-    todo!()
+/// Decode instructions described in A5.6 - Coprocessor instructions, and Supervisor Call. The
+/// unconditional encodings give the "2" variants of the coprocessor instructions
+pub fn arm_coprocessor(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
+    let op1 = bits(instr, 20..25);
+    let unconditional = bits(instr, 28..31) == 0b1111;
+    let mut instruction = match op1 {
+        x if x & 0b111110 == 0b000000 => {
+            return Err(DisasmError::undefined(instr));
+        }
+        x if x & 0b110000 == 0b110000 => {
+            if unconditional {
+                return Err(DisasmError::undefined(instr));
+            }
+            Instruction {
+                op: Op::SVC,
+                operands: operands![Operand::Imm(bits(instr, 0..23))],
+                ..Default::default()
+            }
+        }
+        x if x & 0b111110 == 0b000100 => {
+            // MCRR2/MRRC2 were only added in ARMv6
+            if unconditional {
+                return Err(DisasmError::undefined(instr));
+            }
+            if arch < Arch::ARMv5TE {
+                return Err(DisasmError::unsupported(instr, arch));
+            }
+            arm_coproc_reg_pair(instr)
+        }
+        x if x & 0b100000 == 0b000000 => arm_coproc_load_store(instr)?,
+        _ => match bit(instr, 4) {
+            0 => arm_coproc_data_proc(instr),
+            _ => arm_coproc_reg_transfer(instr),
+        },
+    };
+    if unconditional {
+        instruction.op = match instruction.op {
+            Op::CDP => Op::CDP2,
+            Op::MCR => Op::MCR2,
+            Op::MRC => Op::MRC2,
+            Op::LDC => Op::LDC2,
+            Op::LDCL => Op::LDC2L,
+            Op::STC => Op::STC2,
+            Op::STCL => Op::STC2L,
+            _ => return Err(DisasmError::undefined(instr)),
+        };
+    } else {
+        instruction.cond = COND_MAP[bits(instr, 28..31) as usize];
+    }
+    Ok(instruction)
 }
 
-pub fn arm_block_data_transfer(instr: u32) -> DisasmResult<Instruction> {
-    todo!()
+/// CDP
+fn arm_coproc_data_proc(instr: u32) -> Instruction {
+    Instruction {
+        op: Op::CDP,
+        operands: operands![
+            Operand::Coproc(bits(instr, 8..11)),
+            Operand::CoprocOpcode(bits(instr, 20..23)),
+            Operand::CoprocReg(bits(instr, 12..15)),
+            Operand::CoprocReg(bits(instr, 16..19)),
+            Operand::CoprocReg(bits(instr, 0..3)),
+            Operand::CoprocOpcode(bits(instr, 5..7)),
+        ],
+        ..Default::default()
+    }
+}
+
+/// MCR/MRC, transferring a single ARM register
+fn arm_coproc_reg_transfer(instr: u32) -> Instruction {
+    let op = match bit(instr, 20) {
+        0 => Op::MCR,
+        _ => Op::MRC,
+    };
+    Instruction {
+        op,
+        operands: operands![
+            Operand::Coproc(bits(instr, 8..11)),
+            Operand::CoprocOpcode(bits(instr, 21..23)),
+            Operand::Reg(REG_MAP[bits(instr, 12..15) as usize]),
+            Operand::CoprocReg(bits(instr, 16..19)),
+            Operand::CoprocReg(bits(instr, 0..3)),
+            Operand::CoprocOpcode(bits(instr, 5..7)),
+        ],
+        ..Default::default()
+    }
+}
+
+/// MCRR/MRRC, transferring a pair of ARM registers
+fn arm_coproc_reg_pair(instr: u32) -> Instruction {
+    let op = match bit(instr, 20) {
+        0 => Op::MCRR,
+        _ => Op::MRRC,
+    };
+    Instruction {
+        op,
+        operands: operands![
+            Operand::Coproc(bits(instr, 8..11)),
+            Operand::CoprocOpcode(bits(instr, 4..7)),
+            Operand::Reg(REG_MAP[bits(instr, 12..15) as usize]),
+            Operand::Reg(REG_MAP[bits(instr, 16..19) as usize]),
+            Operand::CoprocReg(bits(instr, 0..3)),
+        ],
+        ..Default::default()
+    }
+}
+
+/// LDC/STC. The offset is a word offset, and the unindexed form passes the immediate to the
+/// coprocessor as an option instead
+fn arm_coproc_load_store(instr: u32) -> DisasmResult<Instruction> {
+    let op = match (bit(instr, 20), bit(instr, 22)) {
+        (0, 0) => Op::STC,
+        (0, _) => Op::STCL,
+        (_, 0) => Op::LDC,
+        _ => Op::LDCL,
+    };
+    let rn = REG_MAP[bits(instr, 16..19) as usize];
+    let imm = bits(instr, 0..7);
+    let add = bit(instr, 23) == 1;
+    let mut operands = operands![
+        Operand::Coproc(bits(instr, 8..11)),
+        Operand::CoprocReg(bits(instr, 12..15)),
+    ];
+    let mode = match (bit(instr, 24), bit(instr, 21)) {
+        (1, 0) => AddrMode::Offset,
+        (1, _) => AddrMode::PreIndex,
+        (0, 1) => AddrMode::PostIndex,
+        _ => {
+            if !add {
+                return Err(DisasmError::undefined(instr));
+            }
+            operands.push(Operand::Addr(Address { base: rn, mode: AddrMode::Offset }));
+            operands.push(Operand::CoprocOption(imm));
+            return Ok(Instruction { op, operands, ..Default::default() });
+        }
+    };
+    operands.push(Operand::Addr(Address { base: rn, mode }));
+    Ok(Instruction {
+        op,
+        operands,
+        extra: Some(Offset::imm(imm << 2, add).into()),
+        ..Default::default()
+    })
+}
+
+/// Decode LDM/STM and the PUSH/POP aliases (A5.5 - Branch, branch with link, and block data
+/// transfer)
+pub fn arm_block_data_transfer(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
+    let load = bit(instr, 20) == 1;
+    let w = bit(instr, 21) == 1;
+    let user = bit(instr, 22) == 1;
+    let rn = REG_MAP[bits(instr, 16..19) as usize];
+    let list = RegList { regs: RegSet::new(bits(instr, 0..15)), user };
+
+    // P and U bits select the addressing mode
+    let op = match (bits(instr, 23..24), load) {
+        (0b00, false) => Op::STMDA,
+        (0b00, true) => Op::LDMDA,
+        (0b01, false) => Op::STMIA,
+        (0b01, true) => Op::LDMIA,
+        (0b10, false) => Op::STMDB,
+        (0b10, true) => Op::LDMDB,
+        (0b11, false) => Op::STMIB,
+        (_, _) => Op::LDMIB,
+    };
+    let cond = COND_MAP[bits(instr, 28..31) as usize];
+    let checks = [
+        (rn == Register::PC, Unpredictable::PcOperand),
+        (list.regs.is_empty(), Unpredictable::EmptyRegList),
+        // ARMv4 defines the result as the loaded value, but ARMv5 leaves it UNPREDICTABLE
+        (load && w && list.regs.contains(rn) && arch >= Arch::ARMv5TE, Unpredictable::BaseInList),
+        // STM stores the original base only if it's the lowest register in the list
+        (
+            !load && w && list.regs.contains(rn) && list.regs.iter().next() != Some(rn),
+            Unpredictable::BaseInList,
+        ),
+        (user && w && !(load && list.regs.contains(Register::PC)), Unpredictable::UserWriteback),
+    ];
+
+    // Single register pushes and pops are encoded as STR/LDR instead, so the alias only applies
+    // when there are at least 2 registers
+    if rn == Register::SP && w && !user && list.regs.len() >= 2 {
+        let alias = match op {
+            Op::STMDB => Some(Op::PUSH),
+            Op::LDMIA => Some(Op::POP),
+            _ => None,
+        };
+        if let Some(op) = alias {
+            let decoded = Instruction {
+                cond,
+                op,
+                operands: operands![Operand::RegList(list)],
+                ..Default::default()
+            };
+            return check_predictable(instr, decoded, &checks);
+        }
+    }
+
+    let base = if w {
+        Operand::RegWb(rn)
+    } else {
+        Operand::Reg(rn)
+    };
+    let decoded = Instruction {
+        cond,
+        op,
+        operands: operands![base, Operand::RegList(list)],
+        ..Default::default()
+    };
+    check_predictable(instr, decoded, &checks)
 }
 
 /// Decode data-processing instructions with a register operand  that can optionally be shifted by a
@@ -296,27 +597,26 @@ fn arm_data_proc_reg(instr: u32) -> DisasmResult<Instruction> {
     let op1 = bits(instr, 20..24);
     let op2 = bits(instr, 5..6);
     let imm = bits(instr, 7..11);
-    let op = decode_data_proc_op(op1, Some(op2), Some(imm)).map_err(|e| e.set_instr(instr))?;
+    let op = decode_data_proc_op(op1, Some(op2), Some(imm)).ok_or(DisasmError::undefined(instr))?;
 
-    let mut result = Instruction::default();
-    result.op = op;
-    result.cond = COND_MAP[bits(instr, 28..31) as usize];
-    result.set_flags = bit(instr, 20) == 1;
+    let mut result = Instruction {
+        op,
+        cond: COND_MAP[bits(instr, 28..31) as usize],
+        set_flags: bit(instr, 20) == 1,
+        ..Default::default()
+    };
 
     let rd = REG_MAP[bits(instr, 12..15) as usize];
     let rn = REG_MAP[bits(instr, 16..19) as usize];
     let rm = REG_MAP[bits(instr, 0..3) as usize];
     let imm5 = bits(instr, 7..11);
 
-    let shift = decode_imm_shift(bits(instr, 5..6), imm5)?;
-    let shift = (shift.imm != 0).then_some(ExtraOperand::from(shift));
+    let imm_shift = decode_imm_shift(bits(instr, 5..6), imm5);
+    let shift = (imm_shift.imm != 0).then_some(ExtraOperand::from(imm_shift));
 
     match op {
         Op::ADR => {
-            return Err(DisasmError::new(
-                "The second arg for ADR op cannot come from a register",
-                instr,
-            ));
+            return Err(DisasmError::undefined(instr));
         }
         Op::MVN => {
             result.operands.push(Operand::Reg(rd));
@@ -332,7 +632,8 @@ fn arm_data_proc_reg(instr: u32) -> DisasmResult<Instruction> {
             // place them in the "data-processing (register)" category
             result.operands.push(Operand::Reg(rd));
             result.operands.push(Operand::Reg(rm));
-            result.operands.push(Operand::Imm(imm5));
+            // LSR and ASR by 32 are encoded as 0
+            result.operands.push(Operand::Imm(imm_shift.imm));
         }
         Op::TEQ | Op::TST | Op::CMN | Op::CMP => {
             result.operands.push(Operand::Reg(rn));
@@ -353,12 +654,14 @@ fn arm_data_proc_reg(instr: u32) -> DisasmResult<Instruction> {
 fn arm_data_proc_shift_reg(instr: u32) -> DisasmResult<Instruction> {
     let op1 = bits(instr, 20..24);
     let op2 = bits(instr, 5..6);
-    let op = decode_data_proc_op(op1, Some(op2), None).map_err(|e| e.set_instr(instr))?;
+    let op = decode_data_proc_op(op1, Some(op2), None).ok_or(DisasmError::undefined(instr))?;
 
-    let mut result = Instruction::default();
-    result.op = op;
-    result.cond = COND_MAP[bits(instr, 28..31) as usize];
-    result.set_flags = bit(instr, 20) == 1;
+    let mut result = Instruction {
+        op,
+        cond: COND_MAP[bits(instr, 28..31) as usize],
+        set_flags: bit(instr, 20) == 1,
+        ..Default::default()
+    };
 
     let rd = REG_MAP[bits(instr, 12..15) as usize];
     let rn = REG_MAP[bits(instr, 16..19) as usize];
@@ -370,7 +673,7 @@ fn arm_data_proc_shift_reg(instr: u32) -> DisasmResult<Instruction> {
             0b01 => ShiftOp::LSR,
             0b10 => ShiftOp::ASR,
             0b11 => ShiftOp::ROR,
-            _ => unreachable!(),
+            _ => return Err(DisasmError::undefined(instr)),
         },
         value: ExtraValue::Reg(rs),
     }));
@@ -382,10 +685,7 @@ fn arm_data_proc_shift_reg(instr: u32) -> DisasmResult<Instruction> {
             result.extra = shift;
         }
         Op::ADR | Op::MOV | Op::RRX => {
-            return Err(DisasmError::new(
-                format!("{:?} op cannot be register-shifted", op).as_str(),
-                instr,
-            ));
+            return Err(DisasmError::undefined(instr));
         }
         // NOTE - could also encode these as a MOV
         Op::LSL | Op::LSR | Op::ASR | Op::ROR => {
@@ -407,7 +707,8 @@ fn arm_data_proc_shift_reg(instr: u32) -> DisasmResult<Instruction> {
             result.extra = shift
         }
     }
-    Ok(result)
+    let uses_pc = rs == Register::PC || result.operands.contains(&Operand::Reg(Register::PC));
+    check_predictable(instr, result, &[(uses_pc, Unpredictable::PcOperand)])
 }
 
 /// Implements the ARMExpandImm() psuedo-code function
@@ -417,28 +718,45 @@ fn expand_imm(imm12: u32) -> u32 {
     val.rotate_right(shift)
 }
 
+/// Decodes the immediate operand of a data-processing instruction. Assemblers use the smallest
+/// rotation, which is what `Operand::Imm` implies, and any other rotation is kept as a ROR of the
+/// 8-bit value. It's needed to encode the instruction again, and it decides the shifter carry out:
+/// e.g. 1 encoded as 4 ROR 2 sets C, unlike the unrotated 1
+fn decode_rotated_imm(imm12: u32) -> (u32, Option<ExtraOperand>) {
+    let imm = expand_imm(imm12);
+    let rot = bits(imm12, 8..11);
+    let smallest = (0..16).find(|r| imm.rotate_left(2 * r) <= 0xFF);
+    match smallest == Some(rot) {
+        true => (imm, None),
+        false => {
+            let shift = ImmShift { op: ShiftOp::ROR, imm: 2 * rot };
+            (bits(imm12, 0..7), Some(shift.into()))
+        }
+    }
+}
+
 /// Decode data-processing instructions with an immedate data operand (excluding shift instructions)
 fn arm_data_proc_imm(instr: u32) -> DisasmResult<Instruction> {
     let op1 = bits(instr, 20..24);
-    let op = decode_data_proc_op(op1, None, None).map_err(|e| e.set_instr(instr))?;
+    let op = decode_data_proc_op(op1, None, None).ok_or(DisasmError::undefined(instr))?;
 
-    let mut result = Instruction::default();
-    result.op = op;
-    result.cond = COND_MAP[bits(instr, 28..31) as usize];
-    result.set_flags = bit(instr, 20) == 1;
+    let mut result = Instruction {
+        op,
+        cond: COND_MAP[bits(instr, 28..31) as usize],
+        set_flags: bit(instr, 20) == 1,
+        ..Default::default()
+    };
 
     let rd = REG_MAP[bits(instr, 12..15) as usize];
     let rn = REG_MAP[bits(instr, 16..19) as usize];
     let rm = REG_MAP[bits(instr, 0..3) as usize];
     let rs = REG_MAP[bits(instr, 8..11) as usize];
-    let imm = expand_imm(bits(instr, 0..11));
+    let (imm, extra) = decode_rotated_imm(bits(instr, 0..11));
+    result.extra = extra;
 
     match op {
         Op::LSL | Op::LSR | Op::ASR | Op::ROR | Op::RRX => {
-            return Err(DisasmError::new(
-                format!("{:?} op has no immediate form", op).as_str(),
-                instr,
-            ));
+            return Err(DisasmError::undefined(instr));
         }
         Op::ADR | Op::MOV | Op::MVN => {
             // TODO - ADR is a PC-relative instruction. Need to figure out it the address should be
@@ -459,33 +777,89 @@ fn arm_data_proc_imm(instr: u32) -> DisasmResult<Instruction> {
     Ok(result)
 }
 
-fn arm_misc(instr: u32) -> DisasmResult<Instruction> {
+fn arm_misc(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
     let op = bits(instr, 21..22);
-    let op1 = bits(instr, 16..19);
     let op2 = bits(instr, 4..6);
     let b = bit(instr, 9);
 
-    let op = match (op2, b, op) {
-        // Not sure yet what the special registers are. Possibly need a new enum for them?
-        (0b000, 0b0, 0b00) => todo!(), // MRS
-        (0b000, 0b0, 0b10) => todo!(), // MRS
-        (0b000, 0b0, _) => todo!(),    // MSR
-        (0b001, _, 0b01) => Op::BX,
+    let cond = COND_MAP[bits(instr, 28..31) as usize];
+    let rd = REG_MAP[bits(instr, 12..15) as usize];
+    let rn = REG_MAP[bits(instr, 16..19) as usize];
+    let rm = REG_MAP[bits(instr, 0..3) as usize];
+
+    let (op, operands) = match (op2, b, op) {
+        (0b000, 0b0, 0b00) | (0b000, 0b0, 0b10) => {
+            return arm_mrs(instr);
+        }
+        (0b000, 0b0, _) => {
+            return arm_msr_reg(instr);
+        }
+        (0b001, _, 0b01) => (Op::BX, operands![Operand::Reg(rm)]),
+        (0b001, _, 0b11) if arch >= Arch::ARMv5TE => {
+            (Op::CLZ, operands![Operand::Reg(rd), Operand::Reg(rm)])
+        }
+        (0b011, _, 0b01) if arch >= Arch::ARMv5TE => (Op::BLX, operands![Operand::Reg(rm)]),
+        (0b101, _, _) if arch >= Arch::ARMv5TE => {
+            let op = match op {
+                0b00 => Op::QADD,
+                0b01 => Op::QSUB,
+                0b10 => Op::QDADD,
+                _ => Op::QDSUB,
+            };
+            (op, operands![Operand::Reg(rd), Operand::Reg(rm), Operand::Reg(rn)])
+        }
+        (0b111, _, 0b01) if arch >= Arch::ARMv5TE => {
+            let imm16 = (bits(instr, 8..19) << 4) | bits(instr, 0..3);
+            (Op::BKPT, operands![Operand::Imm(imm16)])
+        }
+        (0b001, _, 0b11) | (0b011, _, 0b01) | (0b101, _, _) | (0b111, _, 0b01) => {
+            return Err(DisasmError::unsupported(instr, arch));
+        }
         _ => {
             return Err(DisasmError::undefined(instr));
         }
     };
+    // BX is the only one of these which may use PC
+    let uses_pc = op != Op::BX && operands.contains(&Operand::Reg(Register::PC));
+    let decoded = Instruction { cond, op, operands, ..Default::default() };
+    check_predictable(instr, decoded, &[(uses_pc, Unpredictable::PcOperand)])
+}
 
-    let cond = COND_MAP[bits(instr, 28..31) as usize];
-    let rm = REG_MAP[bits(instr, 0..3) as usize];
-    Ok(Instruction {
-        cond,
-        op,
-        operands: vec![Operand::Reg(rm)],
+/// Status register selected by the R bit of MRS/MSR
+fn decode_status_reg(instr: u32) -> StatusReg {
+    match bit(instr, 22) {
+        0 => StatusReg::CPSR,
+        _ => StatusReg::SPSR,
+    }
+}
+
+/// Move status register to a general purpose register
+fn arm_mrs(instr: u32) -> DisasmResult<Instruction> {
+    let rd = REG_MAP[bits(instr, 12..15) as usize];
+    let decoded = Instruction {
+        cond: COND_MAP[bits(instr, 28..31) as usize],
+        op: Op::MRS,
+        operands: operands![Operand::Reg(rd), Operand::Psr(decode_status_reg(instr))],
         ..Default::default()
-    })
+    };
+    check_predictable(instr, decoded, &[(rd == Register::PC, Unpredictable::PcOperand)])
 }
 
+/// Move a register to the status register fields selected by the mask
+fn arm_msr_reg(instr: u32) -> DisasmResult<Instruction> {
+    let psr = Operand::PsrFields(decode_status_reg(instr), bits(instr, 16..19));
+    let rn = REG_MAP[bits(instr, 0..3) as usize];
+    let decoded = Instruction {
+        cond: COND_MAP[bits(instr, 28..31) as usize],
+        op: Op::MSR,
+        operands: operands![psr, Operand::Reg(rn)],
+        ..Default::default()
+    };
+    check_predictable(instr, decoded, &[(rn == Register::PC, Unpredictable::PcOperand)])
+}
+
+/// Decode MUL/MLA and the long multiplies. The long multiplies write RdLo and RdHi, which are the
+/// first two operands in that order
 fn arm_mult(instr: u32) -> DisasmResult<Instruction> {
     let op = match bits(instr, 21..23) {
         0b000 => Op::MUL,
@@ -498,34 +872,98 @@ fn arm_mult(instr: u32) -> DisasmResult<Instruction> {
             return Err(DisasmError::undefined(instr));
         }
     };
+    // RdHi and RdLo share the Rd and Rn fields
     let rd = REG_MAP[bits(instr, 16..19) as usize];
-    let ra = REG_MAP[bits(instr, 12..15) as usize];
-    let rm = REG_MAP[bits(instr, 8..11) as usize];
-    let rn = REG_MAP[bits(instr, 0..3) as usize];
-    let s = bit(instr, 20) == 1;
+    let rn = REG_MAP[bits(instr, 12..15) as usize];
+    let rs = REG_MAP[bits(instr, 8..11) as usize];
+    let rm = REG_MAP[bits(instr, 0..3) as usize];
 
-    let mut instr = Instruction::default();
-    match op {
-        Op::MUL => {
-            instr.operands.push(Operand::Reg(rd));
-            instr.operands.push(Operand::Reg(rn));
-            instr.operands.push(Operand::Reg(rm));
-        }
-        Op::MLA | Op::UMULL | Op::SMULL | Op::SMLAL => {
-            instr.operands.push(Operand::Reg(rd));
-            instr.operands.push(Operand::Reg(rn));
-            instr.operands.push(Operand::Reg(rm));
-            instr.operands.push(Operand::Reg(ra));
-        }
-        _ => unreachable!(),
-    }
-    Ok(instr)
+    let operands = match op {
+        Op::MUL => operands![Operand::Reg(rd), Operand::Reg(rm), Operand::Reg(rs)],
+        Op::MLA => operands![
+            Operand::Reg(rd),
+            Operand::Reg(rm),
+            Operand::Reg(rs),
+            Operand::Reg(rn)
+        ],
+        _ => operands![
+            Operand::Reg(rn),
+            Operand::Reg(rd),
+            Operand::Reg(rm),
+            Operand::Reg(rs)
+        ],
+    };
+    let decoded = Instruction {
+        cond: COND_MAP[bits(instr, 28..31) as usize],
+        op,
+        operands,
+        extra: None,
+        set_flags: bit(instr, 20) == 1,
+    };
+
+    // Before ARMv6 the destination registers must also differ from Rm
+    let long = !matches!(op, Op::MUL | Op::MLA);
+    let overlap = rd == rm || (long && (rn == rm || rn == rd));
+    let uses_pc = [rd, rm, rs].contains(&Register::PC) || (op != Op::MUL && rn == Register::PC);
+    check_predictable(
+        instr,
+        decoded,
+        &[
+            (uses_pc, Unpredictable::PcOperand),
+            (overlap, Unpredictable::RegisterOverlap),
+        ],
+    )
 }
 
-fn arm_halfword_mult(instr: u32) -> DisasmResult<Instruction> {
-    Err(DisasmError::new("halfword multiply instructions undefined in ARMv4T", instr))
+/// Decode the signed 16-bit multiplies. The x and y suffixes select the bottom (B) or top (T) half
+/// of the first and second source registers respectively
+fn arm_halfword_mult(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
+    if arch < Arch::ARMv5TE {
+        return Err(DisasmError::unsupported(instr, arch));
+    }
+    let rd = Operand::Reg(REG_MAP[bits(instr, 16..19) as usize]);
+    let ra = Operand::Reg(REG_MAP[bits(instr, 12..15) as usize]);
+    let rm = Operand::Reg(REG_MAP[bits(instr, 8..11) as usize]);
+    let rn = Operand::Reg(REG_MAP[bits(instr, 0..3) as usize]);
+
+    let (op, operands) = match (bits(instr, 21..22), bits(instr, 5..6)) {
+        (0b00, xy) => {
+            let op = [Op::SMLABB, Op::SMLATB, Op::SMLABT, Op::SMLATT][xy as usize];
+            (op, operands![rd, rn, rm, ra])
+        }
+        (0b01, 0b00) => (Op::SMLAWB, operands![rd, rn, rm, ra]),
+        (0b01, 0b10) => (Op::SMLAWT, operands![rd, rn, rm, ra]),
+        (0b01, 0b01) => (Op::SMULWB, operands![rd, rn, rm]),
+        (0b01, _) => (Op::SMULWT, operands![rd, rn, rm]),
+        (0b10, xy) => {
+            let op = [Op::SMLALBB, Op::SMLALTB, Op::SMLALBT, Op::SMLALTT][xy as usize];
+            // RdLo, RdHi
+            (op, operands![ra, rd, rn, rm])
+        }
+        (_, xy) => {
+            let op = [Op::SMULBB, Op::SMULTB, Op::SMULBT, Op::SMULTT][xy as usize];
+            (op, operands![rd, rn, rm])
+        }
+    };
+    let overlap = matches!(op, Op::SMLALBB | Op::SMLALTB | Op::SMLALBT | Op::SMLALTT) && rd == ra;
+    let uses_pc = operands.contains(&Operand::Reg(Register::PC));
+    let decoded = Instruction {
+        cond: COND_MAP[bits(instr, 28..31) as usize],
+        op,
+        operands,
+        ..Default::default()
+    };
+    check_predictable(
+        instr,
+        decoded,
+        &[
+            (uses_pc, Unpredictable::PcOperand),
+            (overlap, Unpredictable::RegisterOverlap),
+        ],
+    )
 }
 
+/// SWP and SWPB, which load Rt from memory and store Rt2 to the same address
 fn arm_sync(instr: u32) -> DisasmResult<Instruction> {
     let op = match bits(instr, 20..23) {
         0b0000 => Op::SWP,
@@ -534,72 +972,159 @@ fn arm_sync(instr: u32) -> DisasmResult<Instruction> {
             return Err(DisasmError::undefined(instr));
         }
     };
+    let rn = REG_MAP[bits(instr, 16..19) as usize];
+    let rt = REG_MAP[bits(instr, 12..15) as usize];
+    let rt2 = REG_MAP[bits(instr, 0..3) as usize];
+
+    let decoded = Instruction {
+        cond: COND_MAP[bits(instr, 28..31) as usize],
+        op,
+        operands: operands![
+            Operand::Reg(rt),
+            Operand::Reg(rt2),
+            Operand::Addr(Address { base: rn, mode: AddrMode::Offset }),
+        ],
+        ..Default::default()
+    };
+    let uses_pc = [rn, rt, rt2].contains(&Register::PC);
+    let overlap = rn == rt || rn == rt2;
+    check_predictable(
+        instr,
+        decoded,
+        &[
+            (uses_pc, Unpredictable::PcOperand),
+            (overlap, Unpredictable::RegisterOverlap),
+        ],
+    )
+}
 
-    let instr = Instruction::default();
-    Ok(instr)
+/// The transfer registers and address for an extra load/store. The doubleword transfers use a pair
+/// of consecutive registers starting at Rt
+fn extra_load_store_operands(op: Op, instr: u32, addr: Address) -> Operands {
+    let t = bits(instr, 12..15) as usize;
+    match op {
+        Op::LDRD | Op::STRD => operands![
+            Operand::Reg(REG_MAP[t]),
+            Operand::Reg(REG_MAP[(t + 1) % 16]),
+            Operand::Addr(addr),
+        ],
+        _ => operands![Operand::Reg(REG_MAP[t]), Operand::Addr(addr)],
+    }
+}
+
+/// Checks for the UNPREDICTABLE register combinations of the extra load/stores. `rm` is the offset
+/// register of the register forms
+fn check_extra_load_store(
+    instr: u32,
+    decoded: Instruction,
+    rm: Option<Register>,
+) -> DisasmResult<Instruction> {
+    let t = bits(instr, 12..15);
+    let rt = REG_MAP[t as usize];
+    let rn = REG_MAP[bits(instr, 16..19) as usize];
+    let doubleword = matches!(decoded.op, Op::LDRD | Op::STRD);
+    // The second register of LDRD/STRD
+    let rt2 = if doubleword {
+        REG_MAP[(t as usize + 1) % 16]
+    } else {
+        rt
+    };
+    let writeback = bit(instr, 24) == 0 || bit(instr, 21) == 1;
+    check_predictable(
+        instr,
+        decoded,
+        &[
+            (doubleword && (t % 2 == 1 || rt == Register::LR), Unpredictable::OddRegisterPair),
+            (rt == Register::PC || rm == Some(Register::PC), Unpredictable::PcOperand),
+            (
+                writeback && (rn == Register::PC || rn == rt || rn == rt2),
+                Unpredictable::WritebackBase,
+            ),
+            // Only LDRD restricts its offset register, the other loads may reuse Rt
+            (
+                decoded.op == Op::LDRD && (rm == Some(rt) || rm == Some(rt2)),
+                Unpredictable::RegisterOverlap,
+            ),
+        ],
+    )
 }
 
-fn arm_extra_load_store_reg(instr: u32) -> DisasmResult<Instruction> {
+fn arm_extra_load_store_reg(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
     let op1 = (bit(instr, 22) << 1) | (bit(instr, 20));
     let op2 = bits(instr, 5..6);
 
-    let op = decode_extra_load_store_op(op1, op2).map_err(|e| e.set_instr(instr))?;
+    let op = decode_extra_load_store_op(op1, op2, arch).ok_or(DisasmError::undefined(instr))?;
 
     let rm = REG_MAP[bits(instr, 0..3) as usize];
-    let rt = REG_MAP[bits(instr, 12..15) as usize];
     let rn = REG_MAP[bits(instr, 16..19) as usize];
     let w = bit(instr, 21);
     let p = bit(instr, 24);
     let add = bit(instr, 23) == 1;
 
-    let mode = decode_addressing_mode(p, w).map_err(|e| e.set_instr(instr))?;
+    let mode = decode_addressing_mode(p, w).ok_or(DisasmError::undefined(instr))?;
     let addr = Address { base: rn, mode };
     let offset = Offset::reg(rm, None, add);
 
-    Ok(Instruction {
+    let decoded = Instruction {
         op,
         cond: COND_MAP[bits(instr, 28..31) as usize],
-        operands: vec![Operand::Reg(rt), Operand::Addr(addr)],
+        operands: extra_load_store_operands(op, instr, addr),
         extra: Some(offset.into()),
         set_flags: false,
-    })
+    };
+    check_extra_load_store(instr, decoded, Some(rm))
 }
 
-fn arm_extra_load_store_imm(instr: u32) -> DisasmResult<Instruction> {
+fn arm_extra_load_store_imm(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
     let op1 = (bit(instr, 22) << 1) | (bit(instr, 20));
     let op2 = bits(instr, 5..6);
 
-    let op = decode_extra_load_store_op(op1, op2).map_err(|e| e.set_instr(instr))?;
+    let op = decode_extra_load_store_op(op1, op2, arch).ok_or(DisasmError::undefined(instr))?;
 
-    let rt = REG_MAP[bits(instr, 12..15) as usize];
     let rn = REG_MAP[bits(instr, 16..19) as usize];
     let imm = (bits(instr, 8..11) << 4) | bits(instr, 0..3);
     let w = bit(instr, 21);
     let p = bit(instr, 24);
     let add = bit(instr, 23) == 1;
 
-    let mode = decode_addressing_mode(p, w).map_err(|e| e.set_instr(instr))?;
+    let mode = decode_addressing_mode(p, w).ok_or(DisasmError::undefined(instr))?;
 
     let addr = Address { base: rn, mode };
     let offset = Offset::imm(imm, add);
 
-    Ok(Instruction {
+    let decoded = Instruction {
         op,
         cond: COND_MAP[bits(instr, 28..31) as usize],
-        operands: vec![Operand::Reg(rt), Operand::Addr(addr)],
+        operands: extra_load_store_operands(op, instr, addr),
         extra: Some(offset.into()),
         set_flags: false,
-    })
+    };
+    check_extra_load_store(instr, decoded, None)
 }
 
-fn arm_msr_and_hints(instr: u32) -> DisasmResult<Instruction> {
-    todo!()
+/// Decode MSR (immediate). The hints which share this space were added in ARMv6K, so before that
+/// writing no fields of CPSR is an MSR like any other, which has no effect
+fn arm_msr_imm(instr: u32) -> DisasmResult<Instruction> {
+    let cond = COND_MAP[bits(instr, 28..31) as usize];
+    let status_reg = decode_status_reg(instr);
+    let mask = bits(instr, 16..19);
+    Ok(Instruction {
+        cond,
+        op: Op::MSR,
+        operands: operands![
+            Operand::PsrFields(status_reg, mask),
+            Operand::Imm(expand_imm(bits(instr, 0..11))),
+        ],
+        ..Default::default()
+    })
 }
 
-fn arm_load_halfword_imm(instr: u32) -> DisasmResult<Instruction> {
-    todo!()
+/// MOVW, added in ARMv6T2
+fn arm_load_halfword_imm(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
+    Err(DisasmError::unsupported(instr, arch))
 }
 
-fn arm_load_high_halfword_imm(instr: u32) -> DisasmResult<Instruction> {
-    todo!()
+/// MOVT, added in ARMv6T2
+fn arm_load_high_halfword_imm(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
+    Err(DisasmError::unsupported(instr, arch))
 }
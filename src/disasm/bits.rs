@@ -8,7 +8,7 @@ pub fn bits(word: u32, r: Range<usize>) -> u32 {
 
 /// Takes the bits at the given positions and slides them to the right, in the order returned by the
 /// iterator
-pub fn pick_bits(word: u32, bits: &Vec<u32>) -> u32 {
+pub fn pick_bits(word: u32, bits: &[u32]) -> u32 {
     let mut x = 0;
     for (i, b) in bits.iter().enumerate() {
         x |= ((word >> b) & 1) << i;
@@ -80,8 +80,8 @@ mod tests {
 
     #[test]
     fn test_pick_bits() {
-        assert_eq!(pick_bits(0b10010101010111, &vec![1, 5, 4, 9]), 0b0101);
-        assert_eq!(pick_bits(0b10010101010111, &vec![3, 4]), 0b10);
-        assert_eq!(pick_bits(0b10010101010111, &vec![4, 3]), 0b01);
+        assert_eq!(pick_bits(0b10010101010111, &[1, 5, 4, 9]), 0b0101);
+        assert_eq!(pick_bits(0b10010101010111, &[3, 4]), 0b10);
+        assert_eq!(pick_bits(0b10010101010111, &[4, 3]), 0b01);
     }
 }
@@ -0,0 +1,260 @@
+use super::{
+    disassemble_arm_at, disassemble_thumb_at, disassemble_thumb_bl_at, is_thumb_bl_prefix, Arch,
+    DisasmResult,
+};
+use crate::ir::Instruction;
+
+/// The instruction set being executed, selected by the T bit of CPSR
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InstrSet {
+    ARM,
+    THUMB,
+}
+
+/// Guest memory that code is fetched from. Reads are little-endian, and return None for addresses
+/// which aren't mapped
+pub trait MemoryReader {
+    fn read_u16(&self, addr: u32) -> Option<u16>;
+    fn read_u32(&self, addr: u32) -> Option<u32>;
+}
+
+impl<M: MemoryReader + ?Sized> MemoryReader for &M {
+    fn read_u16(&self, addr: u32) -> Option<u16> {
+        (**self).read_u16(addr)
+    }
+
+    fn read_u32(&self, addr: u32) -> Option<u32> {
+        (**self).read_u32(addr)
+    }
+}
+
+/// Code held in a byte slice, which is mapped starting at `base`
+#[derive(Debug, Copy, Clone)]
+pub struct CodeSlice<'a> {
+    pub base: u32,
+    pub bytes: &'a [u8],
+}
+
+impl<'a> CodeSlice<'a> {
+    fn read<const N: usize>(&self, addr: u32) -> Option<[u8; N]> {
+        let start = addr.checked_sub(self.base)? as usize;
+        self.bytes
+            .get(start..start.checked_add(N)?)?
+            .try_into()
+            .ok()
+    }
+}
+
+impl<'a> MemoryReader for CodeSlice<'a> {
+    fn read_u16(&self, addr: u32) -> Option<u16> {
+        self.read(addr).map(u16::from_le_bytes)
+    }
+
+    fn read_u32(&self, addr: u32) -> Option<u32> {
+        self.read(addr).map(u32::from_le_bytes)
+    }
+}
+
+/// Iterator over the instructions of a basic block, yielding the address, raw encoding and decoded
+/// instruction of each. PC-relative operands are resolved as for [disassemble_arm_at].
+///
+/// The block ends after the first instruction which may write PC (see
+/// [Instruction::changes_control_flow]), the first which fails to decode, or at the end of mapped
+/// memory. For a THUMB BL/BLX pair, the raw encoding holds the prefix in the low halfword and the
+/// suffix in the high halfword, as they are laid out in memory
+pub struct BlockDisassembler<M> {
+    memory: M,
+    addr: u32,
+    instr_set: InstrSet,
+    arch: Arch,
+    done: bool,
+}
+
+impl<M: MemoryReader> BlockDisassembler<M> {
+    /// Disassemble the block starting at `addr`
+    pub fn new(memory: M, addr: u32, instr_set: InstrSet, arch: Arch) -> Self {
+        Self { memory, addr, instr_set, arch, done: false }
+    }
+
+    /// Address of the next instruction, which follows the block once the iterator is exhausted
+    pub fn addr(&self) -> u32 {
+        self.addr
+    }
+
+    /// Decode the next instruction, returning its raw encoding and size in bytes
+    fn decode_arm(&self) -> Option<(u32, u32, DisasmResult<Instruction>)> {
+        let raw = self.memory.read_u32(self.addr)?;
+        Some((raw, 4, disassemble_arm_at(self.addr, raw, self.arch)))
+    }
+
+    fn decode_thumb(&self) -> Option<(u32, u32, DisasmResult<Instruction>)> {
+        let addr = self.addr;
+        let prefix = self.memory.read_u16(addr)?;
+        // A prefix without a readable suffix is decoded alone, which gives an Incomplete error
+        if is_thumb_bl_prefix(prefix) {
+            if let Some(suffix) = self.memory.read_u16(addr.wrapping_add(2)) {
+                let raw = (prefix as u32) | ((suffix as u32) << 16);
+                return Some((raw, 4, disassemble_thumb_bl_at(addr, prefix, suffix, self.arch)));
+            }
+        }
+        Some((prefix as u32, 2, disassemble_thumb_at(addr, prefix, self.arch)))
+    }
+}
+
+impl<M: MemoryReader> Iterator for BlockDisassembler<M> {
+    type Item = (u32, u32, DisasmResult<Instruction>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let decoded = match self.instr_set {
+            InstrSet::ARM => self.decode_arm(),
+            InstrSet::THUMB => self.decode_thumb(),
+        };
+        let Some((raw, size, result)) = decoded else {
+            self.done = true;
+            return None;
+        };
+        let addr = self.addr;
+        self.addr = addr.wrapping_add(size);
+        self.done = match &result {
+            Ok(instr) => instr.changes_control_flow(),
+            Err(_) => true,
+        };
+        Some((addr, raw, result))
+    }
+}
+
+/// Disassemble the basic block starting at the beginning of `bytes`, which is located at `base`
+pub fn disassemble_block(
+    bytes: &[u8],
+    base: u32,
+    instr_set: InstrSet,
+    arch: Arch,
+) -> BlockDisassembler<CodeSlice<'_>> {
+    BlockDisassembler::new(CodeSlice { base, bytes }, base, instr_set, arch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble_block, BlockDisassembler, CodeSlice, InstrSet, MemoryReader};
+    use crate::disasm::{Arch::ARMv5TE, DisasmError};
+    use crate::encode::encode_arm_at;
+    use crate::ir::{parsing::instruction, Op, Operand};
+    use crate::operands;
+
+    /// Assemble ARM code starting at `base`
+    fn assemble(base: u32, asm: &[&str]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for (i, line) in asm.iter().enumerate() {
+            let addr = base + 4 * i as u32;
+            let encoding = encode_arm_at(addr, &instruction(line).unwrap().1).unwrap();
+            bytes.extend(encoding.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn ops<M: MemoryReader>(block: BlockDisassembler<M>) -> Vec<(u32, Op)> {
+        block
+            .map(|(addr, _, result)| (addr, result.unwrap().op))
+            .collect()
+    }
+
+    #[test]
+    fn test_block_arm() {
+        let base = 0x0200_0000;
+        let code = assemble(
+            base,
+            &[
+                "mov r0, #1",
+                "add r1, r0, r0",
+                "bne 0x02000000",
+                "mov r2, #3",
+            ],
+        );
+        let mut block = disassemble_block(&code, base, InstrSet::ARM, ARMv5TE);
+        let (addr, raw, result) = block.next().unwrap();
+        assert_eq!((addr, raw), (base, 0xE3A0_0001));
+        assert_eq!(result.unwrap().op, Op::MOV);
+        assert_eq!(block.next().unwrap().0, base + 4);
+        // The branch target is resolved
+        let (addr, _, result) = block.next().unwrap();
+        assert_eq!(addr, base + 8);
+        assert_eq!(result.unwrap().operands, operands![Operand::Target(base)]);
+        assert!(block.next().is_none());
+        assert_eq!(block.addr(), base + 12);
+
+        // Blocks can also start part way through memory
+        let memory = CodeSlice { base, bytes: &code };
+        let block = BlockDisassembler::new(&memory, base + 12, InstrSet::ARM, ARMv5TE);
+        assert_eq!(ops(block), vec![(base + 12, Op::MOV)]);
+
+        // PC writes end the block
+        for terminator in [
+            "mov pc, lr",
+            "ldr pc, [r0]",
+            "ldmia sp!, {r4, pc}",
+            "pop {r0, pc}",
+            "addne pc, pc, r0, lsl #2",
+            "svc #0",
+        ] {
+            let code = assemble(base, &["cmp r0, #0", terminator, "mov r0, r0"]);
+            let block = disassemble_block(&code, base, InstrSet::ARM, ARMv5TE);
+            assert_eq!(block.count(), 2, "{terminator}");
+        }
+        for other in [
+            "str pc, [r0]",
+            "cmp pc, r0",
+            "push {r0, lr}",
+            "ldmia r0!, {r1, r2}",
+        ] {
+            let code = assemble(base, &[other, "mov r0, r0"]);
+            let block = disassemble_block(&code, base, InstrSet::ARM, ARMv5TE);
+            assert_eq!(block.count(), 2, "{other}");
+        }
+
+        // Undefined instructions end the block
+        let mut code = assemble(base, &["mov r0, #1"]);
+        code.extend(0xE7F0_00F0u32.to_le_bytes());
+        code.extend(assemble(base + 8, &["mov r0, #1"]));
+        let mut block = disassemble_block(&code, base, InstrSet::ARM, ARMv5TE);
+        assert!(block.next().unwrap().2.is_ok());
+        assert_eq!(block.next().unwrap().2.unwrap_err().instr(), 0xE7F0_00F0);
+        assert!(block.next().is_none());
+
+        // So does the end of memory, including a partial word
+        let block = disassemble_block(&code[..10], base, InstrSet::ARM, ARMv5TE);
+        assert_eq!(block.count(), 2);
+        let block = disassemble_block(&code[..6], base, InstrSet::ARM, ARMv5TE);
+        assert_eq!(block.count(), 1);
+    }
+
+    #[test]
+    fn test_block_thumb() {
+        let base = 0x0200_0000;
+        // movs r0, #1; adds r1, r0, r0; bl 0x02000100; bx lr
+        let halfwords: [u16; 5] = [0x2001, 0x1801, 0xF000, 0xF87C, 0x4770];
+        let code: Vec<u8> = halfwords.iter().flat_map(|h| h.to_le_bytes()).collect();
+
+        let mut block = disassemble_block(&code, base, InstrSet::THUMB, ARMv5TE);
+        assert_eq!(block.next().unwrap().0, base);
+        assert_eq!(block.next().unwrap().0, base + 2);
+        let (addr, raw, result) = block.next().unwrap();
+        assert_eq!((addr, raw), (base + 4, 0xF87C_F000));
+        let bl = result.unwrap();
+        assert_eq!((bl.op, bl.operands[0]), (Op::BL, Operand::Target(base + 0x100)));
+        assert!(block.next().is_none());
+        assert_eq!(block.addr(), base + 8);
+
+        let block = disassemble_block(&code[8..], base + 8, InstrSet::THUMB, ARMv5TE);
+        assert_eq!(ops(block), vec![(base + 8, Op::BX)]);
+
+        // A BL prefix at the end of memory can't be decoded
+        let mut block = disassemble_block(&code[..6], base, InstrSet::THUMB, ARMv5TE);
+        let (addr, raw, result) = block.nth(2).unwrap();
+        assert_eq!((addr, raw), (base + 4, 0xF000));
+        assert!(matches!(result, Err(DisasmError::Incomplete { .. })));
+        assert!(block.next().is_none());
+    }
+}
@@ -1,2 +1,381 @@
+use super::arm::{COND_MAP, REG_MAP};
+use super::bits::{bit, bits};
+use super::{check_predictable, Arch, DisasmError, DisasmResult, Unpredictable};
+use crate::ir::{
+    AddrMode, Address, ExtraOperand, Instruction, Offset, Op, Operand, RegList, Register,
+};
+use crate::operands;
+
 /// Number of lookahead bytes in THUMB mode
-const PC_LA_THUMB: u32 = 4;
+pub(super) const PC_LA_THUMB: u32 = 4;
+
+/// Sign extend the lowest `width` bits of `x`
+fn sign_extend(x: u32, width: u32) -> u32 {
+    let shift = 32 - width;
+    (((x << shift) as i32) >> shift) as u32
+}
+
+/// Get a low register (R0-R7) from the 3 bits starting at `start`
+fn low_reg(instr: u32, start: usize) -> Register {
+    REG_MAP[bits(instr, start..start + 2) as usize]
+}
+
+/// Returns true if the halfword is the first half of a BL or BLX (immediate) instruction. These
+/// are made up of a prefix holding the upper bits of the branch offset, followed by a suffix
+/// holding the lower bits, and must be decoded together using `thumb_bl_pair`
+pub fn is_bl_prefix(instr: u16) -> bool {
+    bits(instr as u32, 11..15) == 0b11110
+}
+
+/// Decode a single 16-bit THUMB instruction. Halves of a BL/BLX pair cannot be decoded on their own
+pub fn thumb(instr: u16, arch: Arch) -> DisasmResult<Instruction> {
+    let instr = instr as u32;
+    match bits(instr, 13..15) {
+        0b000 => match bits(instr, 11..12) {
+            0b11 => thumb_add_sub(instr),
+            _ => thumb_shift_imm(instr),
+        },
+        0b001 => thumb_data_proc_imm(instr),
+        0b010 => match bits(instr, 10..12) {
+            0b000 => thumb_data_proc_reg(instr),
+            0b001 => thumb_hi_reg(instr, arch),
+            0b010 | 0b011 => thumb_load_literal(instr),
+            _ => thumb_load_store_reg(instr),
+        },
+        0b011 => thumb_load_store_imm(instr),
+        0b100 => match bit(instr, 12) {
+            0 => thumb_load_store_halfword(instr),
+            _ => thumb_load_store_sp(instr),
+        },
+        0b101 => match bit(instr, 12) {
+            0 => thumb_add_pc_sp(instr),
+            _ => thumb_misc(instr, arch),
+        },
+        0b110 => match bit(instr, 12) {
+            0 => thumb_block_data_transfer(instr),
+            _ => thumb_cond_branch(instr),
+        },
+        _ => match bits(instr, 11..12) {
+            0b00 => thumb_branch(instr),
+            0b01 if arch < Arch::ARMv5TE => Err(DisasmError::unsupported(instr, arch)),
+            _ => Err(DisasmError::Incomplete { instr }),
+        },
+    }
+}
+
+/// Decode a BL or BLX (immediate) from its prefix and suffix halfwords
+pub fn thumb_bl_pair(prefix: u16, suffix: u16, arch: Arch) -> DisasmResult<Instruction> {
+    let (prefix, suffix) = (prefix as u32, suffix as u32);
+    let instr = (prefix << 16) | suffix;
+    if bits(prefix, 11..15) != 0b11110 {
+        return Err(DisasmError::undefined(instr));
+    }
+    let op = match bits(suffix, 11..15) {
+        0b11111 => Op::BL,
+        0b11101 if arch < Arch::ARMv5TE => {
+            return Err(DisasmError::unsupported(instr, arch));
+        }
+        0b11101 if bit(suffix, 0) == 0 => Op::BLX,
+        _ => {
+            return Err(DisasmError::undefined(instr));
+        }
+    };
+    let offset = sign_extend((bits(prefix, 0..10) << 12) | (bits(suffix, 0..10) << 1), 23);
+    Ok(Instruction {
+        op,
+        operands: operands![Operand::Imm(offset)],
+        ..Default::default()
+    })
+}
+
+/// LSL, LSR and ASR by an immediate
+fn thumb_shift_imm(instr: u32) -> DisasmResult<Instruction> {
+    let rd = low_reg(instr, 0);
+    let rm = low_reg(instr, 3);
+    let imm5 = bits(instr, 6..10);
+    let (op, imm) = match bits(instr, 11..12) {
+        0b00 if imm5 == 0 => {
+            return Ok(Instruction {
+                op: Op::MOV,
+                operands: operands![Operand::Reg(rd), Operand::Reg(rm)],
+                set_flags: true,
+                ..Default::default()
+            });
+        }
+        0b00 => (Op::LSL, imm5),
+        0b01 => (Op::LSR, if imm5 == 0 { 32 } else { imm5 }),
+        _ => (Op::ASR, if imm5 == 0 { 32 } else { imm5 }),
+    };
+    Ok(Instruction {
+        op,
+        operands: operands![Operand::Reg(rd), Operand::Reg(rm), Operand::Imm(imm)],
+        set_flags: true,
+        ..Default::default()
+    })
+}
+
+/// ADD and SUB with a register or 3-bit immediate operand
+fn thumb_add_sub(instr: u32) -> DisasmResult<Instruction> {
+    let rd = low_reg(instr, 0);
+    let rn = low_reg(instr, 3);
+    let op = match bit(instr, 9) {
+        0 => Op::ADD,
+        _ => Op::SUB,
+    };
+    let arg = match bit(instr, 10) {
+        0 => Operand::Reg(low_reg(instr, 6)),
+        _ => Operand::Imm(bits(instr, 6..8)),
+    };
+    Ok(Instruction {
+        op,
+        operands: operands![Operand::Reg(rd), Operand::Reg(rn), arg],
+        set_flags: true,
+        ..Default::default()
+    })
+}
+
+/// MOV, CMP, ADD and SUB with an 8-bit immediate
+fn thumb_data_proc_imm(instr: u32) -> DisasmResult<Instruction> {
+    let rd = low_reg(instr, 8);
+    let imm = Operand::Imm(bits(instr, 0..7));
+    let (op, operands) = match bits(instr, 11..12) {
+        0b00 => (Op::MOV, operands![Operand::Reg(rd), imm]),
+        0b01 => (Op::CMP, operands![Operand::Reg(rd), imm]),
+        0b10 => (Op::ADD, operands![Operand::Reg(rd), Operand::Reg(rd), imm]),
+        _ => (Op::SUB, operands![Operand::Reg(rd), Operand::Reg(rd), imm]),
+    };
+    Ok(Instruction { op, operands, set_flags: true, ..Default::default() })
+}
+
+/// Data-processing instructions operating on a pair of low registers. These are all decoded into
+/// the equivalent 3 operand ARM forms, e.g. "ANDS r0, r1" becomes "ANDS r0, r0, r1"
+fn thumb_data_proc_reg(instr: u32) -> DisasmResult<Instruction> {
+    let rdn = Operand::Reg(low_reg(instr, 0));
+    let rm = Operand::Reg(low_reg(instr, 3));
+    let (op, operands) = match bits(instr, 6..9) {
+        0b0000 => (Op::AND, operands![rdn, rdn, rm]),
+        0b0001 => (Op::EOR, operands![rdn, rdn, rm]),
+        0b0010 => (Op::LSL, operands![rdn, rdn, rm]),
+        0b0011 => (Op::LSR, operands![rdn, rdn, rm]),
+        0b0100 => (Op::ASR, operands![rdn, rdn, rm]),
+        0b0101 => (Op::ADC, operands![rdn, rdn, rm]),
+        0b0110 => (Op::SBC, operands![rdn, rdn, rm]),
+        0b0111 => (Op::ROR, operands![rdn, rdn, rm]),
+        0b1000 => (Op::TST, operands![rdn, rm]),
+        // NEG
+        0b1001 => (Op::RSB, operands![rdn, rm, Operand::Imm(0)]),
+        0b1010 => (Op::CMP, operands![rdn, rm]),
+        0b1011 => (Op::CMN, operands![rdn, rm]),
+        0b1100 => (Op::ORR, operands![rdn, rdn, rm]),
+        0b1101 => (Op::MUL, operands![rdn, rm, rdn]),
+        0b1110 => (Op::BIC, operands![rdn, rdn, rm]),
+        _ => (Op::MVN, operands![rdn, rm]),
+    };
+    Ok(Instruction { op, operands, set_flags: true, ..Default::default() })
+}
+
+/// ADD, CMP and MOV on the full register set, and BX/BLX (register)
+fn thumb_hi_reg(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
+    let rdn = REG_MAP[((bit(instr, 7) << 3) | bits(instr, 0..2)) as usize];
+    let rm = REG_MAP[bits(instr, 3..6) as usize];
+    let (rdn, rm) = (Operand::Reg(rdn), Operand::Reg(rm));
+    let instruction = match bits(instr, 8..9) {
+        0b00 => Instruction {
+            op: Op::ADD,
+            operands: operands![rdn, rdn, rm],
+            ..Default::default()
+        },
+        0b01 => Instruction {
+            op: Op::CMP,
+            operands: operands![rdn, rm],
+            set_flags: true,
+            ..Default::default()
+        },
+        0b10 => Instruction {
+            op: Op::MOV,
+            operands: operands![rdn, rm],
+            ..Default::default()
+        },
+        _ => {
+            let op = match bit(instr, 7) {
+                0 => Op::BX,
+                _ if arch < Arch::ARMv5TE => {
+                    return Err(DisasmError::unsupported(instr, arch));
+                }
+                _ => Op::BLX,
+            };
+            Instruction { op, operands: operands![rm], ..Default::default() }
+        }
+    };
+    Ok(instruction)
+}
+
+/// Builds a load/store instruction using offset addressing
+fn load_store(op: Op, rt: Register, rn: Register, offset: Offset) -> Instruction {
+    let addr = Address { base: rn, mode: AddrMode::Offset };
+    Instruction {
+        op,
+        operands: operands![Operand::Reg(rt), Operand::Addr(addr)],
+        extra: Some(ExtraOperand::from(offset)),
+        ..Default::default()
+    }
+}
+
+/// PC-relative LDR
+fn thumb_load_literal(instr: u32) -> DisasmResult<Instruction> {
+    let rt = low_reg(instr, 8);
+    let offset = Offset::imm(bits(instr, 0..7) << 2, true);
+    Ok(load_store(Op::LDR, rt, Register::PC, offset))
+}
+
+/// Loads and stores with a register offset
+fn thumb_load_store_reg(instr: u32) -> DisasmResult<Instruction> {
+    let op = match bits(instr, 9..11) {
+        0b000 => Op::STR,
+        0b001 => Op::STRH,
+        0b010 => Op::STRB,
+        0b011 => Op::LDRSB,
+        0b100 => Op::LDR,
+        0b101 => Op::LDRH,
+        0b110 => Op::LDRB,
+        _ => Op::LDRSH,
+    };
+    let offset = Offset::reg(low_reg(instr, 6), None, true);
+    Ok(load_store(op, low_reg(instr, 0), low_reg(instr, 3), offset))
+}
+
+/// Word and byte loads and stores with a 5-bit immediate offset
+fn thumb_load_store_imm(instr: u32) -> DisasmResult<Instruction> {
+    let imm5 = bits(instr, 6..10);
+    let (op, imm) = match bits(instr, 11..12) {
+        0b00 => (Op::STR, imm5 << 2),
+        0b01 => (Op::LDR, imm5 << 2),
+        0b10 => (Op::STRB, imm5),
+        _ => (Op::LDRB, imm5),
+    };
+    let offset = Offset::imm(imm, true);
+    Ok(load_store(op, low_reg(instr, 0), low_reg(instr, 3), offset))
+}
+
+/// Halfword loads and stores with a 5-bit immediate offset
+fn thumb_load_store_halfword(instr: u32) -> DisasmResult<Instruction> {
+    let op = match bit(instr, 11) {
+        0 => Op::STRH,
+        _ => Op::LDRH,
+    };
+    let offset = Offset::imm(bits(instr, 6..10) << 1, true);
+    Ok(load_store(op, low_reg(instr, 0), low_reg(instr, 3), offset))
+}
+
+/// SP-relative loads and stores
+fn thumb_load_store_sp(instr: u32) -> DisasmResult<Instruction> {
+    let op = match bit(instr, 11) {
+        0 => Op::STR,
+        _ => Op::LDR,
+    };
+    let offset = Offset::imm(bits(instr, 0..7) << 2, true);
+    Ok(load_store(op, low_reg(instr, 8), Register::SP, offset))
+}
+
+/// ADR, and ADD with SP as the base
+fn thumb_add_pc_sp(instr: u32) -> DisasmResult<Instruction> {
+    let rd = Operand::Reg(low_reg(instr, 8));
+    let imm = Operand::Imm(bits(instr, 0..7) << 2);
+    let (op, operands) = match bit(instr, 11) {
+        0 => (Op::ADR, operands![rd, imm]),
+        _ => (Op::ADD, operands![rd, Operand::Reg(Register::SP), imm]),
+    };
+    Ok(Instruction { op, operands, ..Default::default() })
+}
+
+/// Miscellaneous instructions: SP adjustment, PUSH/POP and BKPT
+fn thumb_misc(instr: u32, arch: Arch) -> DisasmResult<Instruction> {
+    let sp = Operand::Reg(Register::SP);
+    let instruction = match bits(instr, 8..11) {
+        0b0000 => {
+            let op = match bit(instr, 7) {
+                0 => Op::ADD,
+                _ => Op::SUB,
+            };
+            let imm = Operand::Imm(bits(instr, 0..6) << 2);
+            Instruction { op, operands: operands![sp, sp, imm], ..Default::default() }
+        }
+        0b0100 | 0b0101 => {
+            // The R bit adds LR to the list
+            let regs = bits(instr, 0..7) | (bit(instr, 8) << 14);
+            let list = Operand::RegList(RegList::new(regs as u16));
+            let push = Instruction {
+                op: Op::PUSH,
+                operands: operands![list],
+                ..Default::default()
+            };
+            return check_predictable(instr, push, &[(regs == 0, Unpredictable::EmptyRegList)]);
+        }
+        0b1100 | 0b1101 => {
+            // The R bit adds PC to the list
+            let regs = bits(instr, 0..7) | (bit(instr, 8) << 15);
+            let list = Operand::RegList(RegList::new(regs as u16));
+            let pop = Instruction { op: Op::POP, operands: operands![list], ..Default::default() };
+            return check_predictable(instr, pop, &[(regs == 0, Unpredictable::EmptyRegList)]);
+        }
+        0b1110 if arch < Arch::ARMv5TE => {
+            return Err(DisasmError::unsupported(instr, arch));
+        }
+        0b1110 => Instruction {
+            op: Op::BKPT,
+            operands: operands![Operand::Imm(bits(instr, 0..7))],
+            ..Default::default()
+        },
+        _ => {
+            return Err(DisasmError::undefined(instr));
+        }
+    };
+    Ok(instruction)
+}
+
+/// LDMIA and STMIA
+fn thumb_block_data_transfer(instr: u32) -> DisasmResult<Instruction> {
+    let rn = low_reg(instr, 8);
+    let list = RegList::new(bits(instr, 0..7) as u16);
+    let (op, base) = match bit(instr, 11) {
+        0 => (Op::STMIA, Operand::RegWb(rn)),
+        // There is no writeback if the base register is loaded
+        _ if list.regs.contains(rn) => (Op::LDMIA, Operand::Reg(rn)),
+        _ => (Op::LDMIA, Operand::RegWb(rn)),
+    };
+    let decoded = Instruction {
+        op,
+        operands: operands![base, Operand::RegList(list)],
+        ..Default::default()
+    };
+    check_predictable(instr, decoded, &[(list.regs.is_empty(), Unpredictable::EmptyRegList)])
+}
+
+/// Conditional branch and SWI
+fn thumb_cond_branch(instr: u32) -> DisasmResult<Instruction> {
+    let imm8 = bits(instr, 0..7);
+    match bits(instr, 8..11) {
+        0b1110 => Err(DisasmError::undefined(instr)),
+        0b1111 => Ok(Instruction {
+            op: Op::SVC,
+            operands: operands![Operand::Imm(imm8)],
+            ..Default::default()
+        }),
+        cond => Ok(Instruction {
+            cond: COND_MAP[cond as usize],
+            op: Op::B,
+            operands: operands![Operand::Imm(sign_extend(imm8 << 1, 9))],
+            ..Default::default()
+        }),
+    }
+}
+
+/// Unconditional branch
+fn thumb_branch(instr: u32) -> DisasmResult<Instruction> {
+    let offset = sign_extend(bits(instr, 0..10) << 1, 12);
+    Ok(Instruction {
+        op: Op::B,
+        operands: operands![Operand::Imm(offset)],
+        ..Default::default()
+    })
+}
@@ -0,0 +1,1138 @@
+use crate::disasm::{disassemble_arm_at, Arch, DisasmError, Unpredictable, PC_LA_ARM};
+use crate::ir::{
+    AddrMode, Address, Cond, ExtraOperand, ExtraValue, ImmShift, Instruction, Offset, OffsetValue,
+    Op, Operand, Register, Shift, ShiftOp, StatusReg,
+};
+use crate::operands;
+use std::error::Error;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The operation has no ARM encoding in ARMv4T/ARMv5TE
+    UnsupportedOp { op: Op },
+    /// The operands (or the extra operand) don't match any form of the operation
+    InvalidOperands { op: Op },
+    /// The condition can't be used with the operation. The unconditional instructions must be AL,
+    /// and NV can't be used with any others
+    InvalidCondition { op: Op, cond: Cond },
+    /// An immediate which doesn't fit in its field, including misaligned branch offsets
+    ImmediateOutOfRange { op: Op, imm: u32 },
+    /// A data-processing or MSR immediate which isn't an 8-bit value rotated right by an even
+    /// amount
+    InvalidModifiedImm { op: Op, imm: u32 },
+    /// An immediate shift amount outside the range of the shift type, e.g. LSL #32 or LSR #0
+    ShiftOutOfRange { op: Op, shift: ImmShift },
+    /// A register-shifted register on an operation other than data-processing
+    RegisterShift { op: Op },
+    /// The instruction encodes, but its behaviour is UNPREDICTABLE. See [validate_arm_at]
+    Unpredictable { op: Op, reason: Unpredictable },
+    /// The encoding doesn't decode on the architecture it was validated for, e.g. an ARMv5TE-only
+    /// instruction on ARMv4T. See [validate_arm_at]
+    Undecodable { op: Op, error: DisasmError },
+    /// A Target operand, which can only be encoded relative to the address of the instruction. See
+    /// [encode_arm_at]
+    UnresolvedTarget { op: Op },
+}
+
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedOp { op } => write!(f, "no ARM encoding for {}", op),
+            Self::InvalidOperands { op } => write!(f, "invalid operands for {}", op),
+            Self::InvalidCondition { op, cond } => {
+                write!(f, "{} can't be encoded with condition {:?}", op, cond)
+            }
+            Self::ImmediateOutOfRange { op, imm } => {
+                write!(f, "immediate can't be encoded in {}: {:#x}", op, imm)
+            }
+            Self::InvalidModifiedImm { op, imm } => write!(
+                f,
+                "immediate {:#x} can't be encoded in {}, as it isn't an 8-bit value rotated right \
+                 by an even amount",
+                imm, op
+            ),
+            Self::ShiftOutOfRange { op, shift } => {
+                let range = match shift.op {
+                    ShiftOp::LSL => "0-31",
+                    ShiftOp::ROR => "1-31",
+                    _ => "1-32",
+                };
+                write!(
+                    f,
+                    "{:?} #{} is out of range in {}, which takes #{}",
+                    shift.op, shift.imm, op, range
+                )
+            }
+            Self::RegisterShift { op } => {
+                write!(f, "{} can't take a register shifted by a register", op)
+            }
+            Self::Unpredictable { op, reason } => write!(f, "{} is UNPREDICTABLE: {}", op, reason),
+            Self::Undecodable { op, error } => write!(f, "{} doesn't decode: {}", op, error),
+            Self::UnresolvedTarget { op } => {
+                write!(f, "{} has an absolute target, which needs its address", op)
+            }
+        }
+    }
+}
+
+impl Error for EncodeError {}
+
+type EncodeResult<T> = Result<T, EncodeError>;
+
+/// Encode an instruction as an ARM machine word. This is the inverse of `disassemble_arm`, and
+/// accepts every form that it produces: decoding the result gives back the same instruction.
+/// Instructions with Target operands must be encoded with [encode_arm_at] instead
+pub fn encode_arm(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    if instr
+        .operands
+        .iter()
+        .any(|o| matches!(o, Operand::Target(_)))
+    {
+        return Err(EncodeError::UnresolvedTarget { op });
+    }
+    if let Some(ExtraOperand::Shift(Shift { value: ExtraValue::Reg(_), .. })) = instr.extra {
+        if !is_data_proc(op) {
+            return Err(EncodeError::RegisterShift { op });
+        }
+    }
+    let unconditional = match (op, instr.operands.as_slice()) {
+        (Op::BLX, [Operand::Imm(_)]) => true,
+        (op, _) => matches!(
+            op,
+            Op::PLD | Op::CDP2 | Op::MCR2 | Op::MRC2 | Op::LDC2 | Op::LDC2L | Op::STC2 | Op::STC2L
+        ),
+    };
+    let cond = match (instr.cond, unconditional) {
+        (Cond::AL | Cond::NV, true) => 0b1111,
+        (cond, true) | (cond @ Cond::NV, false) => {
+            return Err(EncodeError::InvalidCondition { op, cond });
+        }
+        (cond, false) => cond as u32,
+    };
+    let encoding = match op {
+        _ if is_data_proc(op) => arm_data_proc(instr)?,
+        Op::MRS | Op::MSR => arm_status_reg(instr)?,
+        Op::NOP => arm_nop(instr)?,
+        Op::BX | Op::CLZ | Op::QADD | Op::QSUB | Op::QDADD | Op::QDSUB | Op::BKPT => {
+            arm_misc(instr)?
+        }
+        Op::BLX => match instr.operands.as_slice() {
+            [Operand::Imm(_)] => arm_branch(instr)?,
+            _ => arm_misc(instr)?,
+        },
+        Op::MUL | Op::MLA | Op::UMULL | Op::UMLAL | Op::SMULL | Op::SMLAL => arm_mult(instr)?,
+        Op::SMLABB
+        | Op::SMLABT
+        | Op::SMLATB
+        | Op::SMLATT
+        | Op::SMLAWB
+        | Op::SMLAWT
+        | Op::SMULWB
+        | Op::SMULWT
+        | Op::SMLALBB
+        | Op::SMLALBT
+        | Op::SMLALTB
+        | Op::SMLALTT
+        | Op::SMULBB
+        | Op::SMULBT
+        | Op::SMULTB
+        | Op::SMULTT => arm_halfword_mult(instr)?,
+        Op::SWP | Op::SWPB => arm_sync(instr)?,
+        Op::LDR
+        | Op::STR
+        | Op::LDRB
+        | Op::STRB
+        | Op::LDRT
+        | Op::STRT
+        | Op::LDRBT
+        | Op::STRBT
+        | Op::PLD => arm_load_store(instr)?,
+        Op::LDRH | Op::STRH | Op::LDRSB | Op::LDRSH | Op::LDRD | Op::STRD => {
+            arm_extra_load_store(instr)?
+        }
+        Op::LDM
+        | Op::LDMIA
+        | Op::LDMIB
+        | Op::LDMDA
+        | Op::LDMDB
+        | Op::STM
+        | Op::STMIA
+        | Op::STMIB
+        | Op::STMDA
+        | Op::STMDB
+        | Op::PUSH
+        | Op::POP => arm_block_data_transfer(instr)?,
+        Op::B | Op::BL => arm_branch(instr)?,
+        Op::SVC
+        | Op::CDP
+        | Op::CDP2
+        | Op::MCR
+        | Op::MCR2
+        | Op::MRC
+        | Op::MRC2
+        | Op::MCRR
+        | Op::MRRC
+        | Op::LDC
+        | Op::LDC2
+        | Op::LDCL
+        | Op::LDC2L
+        | Op::STC
+        | Op::STC2
+        | Op::STCL
+        | Op::STC2L => arm_coprocessor(instr)?,
+        _ => {
+            return Err(EncodeError::UnsupportedOp { op });
+        }
+    };
+    Ok((cond << 28) | encoding)
+}
+
+/// Checks that an instruction located at `addr` can be encoded, and that its encoding decodes on
+/// `arch` without being UNPREDICTABLE, e.g. because of a PC operand or writeback to a transferred
+/// register. Returns the encoding
+pub fn validate_arm_at(addr: u32, instr: &Instruction, arch: Arch) -> EncodeResult<u32> {
+    let encoding = encode_arm_at(addr, instr)?;
+    match disassemble_arm_at(addr, encoding, arch) {
+        Ok(_) => Ok(encoding),
+        Err(DisasmError::Unpredictable { reason, .. }) => {
+            Err(EncodeError::Unpredictable { op: instr.op, reason })
+        }
+        Err(error) => Err(EncodeError::Undecodable { op: instr.op, error }),
+    }
+}
+
+/// Encode an instruction located at `addr`. Target operands are converted back to PC-relative
+/// offsets, undoing `disassemble_arm_at`, and ADR is encoded as an ADD or SUB from PC
+pub fn encode_arm_at(addr: u32, instr: &Instruction) -> EncodeResult<u32> {
+    encode_arm(&relative_to_pc(instr, addr.wrapping_add(PC_LA_ARM))?)
+}
+
+/// The data-processing operations, which are the only ones whose operand can be a register shifted
+/// by a register
+fn is_data_proc(op: Op) -> bool {
+    matches!(
+        op,
+        Op::AND
+            | Op::EOR
+            | Op::SUB
+            | Op::RSB
+            | Op::ADD
+            | Op::ADC
+            | Op::SBC
+            | Op::RSC
+            | Op::TST
+            | Op::TEQ
+            | Op::CMP
+            | Op::CMN
+            | Op::ORR
+            | Op::MOV
+            | Op::LSL
+            | Op::LSR
+            | Op::ASR
+            | Op::ROR
+            | Op::RRX
+            | Op::BIC
+            | Op::MVN
+    )
+}
+
+/// Replaces Target operands with offsets from `pc`, the value read from PC by the instruction
+fn relative_to_pc(instr: &Instruction, pc: u32) -> EncodeResult<Instruction> {
+    let mut instr = *instr;
+    match (instr.op, instr.operands.as_slice()) {
+        (Op::B | Op::BL | Op::BLX, &[Operand::Target(target)]) => {
+            instr.operands[0] = Operand::Imm(target.wrapping_sub(pc));
+        }
+        (Op::ADR, &[rd, Operand::Target(target)]) => {
+            let offset = target.wrapping_sub(pc);
+            let (op, imm) = match encode_modified_imm(offset) {
+                Some(_) => (Op::ADD, offset),
+                None => (Op::SUB, offset.wrapping_neg()),
+            };
+            instr.op = op;
+            instr.operands = operands![rd, Operand::Reg(Register::PC), Operand::Imm(imm)];
+        }
+        _ => {
+            let literal = instr
+                .operands
+                .iter()
+                .enumerate()
+                .find_map(|(i, o)| match *o {
+                    Operand::Target(target) => Some((i, target)),
+                    _ => None,
+                });
+            if let Some((i, target)) = literal {
+                if instr.extra.is_some() {
+                    return Err(EncodeError::InvalidOperands { op: instr.op });
+                }
+                let offset = target.wrapping_sub(pc) as i32;
+                let base = Address { base: Register::PC, mode: AddrMode::Offset };
+                instr.operands[i] = Operand::Addr(base);
+                instr.extra = Some(Offset::imm(offset.unsigned_abs(), offset >= 0).into());
+            }
+        }
+    }
+    Ok(instr)
+}
+
+/// Encode a register field. FLAGS is the only register without an encoding
+fn reg(op: Op, reg: Register) -> EncodeResult<u32> {
+    match reg {
+        Register::FLAGS => Err(EncodeError::InvalidOperands { op }),
+        _ => Ok(reg as u32),
+    }
+}
+
+/// Encode the operands of an instruction which only takes registers
+fn reg_operands(instr: &Instruction) -> EncodeResult<Vec<u32>> {
+    let op = instr.op;
+    instr
+        .operands
+        .iter()
+        .map(|o| match *o {
+            Operand::Reg(r) => reg(op, r),
+            _ => Err(EncodeError::InvalidOperands { op }),
+        })
+        .collect()
+}
+
+/// Checks that `value` fits in an unsigned field `width` bits wide
+fn field(op: Op, value: u32, width: u32) -> EncodeResult<u32> {
+    match value >> width {
+        0 => Ok(value),
+        _ => Err(EncodeError::ImmediateOutOfRange { op, imm: value }),
+    }
+}
+
+/// The inverse of ARMExpandImm(): finds the 12-bit encoding of a value as an 8-bit immediate
+/// rotated right by twice the 4-bit rotation. Uses the smallest rotation, as assemblers do
+fn encode_modified_imm(imm: u32) -> Option<u32> {
+    (0..16).find_map(|rot| {
+        let val = imm.rotate_left(2 * rot);
+        (val <= 0xFF).then_some((rot << 8) | val)
+    })
+}
+
+fn shift_type(op: Op, shift_op: ShiftOp) -> EncodeResult<u32> {
+    match shift_op {
+        ShiftOp::LSL => Ok(0b00),
+        ShiftOp::LSR => Ok(0b01),
+        ShiftOp::ASR => Ok(0b10),
+        ShiftOp::ROR => Ok(0b11),
+        ShiftOp::RRX => Err(EncodeError::InvalidOperands { op }),
+    }
+}
+
+/// The inverse of DecodeImmShift(), giving the shift type and imm5 fields in place
+fn encode_imm_shift(op: Op, shift: ImmShift) -> EncodeResult<u32> {
+    let (shift_type, imm5) = match (shift.op, shift.imm) {
+        (ShiftOp::LSL, imm @ 0..=31) => (0b00, imm),
+        (ShiftOp::LSR, imm @ 1..=32) => (0b01, imm % 32),
+        (ShiftOp::ASR, imm @ 1..=32) => (0b10, imm % 32),
+        (ShiftOp::ROR, imm @ 1..=31) => (0b11, imm),
+        (ShiftOp::RRX, _) => (0b11, 0),
+        (_, _) => {
+            return Err(EncodeError::ShiftOutOfRange { op, shift });
+        }
+    };
+    Ok((imm5 << 7) | (shift_type << 5))
+}
+
+/// Encode the flexible second operand of a data-processing instruction: a modified immediate, or a
+/// register shifted by an immediate or register
+fn shifter_operand(op: Op, src: Operand, extra: Option<ExtraOperand>) -> EncodeResult<u32> {
+    match (src, extra) {
+        (Operand::Imm(imm), None) => match encode_modified_imm(imm) {
+            Some(imm12) => Ok((1 << 25) | imm12),
+            None => Err(EncodeError::InvalidModifiedImm { op, imm }),
+        },
+        // An 8-bit value with an explicit rotation, as decoded from a non-canonical encoding
+        (
+            Operand::Imm(imm8 @ 0..=0xFF),
+            Some(ExtraOperand::Shift(Shift { op: ShiftOp::ROR, value: ExtraValue::Imm(rot) })),
+        ) if rot % 2 == 0 && rot < 32 => Ok((1 << 25) | ((rot / 2) << 8) | imm8),
+        (Operand::Reg(rm), None) => reg(op, rm),
+        (Operand::Reg(rm), Some(ExtraOperand::Shift(Shift { op: shift_op, value }))) => match value
+        {
+            ExtraValue::Imm(imm) => {
+                Ok(encode_imm_shift(op, ImmShift { op: shift_op, imm })? | reg(op, rm)?)
+            }
+            ExtraValue::Reg(rs) => {
+                Ok((reg(op, rs)? << 8) | (shift_type(op, shift_op)? << 5) | (1 << 4) | reg(op, rm)?)
+            }
+        },
+        _ => Err(EncodeError::InvalidOperands { op }),
+    }
+}
+
+/// Data-processing instructions, including the shift instructions which are encoded as MOV
+fn arm_data_proc(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    let invalid = EncodeError::InvalidOperands { op };
+    let (opcode, set_flags) = match op {
+        Op::AND => (0b0000, instr.set_flags),
+        Op::EOR => (0b0001, instr.set_flags),
+        Op::SUB => (0b0010, instr.set_flags),
+        Op::RSB => (0b0011, instr.set_flags),
+        Op::ADD => (0b0100, instr.set_flags),
+        Op::ADC => (0b0101, instr.set_flags),
+        Op::SBC => (0b0110, instr.set_flags),
+        Op::RSC => (0b0111, instr.set_flags),
+        // The comparisons always set the flags, the encodings without S are the misc instructions
+        Op::TST => (0b1000, true),
+        Op::TEQ => (0b1001, true),
+        Op::CMP => (0b1010, true),
+        Op::CMN => (0b1011, true),
+        Op::ORR => (0b1100, instr.set_flags),
+        Op::BIC => (0b1110, instr.set_flags),
+        Op::MVN => (0b1111, instr.set_flags),
+        _ => (0b1101, instr.set_flags),
+    };
+
+    let (rd, rn, operand2) = match (op, instr.operands.as_slice()) {
+        (Op::LSL | Op::LSR | Op::ASR | Op::ROR, &[Operand::Reg(rd), Operand::Reg(rm), amount]) => {
+            if instr.extra.is_some() {
+                return Err(invalid);
+            }
+            let shift_op = match op {
+                Op::LSL => ShiftOp::LSL,
+                Op::LSR => ShiftOp::LSR,
+                Op::ASR => ShiftOp::ASR,
+                _ => ShiftOp::ROR,
+            };
+            let shift = match amount {
+                Operand::Imm(imm) => Shift::imm(shift_op, imm),
+                Operand::Reg(rs) => Shift::reg(shift_op, rs),
+                _ => {
+                    return Err(invalid);
+                }
+            };
+            (rd, Register::R0, shifter_operand(op, Operand::Reg(rm), Some(shift.into()))?)
+        }
+        (Op::RRX, &[Operand::Reg(rd), Operand::Reg(rm)]) => {
+            if instr.extra.is_some() {
+                return Err(invalid);
+            }
+            let shift = ImmShift { op: ShiftOp::RRX, imm: 1 };
+            (rd, Register::R0, shifter_operand(op, Operand::Reg(rm), Some(shift.into()))?)
+        }
+        (Op::MOV | Op::MVN, &[Operand::Reg(rd), src]) => {
+            (rd, Register::R0, shifter_operand(op, src, instr.extra)?)
+        }
+        (Op::TST | Op::TEQ | Op::CMP | Op::CMN, &[Operand::Reg(rn), src]) => {
+            (Register::R0, rn, shifter_operand(op, src, instr.extra)?)
+        }
+        (
+            Op::AND
+            | Op::EOR
+            | Op::SUB
+            | Op::RSB
+            | Op::ADD
+            | Op::ADC
+            | Op::SBC
+            | Op::RSC
+            | Op::ORR
+            | Op::BIC,
+            &[Operand::Reg(rd), Operand::Reg(rn), src],
+        ) => (rd, rn, shifter_operand(op, src, instr.extra)?),
+        _ => {
+            return Err(invalid);
+        }
+    };
+    Ok((opcode << 21)
+        | ((set_flags as u32) << 20)
+        | (reg(op, rn)? << 16)
+        | (reg(op, rd)? << 12)
+        | operand2)
+}
+
+fn status_reg_bit(psr: StatusReg) -> u32 {
+    match psr {
+        StatusReg::CPSR => 0,
+        StatusReg::SPSR => 1 << 22,
+    }
+}
+
+/// MRS, and MSR from a register or an immediate
+fn arm_status_reg(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    match (op, instr.operands.as_slice()) {
+        (Op::MRS, &[Operand::Reg(rd), Operand::Psr(psr)]) => {
+            Ok(0x010F_0000 | status_reg_bit(psr) | (reg(op, rd)? << 12))
+        }
+        (Op::MSR, &[Operand::PsrFields(psr, mask), Operand::Reg(rn)]) => {
+            Ok(0x0120_F000 | status_reg_bit(psr) | (field(op, mask, 4)? << 16) | reg(op, rn)?)
+        }
+        (Op::MSR, &[Operand::PsrFields(psr, mask), Operand::Imm(imm)]) => {
+            let imm12 =
+                encode_modified_imm(imm).ok_or(EncodeError::InvalidModifiedImm { op, imm })?;
+            Ok(0x0320_F000 | status_reg_bit(psr) | (field(op, mask, 4)? << 16) | imm12)
+        }
+        _ => Err(EncodeError::InvalidOperands { op }),
+    }
+}
+
+/// NOP, encoded as an MSR (immediate) to no fields of CPSR, which has no effect. The other hints
+/// in that space (YIELD, WFE, WFI, SEV and DBG) were only added in ARMv6K
+fn arm_nop(instr: &Instruction) -> EncodeResult<u32> {
+    match instr.operands.as_slice() {
+        [] => Ok(0x0320_F000),
+        _ => Err(EncodeError::InvalidOperands { op: instr.op }),
+    }
+}
+
+/// BX, BLX (register), CLZ, the saturating adds and subtracts, and BKPT
+fn arm_misc(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    match (op, instr.operands.as_slice()) {
+        (Op::BX, &[Operand::Reg(rm)]) => Ok(0x012F_FF10 | reg(op, rm)?),
+        (Op::BLX, &[Operand::Reg(rm)]) => Ok(0x012F_FF30 | reg(op, rm)?),
+        (Op::CLZ, &[Operand::Reg(rd), Operand::Reg(rm)]) => {
+            Ok(0x016F_0F10 | (reg(op, rd)? << 12) | reg(op, rm)?)
+        }
+        (
+            Op::QADD | Op::QSUB | Op::QDADD | Op::QDSUB,
+            &[Operand::Reg(rd), Operand::Reg(rm), Operand::Reg(rn)],
+        ) => {
+            let opc = match op {
+                Op::QADD => 0b00,
+                Op::QSUB => 0b01,
+                Op::QDADD => 0b10,
+                _ => 0b11,
+            };
+            Ok(0x0100_0050
+                | (opc << 21)
+                | (reg(op, rn)? << 16)
+                | (reg(op, rd)? << 12)
+                | reg(op, rm)?)
+        }
+        (Op::BKPT, &[Operand::Imm(imm)]) => {
+            let imm16 = field(op, imm, 16)?;
+            Ok(0x0120_0070 | ((imm16 >> 4) << 8) | (imm16 & 0xF))
+        }
+        _ => Err(EncodeError::InvalidOperands { op }),
+    }
+}
+
+/// MUL/MLA and the long multiplies, which take RdLo then RdHi
+fn arm_mult(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    let regs = reg_operands(instr)?;
+    // Fields in the order Rd/RdHi, Rn/RdLo, Rs, Rm
+    let (opc, fields) = match (op, regs.as_slice()) {
+        (Op::MUL, &[rd, rm, rs]) => (0b000, [rd, 0, rs, rm]),
+        (Op::MLA, &[rd, rm, rs, rn]) => (0b001, [rd, rn, rs, rm]),
+        (Op::UMULL, &[lo, hi, rm, rs]) => (0b100, [hi, lo, rs, rm]),
+        (Op::UMLAL, &[lo, hi, rm, rs]) => (0b101, [hi, lo, rs, rm]),
+        (Op::SMULL, &[lo, hi, rm, rs]) => (0b110, [hi, lo, rs, rm]),
+        (Op::SMLAL, &[lo, hi, rm, rs]) => (0b111, [hi, lo, rs, rm]),
+        _ => {
+            return Err(EncodeError::InvalidOperands { op });
+        }
+    };
+    let [rd, rn, rs, rm] = fields;
+    Ok((opc << 21)
+        | ((instr.set_flags as u32) << 20)
+        | (rd << 16)
+        | (rn << 12)
+        | (rs << 8)
+        | 0b1001_0000
+        | rm)
+}
+
+/// The signed 16-bit multiplies
+fn arm_halfword_mult(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    let regs = reg_operands(instr)?;
+    // op1 selects the group, and bits 5 and 6 select the halves of the sources
+    let (op1, xy) = match op {
+        Op::SMLABB => (0b00, 0b00),
+        Op::SMLATB => (0b00, 0b01),
+        Op::SMLABT => (0b00, 0b10),
+        Op::SMLATT => (0b00, 0b11),
+        Op::SMLAWB => (0b01, 0b00),
+        Op::SMULWB => (0b01, 0b01),
+        Op::SMLAWT => (0b01, 0b10),
+        Op::SMULWT => (0b01, 0b11),
+        Op::SMLALBB => (0b10, 0b00),
+        Op::SMLALTB => (0b10, 0b01),
+        Op::SMLALBT => (0b10, 0b10),
+        Op::SMLALTT => (0b10, 0b11),
+        Op::SMULBB => (0b11, 0b00),
+        Op::SMULTB => (0b11, 0b01),
+        Op::SMULBT => (0b11, 0b10),
+        _ => (0b11, 0b11),
+    };
+    let accumulate = op1 == 0b00 || op1 == 0b10 || (op1 == 0b01 && xy & 0b01 == 0);
+    // Fields in the order Rd/RdHi, Ra/RdLo, Rm, Rn
+    let [rd, ra, rm, rn] = match (op1, accumulate, regs.as_slice()) {
+        (0b10, _, &[lo, hi, rn, rm]) => [hi, lo, rm, rn],
+        (_, true, &[rd, rn, rm, ra]) => [rd, ra, rm, rn],
+        (_, false, &[rd, rn, rm]) => [rd, 0, rm, rn],
+        _ => {
+            return Err(EncodeError::InvalidOperands { op });
+        }
+    };
+    Ok(0x0100_0080 | (op1 << 21) | (rd << 16) | (ra << 12) | (rm << 8) | (xy << 5) | rn)
+}
+
+/// SWP and SWPB
+fn arm_sync(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    match instr.operands.as_slice() {
+        &[Operand::Reg(rt), Operand::Reg(rt2), Operand::Addr(addr)]
+            if addr.mode == AddrMode::Offset && instr.extra.is_none() =>
+        {
+            let b = (op == Op::SWPB) as u32;
+            Ok(0x0100_0090
+                | (b << 22)
+                | (reg(op, addr.base)? << 16)
+                | (reg(op, rt)? << 12)
+                | reg(op, rt2)?)
+        }
+        _ => Err(EncodeError::InvalidOperands { op }),
+    }
+}
+
+/// The P and W bits for an addressing mode
+fn addressing_mode_bits(mode: AddrMode) -> u32 {
+    match mode {
+        AddrMode::Offset => 1 << 24,
+        AddrMode::PreIndex => (1 << 24) | (1 << 21),
+        AddrMode::PostIndex => 0,
+    }
+}
+
+/// The offset of a load/store, which is #0 if there is no extra operand
+fn load_store_offset(op: Op, extra: Option<ExtraOperand>) -> EncodeResult<Offset> {
+    match extra {
+        None => Ok(Offset::imm(0, true)),
+        Some(ExtraOperand::Offset(offset)) => Ok(offset),
+        Some(_) => Err(EncodeError::InvalidOperands { op }),
+    }
+}
+
+/// Word and unsigned byte loads and stores, and PLD
+fn arm_load_store(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    let (rt, addr) = match (op, instr.operands.as_slice()) {
+        (Op::PLD, &[Operand::Addr(addr)]) if addr.mode == AddrMode::Offset => (0b1111, addr),
+        (_, &[Operand::Reg(rt), Operand::Addr(addr)]) if op != Op::PLD => (reg(op, rt)?, addr),
+        _ => {
+            return Err(EncodeError::InvalidOperands { op });
+        }
+    };
+    let offset = load_store_offset(op, instr.extra)?;
+    let offset_bits = match offset.value {
+        OffsetValue::Imm(imm) => field(op, imm, 12)?,
+        OffsetValue::Reg { reg: rm, shift } => {
+            let shift = match shift {
+                Some(shift) => encode_imm_shift(op, shift)?,
+                None => 0,
+            };
+            (1 << 25) | shift | reg(op, rm)?
+        }
+    };
+    let (load, byte) = match op {
+        Op::STR | Op::STRT => (0, 0),
+        Op::LDR | Op::LDRT => (1, 0),
+        Op::STRB | Op::STRBT => (0, 1),
+        Op::LDRB | Op::LDRBT => (1, 1),
+        // PLD is encoded as a byte load to PC
+        _ => (1, 1),
+    };
+    // The unprivileged variants are post-indexed with W set
+    let mode = match op {
+        Op::LDRT | Op::STRT | Op::LDRBT | Op::STRBT => 1 << 21,
+        _ => addressing_mode_bits(addr.mode),
+    };
+    Ok((0b01 << 26)
+        | mode
+        | ((offset.add as u32) << 23)
+        | (byte << 22)
+        | (load << 20)
+        | (reg(op, addr.base)? << 16)
+        | (rt << 12)
+        | offset_bits)
+}
+
+/// Halfword, signed byte and doubleword loads and stores
+fn arm_extra_load_store(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    let invalid = EncodeError::InvalidOperands { op };
+    let (rt, addr) = match (op, instr.operands.as_slice()) {
+        (Op::LDRD | Op::STRD, &[Operand::Reg(rt), Operand::Reg(rt2), Operand::Addr(addr)]) => {
+            // The second register isn't encoded, it always follows the first
+            let rt = reg(op, rt)?;
+            if reg(op, rt2)? != (rt + 1) % 16 {
+                return Err(invalid);
+            }
+            (rt, addr)
+        }
+        (Op::LDRD | Op::STRD, _) => {
+            return Err(invalid);
+        }
+        (_, &[Operand::Reg(rt), Operand::Addr(addr)]) => (reg(op, rt)?, addr),
+        _ => {
+            return Err(invalid);
+        }
+    };
+    let (load, op2) = match op {
+        Op::STRH => (0, 0b01),
+        Op::LDRH => (1, 0b01),
+        Op::LDRD => (0, 0b10),
+        Op::LDRSB => (1, 0b10),
+        Op::STRD => (0, 0b11),
+        _ => (1, 0b11),
+    };
+    let offset = load_store_offset(op, instr.extra)?;
+    let offset_bits = match offset.value {
+        OffsetValue::Imm(imm) => {
+            let imm8 = field(op, imm, 8)?;
+            (1 << 22) | ((imm8 >> 4) << 8) | (imm8 & 0xF)
+        }
+        OffsetValue::Reg { reg: rm, shift: None } => reg(op, rm)?,
+        OffsetValue::Reg { .. } => {
+            return Err(invalid);
+        }
+    };
+    Ok(addressing_mode_bits(addr.mode)
+        | ((offset.add as u32) << 23)
+        | (load << 20)
+        | (reg(op, addr.base)? << 16)
+        | (rt << 12)
+        | (1 << 7)
+        | (op2 << 5)
+        | (1 << 4)
+        | offset_bits)
+}
+
+/// LDM/STM and the PUSH/POP aliases
+fn arm_block_data_transfer(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    let (base, list) = match (op, instr.operands.as_slice()) {
+        (Op::PUSH | Op::POP, &[Operand::RegList(list)]) => (Operand::RegWb(Register::SP), list),
+        (Op::PUSH | Op::POP, _) => {
+            return Err(EncodeError::InvalidOperands { op });
+        }
+        (_, &[base, Operand::RegList(list)]) => (base, list),
+        _ => {
+            return Err(EncodeError::InvalidOperands { op });
+        }
+    };
+    let (rn, writeback) = match base {
+        Operand::Reg(rn) => (rn, 0),
+        Operand::RegWb(rn) => (rn, 1),
+        _ => {
+            return Err(EncodeError::InvalidOperands { op });
+        }
+    };
+    // P and U bits select the addressing mode
+    let (pu, load) = match op {
+        Op::STMDA => (0b00, 0),
+        Op::LDMDA => (0b00, 1),
+        Op::STM | Op::STMIA => (0b01, 0),
+        Op::LDM | Op::LDMIA | Op::POP => (0b01, 1),
+        Op::STMDB | Op::PUSH => (0b10, 0),
+        Op::LDMDB => (0b10, 1),
+        Op::STMIB => (0b11, 0),
+        _ => (0b11, 1),
+    };
+    Ok((0b100 << 25)
+        | (pu << 23)
+        | ((list.user as u32) << 22)
+        | (writeback << 21)
+        | (load << 20)
+        | (reg(op, rn)? << 16)
+        | list.regs.regs)
+}
+
+/// B, BL and BLX (immediate), with the offset relative to PC. BLX can branch to any halfword, which
+/// is given by the H bit
+fn arm_branch(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    let &[Operand::Imm(offset)] = instr.operands.as_slice() else {
+        return Err(EncodeError::InvalidOperands { op });
+    };
+    let alignment = if op == Op::BLX { 0b01 } else { 0b11 };
+    // The offset is a sign extended 26-bit value
+    let signed = offset as i32;
+    if offset & alignment != 0 || !(-(1 << 25)..(1 << 25)).contains(&signed) {
+        return Err(EncodeError::ImmediateOutOfRange { op, imm: offset });
+    }
+    let h = match op {
+        Op::B => 0,
+        Op::BL => 1,
+        _ => (offset >> 1) & 1,
+    };
+    Ok((0b101 << 25) | (h << 24) | ((offset >> 2) & 0xFF_FFFF))
+}
+
+/// SVC and the coprocessor instructions. The "2" variants share the encodings of the base
+/// instructions, and are distinguished by the condition field alone
+fn arm_coprocessor(instr: &Instruction) -> EncodeResult<u32> {
+    let op = instr.op;
+    let invalid = EncodeError::InvalidOperands { op };
+    match (op, instr.operands.as_slice()) {
+        (Op::SVC, &[Operand::Imm(imm)]) => Ok(0x0F00_0000 | field(op, imm, 24)?),
+        (Op::CDP | Op::CDP2, &[coproc, opc1, crd, crn, crm, opc2]) => {
+            let (
+                Operand::Coproc(coproc),
+                Operand::CoprocOpcode(opc1),
+                Operand::CoprocReg(crd),
+                Operand::CoprocReg(crn),
+                Operand::CoprocReg(crm),
+                Operand::CoprocOpcode(opc2),
+            ) = (coproc, opc1, crd, crn, crm, opc2)
+            else {
+                return Err(invalid);
+            };
+            Ok(0x0E00_0000
+                | (field(op, opc1, 4)? << 20)
+                | (field(op, crn, 4)? << 16)
+                | (field(op, crd, 4)? << 12)
+                | (field(op, coproc, 4)? << 8)
+                | (field(op, opc2, 3)? << 5)
+                | field(op, crm, 4)?)
+        }
+        (Op::MCR | Op::MCR2 | Op::MRC | Op::MRC2, &[coproc, opc1, rt, crn, crm, opc2]) => {
+            let (
+                Operand::Coproc(coproc),
+                Operand::CoprocOpcode(opc1),
+                Operand::Reg(rt),
+                Operand::CoprocReg(crn),
+                Operand::CoprocReg(crm),
+                Operand::CoprocOpcode(opc2),
+            ) = (coproc, opc1, rt, crn, crm, opc2)
+            else {
+                return Err(invalid);
+            };
+            let load = matches!(op, Op::MRC | Op::MRC2) as u32;
+            Ok(0x0E00_0010
+                | (field(op, opc1, 3)? << 21)
+                | (load << 20)
+                | (field(op, crn, 4)? << 16)
+                | (reg(op, rt)? << 12)
+                | (field(op, coproc, 4)? << 8)
+                | (field(op, opc2, 3)? << 5)
+                | field(op, crm, 4)?)
+        }
+        (Op::MCRR | Op::MRRC, &[coproc, opc, rt, rt2, crm]) => {
+            let (
+                Operand::Coproc(coproc),
+                Operand::CoprocOpcode(opc),
+                Operand::Reg(rt),
+                Operand::Reg(rt2),
+                Operand::CoprocReg(crm),
+            ) = (coproc, opc, rt, rt2, crm)
+            else {
+                return Err(invalid);
+            };
+            let load = (op == Op::MRRC) as u32;
+            Ok(0x0C40_0000
+                | (load << 20)
+                | (reg(op, rt2)? << 16)
+                | (reg(op, rt)? << 12)
+                | (field(op, coproc, 4)? << 8)
+                | (field(op, opc, 4)? << 4)
+                | field(op, crm, 4)?)
+        }
+        (
+            Op::LDC | Op::LDC2 | Op::LDCL | Op::LDC2L | Op::STC | Op::STC2 | Op::STCL | Op::STC2L,
+            &[coproc, crd, addr, ref option @ ..],
+        ) => {
+            let (Operand::Coproc(coproc), Operand::CoprocReg(crd), Operand::Addr(addr)) =
+                (coproc, crd, addr)
+            else {
+                return Err(invalid);
+            };
+            let (mode, add, imm8) = match (option, instr.extra) {
+                // Unindexed, with P and W clear
+                (&[Operand::CoprocOption(option)], None) if addr.mode == AddrMode::Offset => {
+                    (0, true, field(op, option, 8)?)
+                }
+                (&[], extra) => {
+                    let offset = load_store_offset(op, extra)?;
+                    let OffsetValue::Imm(imm) = offset.value else {
+                        return Err(invalid);
+                    };
+                    // The offset is a word offset
+                    if imm % 4 != 0 {
+                        return Err(EncodeError::ImmediateOutOfRange { op, imm });
+                    }
+                    let mode = match addr.mode {
+                        AddrMode::PostIndex => 1 << 21,
+                        mode => addressing_mode_bits(mode),
+                    };
+                    (
+                        mode,
+                        offset.add,
+                        field(op, imm / 4, 8)
+                            .map_err(|_| EncodeError::ImmediateOutOfRange { op, imm })?,
+                    )
+                }
+                _ => {
+                    return Err(invalid);
+                }
+            };
+            let long = matches!(op, Op::LDCL | Op::LDC2L | Op::STCL | Op::STC2L) as u32;
+            let load = matches!(op, Op::LDC | Op::LDC2 | Op::LDCL | Op::LDC2L) as u32;
+            Ok((0b110 << 25)
+                | mode
+                | ((add as u32) << 23)
+                | (long << 22)
+                | (load << 20)
+                | (reg(op, addr.base)? << 16)
+                | (field(op, crd, 4)? << 12)
+                | (field(op, coproc, 4)? << 8)
+                | imm8)
+        }
+        _ => Err(invalid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operands;
+    use rand::{thread_rng, Rng};
+
+    use super::{encode_arm, encode_arm_at, validate_arm_at, EncodeError};
+    use crate::disasm::{disassemble_arm, disassemble_arm_at, Arch, DisasmError, Unpredictable::*};
+    use crate::ir::{
+        parsing::instruction, Cond, ImmShift, Instruction, Op, Operand, Register::*, ShiftOp,
+    };
+
+    fn encode_str(asm: &str) -> Result<u32, EncodeError> {
+        encode_arm(&instruction(asm).unwrap().1)
+    }
+
+    #[test]
+    fn test_encode_arm() {
+        // Encodings from arm-none-eabi-as
+        let cases = [
+            ("andeq r0, r1, r2, lsl #3", 0x0001_0182),
+            ("adds r4, r5, #4278190080", 0xE295_44FF),
+            ("mov r0, r1", 0xE1A0_0001),
+            ("mvn r2, r3, ror r4", 0xE1E0_2473),
+            ("cmp r1, #4", 0xE351_0004),
+            ("lsl r0, r1, #31", 0xE1A0_0F81),
+            ("lsr r0, r1, #32", 0xE1A0_0021),
+            ("asr r0, r1, r2", 0xE1A0_0251),
+            ("rrx r3, r4", 0xE1A0_3064),
+            ("add r0, r1, r2, rrx", 0xE081_0062),
+            ("movne r0, #1", 0x13A0_0001),
+            ("mrs r0, spsr", 0xE14F_0000),
+            ("msr cpsr_fc, r1", 0xE129_F001),
+            ("msr spsr_f, #4026531840", 0xE368_F20F),
+            ("bx lr", 0xE12F_FF1E),
+            ("blx r3", 0xE12F_FF33),
+            ("clz r0, r1", 0xE16F_0F11),
+            ("qdadd r0, r1, r2", 0xE142_0051),
+            ("bkpt #4660", 0xE121_2374),
+            ("mul r0, r1, r2", 0xE000_0291),
+            ("mlas r0, r1, r2, r3", 0xE030_3291),
+            ("umull r0, r1, r2, r3", 0xE081_0392),
+            ("smlalne r0, r1, r2, r3", 0x10E1_0392),
+            ("smlabt r0, r1, r2, r3", 0xE100_32C1),
+            ("smulwt r0, r1, r2", 0xE120_02E1),
+            ("smlaltb r0, r1, r2, r3", 0xE141_03A2),
+            ("swpb r0, r1, [r2]", 0xE142_0091),
+            ("ldr r0, [r1, #4]!", 0xE5B1_0004),
+            ("strb r0, [r1], -r2, asr #2", 0xE641_0142),
+            ("ldrt r0, [r1], #4", 0xE4B1_0004),
+            ("str r0, [r1, -r2]", 0xE701_0002),
+            ("ldrh r0, [r1, #-8]", 0xE151_00B8),
+            ("strd r2, r3, [r4], r5", 0xE084_20F5),
+            ("ldrsb r0, [r1, #255]!", 0xE1F1_0FDF),
+            ("ldmia r0!, {r1-r3}", 0xE8B0_000E),
+            ("stmdb sp!, {r4-r11, lr}", 0xE92D_4FF0),
+            ("push {r4, lr}", 0xE92D_4010),
+            ("pop {r4, pc}", 0xE8BD_8010),
+            ("ldmib r0, {r1, r2}^", 0xE9D0_0006),
+            ("svc #1193046", 0xEF12_3456),
+            ("cdp p1, 2, c3, c4, c5, 6", 0xEE24_31C5),
+            ("mcr p15, 0, r0, c1, c0, 0", 0xEE01_0F10),
+            ("mrc2 p14, 1, r2, c3, c4, 5", 0xFE33_2EB4),
+            ("mcrr p5, 3, r0, r1, c2", 0xEC41_0532),
+            ("ldc p1, c2, [r3, #-8]!", 0xED33_2102),
+            ("stcl p1, c2, [r3], #16", 0xECE3_2104),
+            ("ldc2 p1, c2, [r3], {8}", 0xFC93_2108),
+            ("pld [r0, #-12]", 0xF550_F00C),
+            ("pld [r0, r1, lsl #2]", 0xF7D0_F101),
+        ];
+        for (asm, encoding) in cases {
+            assert_eq!(encode_str(asm), Ok(encoding), "{asm}");
+        }
+        // An explicitly rotated immediate keeps its rotation
+        assert_eq!(encode_str("movs r0, #4, ror #2"), Ok(0xE3B0_0104));
+
+        let hint = |op, operands| Instruction { op, operands, ..Default::default() };
+        assert_eq!(encode_arm(&hint(Op::NOP, operands![])), Ok(0xE320_F000));
+        let unsupported = |op| Err(EncodeError::UnsupportedOp { op });
+        assert_eq!(encode_arm(&hint(Op::WFI, operands![])), unsupported(Op::WFI));
+        assert_eq!(encode_arm(&hint(Op::DBG, operands![Operand::Imm(5)])), unsupported(Op::DBG));
+    }
+
+    #[test]
+    fn test_encode_arm_at() {
+        let encode_at = |addr, asm| encode_arm_at(addr, &instruction(asm).unwrap().1);
+        assert_eq!(encode_at(0x0200_0000, "bl 0x02000100"), Ok(0xEB00_003E));
+        assert_eq!(encode_at(0x0200_0100, "b 0x02000000"), Ok(0xEAFF_FFBE));
+        assert_eq!(encode_at(0x0200_0000, "blx 0x02000102"), Ok(0xFB00_003E));
+        assert_eq!(encode_at(0x0200_0000, "ldr r0, 0x02000010"), Ok(0xE59F_0008));
+        assert_eq!(encode_at(0x0200_0020, "ldrh r1, 0x02000010"), Ok(0xE15F_11B8));
+        assert_eq!(encode_at(0x0200_0000, "adr r0, 0x02000108"), Ok(0xE28F_0C01));
+        assert_eq!(encode_at(0x0200_0000, "adr r0, 0x01FFFFF0"), Ok(0xE24F_0018));
+
+        // Targets need the address of the instruction
+        assert_eq!(encode_str("bl 0x02000100"), Err(EncodeError::UnresolvedTarget { op: Op::BL }));
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        let out_of_range = |op, imm| Err(EncodeError::ImmediateOutOfRange { op, imm });
+        let modified_imm = |op, imm| Err(EncodeError::InvalidModifiedImm { op, imm });
+        let shift = |op, shift_op, imm| {
+            Err(EncodeError::ShiftOutOfRange { op, shift: ImmShift { op: shift_op, imm } })
+        };
+        let invalid = |op| Err(EncodeError::InvalidOperands { op });
+
+        // Not an 8-bit value rotated by an even amount
+        assert_eq!(encode_str("add r0, r1, #257"), modified_imm(Op::ADD, 257));
+        assert_eq!(encode_str("mov r0, #510"), modified_imm(Op::MOV, 510));
+        assert_eq!(encode_str("mov r0, #1020"), Ok(0xE3A0_0FFF));
+        assert_eq!(encode_str("mov r0, #1021"), modified_imm(Op::MOV, 1021));
+        assert_eq!(encode_str("msr cpsr_f, #257"), modified_imm(Op::MSR, 257));
+        assert_eq!(encode_str("lsr r0, r1, #0"), shift(Op::LSR, ShiftOp::LSR, 0));
+        assert_eq!(encode_str("ror r0, r1, #32"), shift(Op::ROR, ShiftOp::ROR, 32));
+        assert_eq!(encode_str("add r0, r1, r2, lsl #32"), shift(Op::ADD, ShiftOp::LSL, 32));
+        assert_eq!(encode_str("ldr r0, [r1, r2, lsl #92]!"), shift(Op::LDR, ShiftOp::LSL, 92));
+        assert_eq!(encode_str("ldr r0, [r1, #4096]"), out_of_range(Op::LDR, 4096));
+        assert_eq!(encode_str("ldrh r0, [r1, #256]"), out_of_range(Op::LDRH, 256));
+        assert_eq!(encode_str("ldc p1, c2, [r3, #2]"), out_of_range(Op::LDC, 2));
+        assert_eq!(encode_str("ldc p1, c2, [r3, #1024]"), out_of_range(Op::LDC, 1024));
+        assert_eq!(encode_str("mcr p15, 8, r0, c1, c0, 0"), out_of_range(Op::MCR, 8));
+
+        let branch = |op, offset| Instruction {
+            op,
+            operands: operands![Operand::Imm(offset)],
+            ..Default::default()
+        };
+        assert_eq!(encode_arm(&branch(Op::B, 2)), out_of_range(Op::B, 2));
+        assert_eq!(encode_arm(&branch(Op::BLX, 2)), Ok(0xFB00_0000));
+        assert_eq!(encode_arm(&branch(Op::BL, 1 << 25)), out_of_range(Op::BL, 1 << 25));
+        assert_eq!(encode_arm(&branch(Op::BL, (-(1i32 << 25)) as u32)), Ok(0xEB80_0000));
+
+        assert_eq!(encode_str("strd r0, r1, [r0]"), Ok(0xE1C0_00F0));
+        assert_eq!(encode_str("strd r2, r4, [r0]"), invalid(Op::STRD));
+        assert_eq!(encode_str("ldrh r0, [r1, r2, lsl #1]"), invalid(Op::LDRH));
+        assert_eq!(encode_str("add r0, #1"), invalid(Op::ADD));
+        assert_eq!(
+            encode_str("mul r0, r1, r2, lsl r3"),
+            Err(EncodeError::RegisterShift { op: Op::MUL })
+        );
+        assert_eq!(encode_str("msr cpsr_fc, cpsr"), invalid(Op::MSR));
+        assert_eq!(
+            encode_str("pldeq [r0]"),
+            Err(EncodeError::InvalidCondition { op: Op::PLD, cond: Cond::EQ })
+        );
+        assert_eq!(encode_str("cbz r0, r1"), Err(EncodeError::UnsupportedOp { op: Op::CBZ }));
+
+        let flags = Instruction {
+            op: Op::MOV,
+            operands: operands![Operand::Reg(R0), Operand::Reg(FLAGS)],
+            ..Default::default()
+        };
+        assert_eq!(encode_arm(&flags), invalid(Op::MOV));
+    }
+
+    #[test]
+    fn test_validate_arm() {
+        let validate = |asm| validate_arm_at(0, &instruction(asm).unwrap().1, Arch::ARMv5TE);
+        let unpredictable = |op, reason| Err(EncodeError::Unpredictable { op, reason });
+        assert_eq!(validate("ldr r0, [r1, #4]!"), Ok(0xE5B1_0004));
+        assert_eq!(validate("ldr r0, [r0, #4]!"), unpredictable(Op::LDR, WritebackBase));
+        assert_eq!(validate("str r0, [pc], #4"), unpredictable(Op::STR, WritebackBase));
+        assert_eq!(validate("mul pc, r1, r2"), unpredictable(Op::MUL, PcOperand));
+        assert_eq!(validate("ldmia pc!, {r0}"), unpredictable(Op::LDMIA, PcOperand));
+        assert_eq!(validate("ldmia r0!, {r0, r1}"), unpredictable(Op::LDMIA, BaseInList));
+        // STM may store the base it writes back only if it's the lowest register
+        assert_eq!(validate("stmia r1!, {r0, r1}"), unpredictable(Op::STMIA, BaseInList));
+        assert_eq!(validate("stmia r0!, {r0, r1}"), Ok(0xE8A0_0003));
+        // Transferring the User mode registers can't write back, except for LDM which loads PC
+        assert_eq!(validate("stmia r0!, {r1, r2}^"), unpredictable(Op::STMIA, UserWriteback));
+        assert_eq!(validate("ldmia r0!, {r1, r2}^"), unpredictable(Op::LDMIA, UserWriteback));
+        assert_eq!(validate("ldmia r0!, {r1, pc}^"), Ok(0xE8F0_8002));
+        assert_eq!(validate("ldrd r1, r2, [r0]"), unpredictable(Op::LDRD, OddRegisterPair));
+        assert_eq!(validate("ldrd r2, r3, [r0]"), Ok(0xE1C0_20D0));
+        assert_eq!(validate("ldrd r0, r1, [r2, r1]"), unpredictable(Op::LDRD, RegisterOverlap));
+        // Only LDRD restricts the offset register
+        assert_eq!(validate("ldrh r0, [r1, r0]"), Ok(0xE191_00B0));
+        assert_eq!(validate("ldrsb r0, [r1, r0]"), Ok(0xE191_00D0));
+        assert_eq!(validate("ldrsh r0, [r1, r0]"), Ok(0xE191_00F0));
+        // The ARMv6K hints don't encode on ARMv4T/ARMv5TE
+        assert_eq!(validate("wfi"), Err(EncodeError::UnsupportedOp { op: Op::WFI }));
+        // ARMv5TE instructions don't validate on ARMv4T
+        let clz = instruction("clz r0, r1").unwrap().1;
+        assert_eq!(validate_arm_at(0, &clz, Arch::ARMv5TE), Ok(0xE16F_0F11));
+        assert_eq!(
+            validate_arm_at(0, &clz, Arch::ARMv4T),
+            Err(EncodeError::Undecodable {
+                op: Op::CLZ,
+                error: DisasmError::Unsupported { instr: 0xE16F_0F11, arch: Arch::ARMv4T }
+            })
+        );
+        assert_eq!(
+            validate("ldr r0, [r1, r2, lsl #92]")
+                .unwrap_err()
+                .to_string(),
+            "LSL #92 is out of range in LDR, which takes #0-31"
+        );
+    }
+
+    /// The instruction decoded from an encoding, including UNPREDICTABLE ones
+    fn decoded(result: Result<Instruction, DisasmError>) -> Option<Instruction> {
+        match result {
+            Ok(instr) => Some(instr),
+            Err(DisasmError::Unpredictable { decoded, .. }) => Some(*decoded),
+            Err(_) => None,
+        }
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        // Every instruction the decoder produces must encode to a word which decodes to the same
+        // instruction, with and without resolving PC-relative operands
+        let mut rng = thread_rng();
+        for selector in 0..=u16::MAX as u32 {
+            let cond = (selector >> 12) << 28;
+            let op1 = ((selector >> 4) & 0xFF) << 20;
+            let op2 = (selector & 0xF) << 4;
+            for _ in 0..4 {
+                let encoding = cond | op1 | op2 | (rng.gen::<u32>() & 0x000F_FF0F);
+                let Some(instr) = decoded(disassemble_arm(encoding, Arch::ARMv5TE)) else {
+                    continue;
+                };
+                let reencoded = encode_arm(&instr)
+                    .unwrap_or_else(|e| panic!("{encoding:#010x} {instr:?}: {e}"));
+                assert_eq!(
+                    decoded(disassemble_arm(reencoded, Arch::ARMv5TE)),
+                    Some(instr),
+                    "{encoding:#010x} re-encoded as {reencoded:#010x}"
+                );
+
+                let addr = rng.gen::<u32>() & !0b11;
+                let instr = decoded(disassemble_arm_at(addr, encoding, Arch::ARMv5TE)).unwrap();
+                let reencoded = encode_arm_at(addr, &instr)
+                    .unwrap_or_else(|e| panic!("{encoding:#010x} {instr:?}: {e}"));
+                assert_eq!(
+                    decoded(disassemble_arm_at(addr, reencoded, Arch::ARMv5TE)),
+                    Some(instr),
+                    "{encoding:#010x} at {addr:#010x} re-encoded as {reencoded:#010x}"
+                );
+            }
+        }
+    }
+}
@@ -1,8 +1,11 @@
+mod dataflow;
 pub mod parsing;
 
+pub use dataflow::FLAGS_ALL;
+
 use std::fmt;
 use std::fmt::Write;
-use strum::{Display, EnumIter, EnumString};
+use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString)]
 #[strum(ascii_case_insensitive)]
@@ -22,6 +25,9 @@ pub enum Cond {
     GT,
     LE,
     AL,
+    /// The 0b1111 condition, originally "never". From ARMv5 it selects the unconditional
+    /// instruction space instead, and instructions decoded from it are given AL
+    NV,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString, EnumIter)]
@@ -43,20 +49,171 @@ pub enum Register {
     SP = 13,
     LR = 14,
     PC = 15,
+    /// CPSR, which holds the condition flags along with the mode and control bits. Only the
+    /// registers of the current mode are named, and the others are reached through
+    /// [crate::vm::VMState]
     FLAGS = 16,
 }
 
+/// Program status registers, accessed by MRS and MSR
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum StatusReg {
+    CPSR,
+    SPSR,
+}
+
+/// Bits of the MSR field mask, which select the status register bytes that are written
+pub const PSR_MASK_C: u32 = 0b0001;
+pub const PSR_MASK_X: u32 = 0b0010;
+pub const PSR_MASK_S: u32 = 0b0100;
+pub const PSR_MASK_F: u32 = 0b1000;
+
+/// Bits of CPSR (and the FLAGS register) holding the condition flags, and the sticky overflow
+/// flag Q set by the saturating and DSP multiply instructions
+pub const FLAG_N: u32 = 1 << 31;
+pub const FLAG_Z: u32 = 1 << 30;
+pub const FLAG_C: u32 = 1 << 29;
+pub const FLAG_V: u32 = 1 << 28;
+pub const FLAG_Q: u32 = 1 << 27;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Address {
     pub base: Register,
     pub mode: AddrMode,
 }
 
+/// A set of registers, including FLAGS. Bit n of `regs` is set if the register numbered n is in
+/// the set
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegSet {
+    pub regs: u32,
+}
+
+impl RegSet {
+    pub fn new(regs: u32) -> Self {
+        Self { regs }
+    }
+
+    /// Every register, including FLAGS
+    pub fn all() -> Self {
+        Register::iter().collect()
+    }
+
+    pub fn insert(&mut self, reg: Register) {
+        self.regs |= 1 << reg as u32;
+    }
+
+    pub fn contains(&self, reg: Register) -> bool {
+        (self.regs >> reg as u32) & 1 == 1
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self { regs: self.regs | other.regs }
+    }
+
+    pub fn len(&self) -> usize {
+        self.regs.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regs == 0
+    }
+
+    /// Registers in the set, in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = Register> + '_ {
+        Register::iter().filter(|&r| self.contains(r))
+    }
+}
+
+impl FromIterator<Register> for RegSet {
+    fn from_iter<T: IntoIterator<Item = Register>>(iter: T) -> Self {
+        let mut set = Self::default();
+        iter.into_iter().for_each(|reg| set.insert(reg));
+        set
+    }
+}
+
+/// The register list operand of the block data transfer instructions (LDM/STM, PUSH/POP), which
+/// only holds general purpose registers
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegList {
+    pub regs: RegSet,
+
+    /// Set for the "^" forms of LDM/STM: transfers the user mode registers or, for an LDM which
+    /// loads PC, copies SPSR to CPSR
+    pub user: bool,
+}
+
+impl RegList {
+    pub fn new(regs: u16) -> Self {
+        Self { regs: RegSet::new(regs as u32), user: false }
+    }
+}
+
+impl fmt::Display for RegList {
+    /// Formats the list in assembler syntax, with runs of 3 or more consecutive registers
+    /// collapsed into a range, e.g. "{R0-R3, LR}". The alternate flag lists every register in
+    /// lowercase instead, as objdump does, e.g. "{r0, r1, r2, r3, lr}"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let regs: Vec<Register> = self.regs.iter().collect();
+        if f.alternate() {
+            let regs: Vec<String> = regs
+                .iter()
+                .map(|r| format!("{:?}", r).to_lowercase())
+                .collect();
+            write!(f, "{{{}}}", regs.join(", "))?;
+            if self.user {
+                write!(f, "^")?;
+            }
+            return Ok(());
+        }
+        let mut parts = vec![];
+        let mut i = 0;
+        while i < regs.len() {
+            let mut j = i;
+            while j + 1 < regs.len() && regs[j + 1] as usize == regs[j] as usize + 1 {
+                j += 1;
+            }
+            if j - i >= 2 {
+                parts.push(format!("{:?}-{:?}", regs[i], regs[j]));
+                i = j + 1;
+            } else {
+                parts.push(format!("{:?}", regs[i]));
+                i += 1;
+            }
+        }
+        write!(f, "{{{}}}", parts.join(", "))?;
+        if self.user {
+            write!(f, "^")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Operand {
     Reg(Register),
     Imm(u32),
     Addr(Address),
+    /// Base register which is updated after the transfer, written "Rn!"
+    RegWb(Register),
+    RegList(RegList),
+    /// Status register read by MRS
+    Psr(StatusReg),
+    /// Status register fields written by MSR, with a mask made up of the PSR_MASK_* bits. Written
+    /// e.g. "CPSR_fc"
+    PsrFields(StatusReg, u32),
+    /// Coprocessor number, written e.g. "p15"
+    Coproc(u32),
+    /// Coprocessor specific opcode (opc1/opc2), written without the "#"
+    CoprocOpcode(u32),
+    /// Coprocessor register (CRd/CRn/CRm), written e.g. "c1"
+    CoprocReg(u32),
+    /// Value passed to the coprocessor by the unindexed form of LDC/STC, written e.g. "{8}"
+    CoprocOption(u32),
+    /// Absolute address of a branch target or PC-relative literal, written e.g. "0x02000800"
+    Target(u32),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString)]
@@ -135,7 +292,7 @@ impl Offset {
     }
 
     pub fn reg(reg: Register, shift: Option<ImmShift>, add: bool) -> Self {
-        Self { value: OffsetValue::Reg { reg: reg, shift: shift }, add }
+        Self { value: OffsetValue::Reg { reg, shift }, add }
     }
 }
 
@@ -200,7 +357,6 @@ pub enum Op {
     DSB,
     ENTERX,
     EOR,
-    EORS,
     ERET,
     FLDMDBX,
     FLDMIAX,
@@ -299,7 +455,6 @@ pub enum Op {
     SADD8,
     SASX,
     SBC,
-    SBCS,
     SBFX,
     SDIV,
     SEL,
@@ -592,13 +747,151 @@ pub enum Op {
     YIELD,
 }
 
-const MAX_NUM_OPERANDS: usize = 4;
+/// The most operands any instruction has, which is MCR/MRC's 6
+pub const MAX_NUM_OPERANDS: usize = 6;
+
+/// Fixed-capacity list of an instruction's operands, stored inline so that decoding doesn't
+/// allocate and [Instruction] can be Copy. Dereferences to a slice, and is built with `push` or
+/// the [operands!](crate::operands!) macro
+#[derive(Copy, Clone)]
+pub struct Operands {
+    operands: [Operand; MAX_NUM_OPERANDS],
+    len: u8,
+}
+
+/// Creates [Operands] containing the arguments, like `vec!`
+#[macro_export]
+macro_rules! operands {
+    () => {
+        $crate::ir::Operands::new()
+    };
+    ($($operand:expr),+ $(,)?) => {
+        $crate::ir::Operands::from([$($operand),+])
+    };
+}
+
+impl Operands {
+    pub const fn new() -> Self {
+        // Unused slots are never read, so any operand will do
+        Self { operands: [Operand::Imm(0); MAX_NUM_OPERANDS], len: 0 }
+    }
+
+    pub fn as_slice(&self) -> &[Operand] {
+        self
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [Operand] {
+        self
+    }
+
+    /// Appends an operand. Panics if there are already MAX_NUM_OPERANDS
+    pub fn push(&mut self, operand: Operand) {
+        assert!(self.len() < MAX_NUM_OPERANDS, "too many operands");
+        self.operands[self.len()] = operand;
+        self.len += 1;
+    }
+
+    /// Inserts an operand at `index`, shifting the following ones along. Panics if there are
+    /// already MAX_NUM_OPERANDS or `index` is out of bounds
+    pub fn insert(&mut self, index: usize, operand: Operand) {
+        assert!(index <= self.len(), "insertion index out of bounds");
+        self.push(operand);
+        self[index..].rotate_right(1);
+    }
+
+    /// Removes and returns the operand at `index`, shifting the following ones back
+    pub fn remove(&mut self, index: usize) -> Operand {
+        let operand = self[index];
+        self[index..].rotate_left(1);
+        self.len -= 1;
+        operand
+    }
+
+    pub fn pop(&mut self) -> Option<Operand> {
+        let operand = self.last().copied()?;
+        self.len -= 1;
+        Some(operand)
+    }
+}
+
+impl Default for Operands {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::ops::Deref for Operands {
+    type Target = [Operand];
+
+    fn deref(&self) -> &[Operand] {
+        &self.operands[..self.len as usize]
+    }
+}
+
+impl std::ops::DerefMut for Operands {
+    fn deref_mut(&mut self) -> &mut [Operand] {
+        &mut self.operands[..self.len as usize]
+    }
+}
+
+impl PartialEq for Operands {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Operands {}
+
+impl PartialEq<[Operand]> for Operands {
+    fn eq(&self, other: &[Operand]) -> bool {
+        **self == *other
+    }
+}
+
+impl fmt::Debug for Operands {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<const N: usize> From<[Operand; N]> for Operands {
+    fn from(operands: [Operand; N]) -> Self {
+        operands.into_iter().collect()
+    }
+}
+
+impl FromIterator<Operand> for Operands {
+    /// Panics if the iterator yields more than MAX_NUM_OPERANDS operands
+    fn from_iter<T: IntoIterator<Item = Operand>>(iter: T) -> Self {
+        let mut operands = Self::new();
+        iter.into_iter().for_each(|operand| operands.push(operand));
+        operands
+    }
+}
+
+impl IntoIterator for Operands {
+    type Item = Operand;
+    type IntoIter = std::iter::Take<std::array::IntoIter<Operand, MAX_NUM_OPERANDS>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.operands.into_iter().take(self.len())
+    }
+}
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+impl<'a> IntoIterator for &'a Operands {
+    type Item = &'a Operand;
+    type IntoIter = std::slice::Iter<'a, Operand>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Instruction {
     pub cond: Cond,
     pub op: Op,
-    pub operands: Vec<Operand>,
+    pub operands: Operands,
     pub extra: Option<ExtraOperand>,
     pub set_flags: bool,
 }
@@ -608,52 +901,148 @@ impl Default for Instruction {
         Self {
             cond: Cond::AL,
             op: Op::NOP,
-            operands: Vec::new(),
+            operands: Operands::new(),
             extra: None,
             set_flags: false,
         }
     }
 }
 
+/// Formats an immediate, in hexadecimal for objdump style
+fn fmt_imm(imm: u32, objdump: bool) -> String {
+    match objdump {
+        true => format!("#{:#x}", imm),
+        false => format!("#{}", imm),
+    }
+}
+
+fn fmt_reg(reg: Register, objdump: bool) -> String {
+    match objdump {
+        true => format!("{:?}", reg).to_lowercase(),
+        false => format!("{:?}", reg),
+    }
+}
+
+/// Formats a shift applied to the preceding register, e.g. "LSL #2", "ASR R3" or "RRX"
+fn fmt_shift(shift: Shift, objdump: bool) -> String {
+    let s = match (shift.op, shift.value) {
+        (ShiftOp::RRX, _) => "RRX".to_string(),
+        // Shift amounts are written in decimal by objdump too
+        (op, ExtraValue::Imm(imm)) => format!("{:?} #{}", op, imm),
+        (op, ExtraValue::Reg(reg)) => format!("{:?} {:?}", op, reg),
+    };
+    match objdump {
+        true => s.to_lowercase(),
+        false => s,
+    }
+}
+
+/// Formats an address operand along with its offset, e.g. "[R0, -R1, LSL #2]!" or "[R0], #4"
+fn fmt_addr(addr: Address, offset: Option<Offset>, objdump: bool) -> String {
+    let base = fmt_reg(addr.base, objdump);
+    // objdump leaves out an offset of zero, the parser reads that back as no offset at all
+    let offset = match offset {
+        None if addr.mode == AddrMode::Offset => return format!("[{}]", base),
+        Some(offset)
+            if objdump && addr.mode == AddrMode::Offset && offset == Offset::imm(0, true) =>
+        {
+            return format!("[{}]", base)
+        }
+        offset => offset.unwrap_or(Offset::imm(0, true)),
+    };
+    let sign = if offset.add { "" } else { "-" };
+    let offset_str = match offset.value {
+        OffsetValue::Imm(imm) => match objdump {
+            true => format!("#{}{:#x}", sign, imm),
+            false => format!("#{}{}", sign, imm),
+        },
+        OffsetValue::Reg { reg, shift } => {
+            let mut s = format!("{}{}", sign, fmt_reg(reg, objdump));
+            if let Some(shift) = shift {
+                write!(s, ", {}", fmt_shift(shift.into(), objdump)).unwrap();
+            }
+            s
+        }
+    };
+    match addr.mode {
+        AddrMode::Offset => format!("[{}, {}]", base, offset_str),
+        AddrMode::PreIndex => format!("[{}, {}]!", base, offset_str),
+        AddrMode::PostIndex => format!("[{}], {}", base, offset_str),
+    }
+}
+
 impl fmt::Display for Instruction {
+    /// Formats the instruction in the syntax read by `parsing::instruction`, which parses it back
+    /// to an identical instruction. The alternate flag ("{:#}") selects objdump style instead:
+    /// lowercase UAL mnemonics (S before the condition, and no AL) with hexadecimal immediates
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = if self.set_flags { "S" } else { "" };
-        let mut operand_str = String::new();
-        for (i, operand) in self.operands.iter().enumerate() {
-            match operand {
-                Operand::Reg(reg) => {
-                    if i != 0 {
-                        operand_str.push_str(", ");
-                    }
-                    write!(operand_str, "{:?}", reg)?;
-                }
-                Operand::Imm(imm) => {
-                    write!(operand_str, ", #{}", imm)?;
-                }
-                _ => todo!(),
+        let objdump = f.alternate();
+        // The comparisons always set the flags, so the S is implied
+        let compare = matches!(self.op, Op::TST | Op::TEQ | Op::CMP | Op::CMN);
+        let s = if self.set_flags && !compare { "S" } else { "" };
+        let mnemonic = match objdump {
+            true => {
+                let cond = match self.cond {
+                    Cond::AL => String::new(),
+                    cond => format!("{:?}", cond),
+                };
+                format!("{:?}{}{}", self.op, s, cond).to_lowercase()
             }
-        }
-        if let Some(extra) = self.extra {
-            match extra {
-                ExtraOperand::Shift(shift) => match shift.value {
-                    ExtraValue::Reg(reg) => {
-                        write!(operand_str, ", {:?} {:?}", shift.op, reg)?;
-                    }
-                    ExtraValue::Imm(imm) => {
-                        write!(operand_str, ", {:?} {}", shift.op, imm)?;
+            false => format!("{:?}{:?}{}", self.op, self.cond, s),
+        };
+
+        // An offset belongs to the address operand, and a shift follows the last operand
+        let (offset, shift) = match self.extra {
+            Some(ExtraOperand::Offset(offset)) => (Some(offset), None),
+            Some(ExtraOperand::Shift(shift)) => (None, Some(shift)),
+            None => (None, None),
+        };
+        let mut operands: Vec<String> = self
+            .operands
+            .iter()
+            .map(|operand| match *operand {
+                Operand::Reg(reg) => fmt_reg(reg, objdump),
+                Operand::Imm(imm) => fmt_imm(imm, objdump),
+                Operand::Addr(addr) => fmt_addr(addr, offset, objdump),
+                Operand::RegWb(reg) => format!("{}!", fmt_reg(reg, objdump)),
+                Operand::RegList(list) => match objdump {
+                    true => format!("{:#}", list),
+                    false => format!("{}", list),
+                },
+                Operand::Psr(psr) => format!("{}", psr),
+                Operand::PsrFields(psr, mask) => {
+                    let mut s = format!("{}_", psr);
+                    for (bit, field) in [
+                        (PSR_MASK_F, 'f'),
+                        (PSR_MASK_S, 's'),
+                        (PSR_MASK_X, 'x'),
+                        (PSR_MASK_C, 'c'),
+                    ] {
+                        if mask & bit != 0 {
+                            s.push(field);
+                        }
                     }
+                    s
+                }
+                Operand::Coproc(coproc) => format!("p{}", coproc),
+                Operand::CoprocOpcode(opcode) => format!("{}", opcode),
+                Operand::CoprocReg(reg) => format!("c{}", reg),
+                Operand::CoprocOption(option) => format!("{{{}}}", option),
+                Operand::Target(addr) => match objdump {
+                    true => format!("{:#x}", addr),
+                    false => format!("{:#010x}", addr),
                 },
-                _ => todo!(),
-            }
+            })
+            .collect();
+        if let Some(shift) = shift {
+            operands.push(fmt_shift(shift, objdump));
         }
-        write!(
-            f,
-            "{op:?}{cond:?}{s} {operands}",
-            op = self.op,
-            cond = self.cond,
-            s = s,
-            operands = operand_str
-        )
+
+        write!(f, "{}", mnemonic)?;
+        if !operands.is_empty() {
+            write!(f, " {}", operands.join(", "))?;
+        }
+        Ok(())
     }
 }
 
@@ -662,11 +1051,15 @@ mod tests {
     use std::str::FromStr;
 
     use super::{
+        AddrMode, Address,
         Cond::*,
-        Instruction,
+        ImmShift, Instruction, Offset,
         Op::{self, *},
-        Operand,
+        Operand, Operands, RegList, RegSet,
         Register::*,
+        Shift, ShiftOp,
+        StatusReg::*,
+        MAX_NUM_OPERANDS, PSR_MASK_C, PSR_MASK_F,
     };
 
     #[test]
@@ -674,12 +1067,170 @@ mod tests {
         let instr = Instruction {
             cond: EQ,
             op: AND,
-            operands: vec![Operand::Reg(R12), Operand::Reg(PC), Operand::Imm(12)],
+            operands: operands![Operand::Reg(R12), Operand::Reg(PC), Operand::Imm(12)],
             ..Default::default()
         };
         assert_eq!(instr.to_string(), "ANDEQ R12, PC, #12");
     }
 
+    #[test]
+    fn test_operands() {
+        let mut operands = operands![Operand::Reg(R0), Operand::Imm(1)];
+        assert_eq!(operands.len(), 2);
+        assert_eq!(operands[1], Operand::Imm(1));
+        operands.insert(0, Operand::Reg(R2));
+        operands.push(Operand::Reg(R3));
+        assert_eq!(
+            operands.as_slice(),
+            [
+                Operand::Reg(R2),
+                Operand::Reg(R0),
+                Operand::Imm(1),
+                Operand::Reg(R3)
+            ]
+        );
+        assert_eq!(operands.remove(1), Operand::Reg(R0));
+        assert_eq!(operands.pop(), Some(Operand::Reg(R3)));
+        // Only the used slots take part in comparisons
+        assert_eq!(operands, operands![Operand::Reg(R2), Operand::Imm(1)]);
+        assert_eq!(format!("{:?}", operands), "[Reg(R2), Imm(1)]");
+    }
+
+    #[test]
+    #[should_panic(expected = "too many operands")]
+    fn test_operands_overflow() {
+        let mut operands: Operands = (0..MAX_NUM_OPERANDS as u32).map(Operand::Imm).collect();
+        operands.push(Operand::Imm(0));
+    }
+
+    #[test]
+    fn test_addr_display() {
+        let addr = |base, mode| Operand::Addr(Address { base, mode });
+        let instr = Instruction {
+            cond: EQ,
+            op: LDR,
+            operands: operands![Operand::Reg(R0), addr(R1, AddrMode::PreIndex)],
+            extra: Some(Offset::imm(4, false).into()),
+            ..Default::default()
+        };
+        assert_eq!(instr.to_string(), "LDREQ R0, [R1, #-4]!");
+        assert_eq!(format!("{:#}", instr), "ldreq r0, [r1, #-0x4]!");
+
+        let instr = Instruction {
+            op: STRB,
+            operands: operands![Operand::Reg(R2), addr(SP, AddrMode::PostIndex)],
+            extra: Some(Offset::reg(R3, Some(ImmShift { op: ShiftOp::LSL, imm: 2 }), true).into()),
+            ..Default::default()
+        };
+        assert_eq!(instr.to_string(), "STRBAL R2, [SP], R3, LSL #2");
+        assert_eq!(format!("{:#}", instr), "strb r2, [sp], r3, lsl #2");
+
+        // No offset and a zero offset are distinct, but objdump prints both the same way
+        let instr = Instruction {
+            op: LDRH,
+            operands: operands![Operand::Reg(R0), addr(R1, AddrMode::Offset)],
+            extra: Some(Offset::imm(0, true).into()),
+            ..Default::default()
+        };
+        assert_eq!(instr.to_string(), "LDRHAL R0, [R1, #0]");
+        assert_eq!(format!("{:#}", instr), "ldrh r0, [r1]");
+        let instr = Instruction { extra: None, ..instr };
+        assert_eq!(instr.to_string(), "LDRHAL R0, [R1]");
+    }
+
+    #[test]
+    fn test_objdump_display() {
+        let instr = Instruction {
+            cond: NE,
+            op: ADD,
+            operands: operands![Operand::Reg(R0), Operand::Reg(R1), Operand::Reg(R2)],
+            extra: Some(Shift::reg(ShiftOp::ASR, R3).into()),
+            set_flags: true,
+        };
+        assert_eq!(instr.to_string(), "ADDNES R0, R1, R2, ASR R3");
+        assert_eq!(format!("{:#}", instr), "addsne r0, r1, r2, asr r3");
+
+        let instr = Instruction {
+            op: CMP,
+            operands: operands![Operand::Reg(R4), Operand::Imm(0xFF00)],
+            set_flags: true,
+            ..Default::default()
+        };
+        assert_eq!(instr.to_string(), "CMPAL R4, #65280");
+        assert_eq!(format!("{:#}", instr), "cmp r4, #0xff00");
+
+        let instr = Instruction {
+            op: BL,
+            operands: operands![Operand::Target(0x0200_0100)],
+            ..Default::default()
+        };
+        assert_eq!(format!("{:#}", instr), "bl 0x2000100");
+
+        let instr = Instruction {
+            op: POP,
+            operands: operands![Operand::RegList(RegList::new(0b1000_0000_0000_0111))],
+            ..Default::default()
+        };
+        assert_eq!(instr.to_string(), "POPAL {R0-R2, PC}");
+        assert_eq!(format!("{:#}", instr), "pop {r0, r1, r2, pc}");
+
+        let instr = Instruction { op: NOP, ..Default::default() };
+        assert_eq!(instr.to_string(), "NOPAL");
+        assert_eq!(format!("{:#}", instr), "nop");
+    }
+
+    #[test]
+    fn test_reg_list_display() {
+        let instr = Instruction {
+            op: LDMIA,
+            operands: operands![
+                Operand::RegWb(R0),
+                Operand::RegList(RegList::new(0b0100_0000_1110_1111)),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(instr.to_string(), "LDMIAAL R0!, {R0-R3, R5-R7, LR}");
+
+        let list = RegList { regs: RegSet::new(0b1000_0000_0000_0011), user: true };
+        assert_eq!(list.to_string(), "{R0, R1, PC}^");
+    }
+
+    #[test]
+    fn test_psr_display() {
+        let instr = Instruction {
+            op: MSR,
+            operands: operands![
+                Operand::PsrFields(SPSR, PSR_MASK_C | PSR_MASK_F),
+                Operand::Reg(R1),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(instr.to_string(), "MSRAL SPSR_fc, R1");
+        let instr = Instruction {
+            op: MRS,
+            operands: operands![Operand::Reg(R1), Operand::Psr(CPSR)],
+            ..Default::default()
+        };
+        assert_eq!(instr.to_string(), "MRSAL R1, CPSR");
+    }
+
+    #[test]
+    fn test_coproc_display() {
+        let instr = Instruction {
+            op: MCR,
+            operands: operands![
+                Operand::Coproc(15),
+                Operand::CoprocOpcode(0),
+                Operand::Reg(R0),
+                Operand::CoprocReg(1),
+                Operand::CoprocReg(0),
+                Operand::CoprocOpcode(0),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(instr.to_string(), "MCRAL p15, 0, R0, c1, c0, 0");
+    }
+
     #[test]
     fn test_enum_str_derive() {
         assert_eq!(Op::from_str("AND").unwrap(), AND);
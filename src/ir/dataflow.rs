@@ -0,0 +1,487 @@
+//! Queries for which registers, flags and memory an instruction uses, so that the translator only
+//! has to load, store and compute what a block actually needs
+
+use super::{
+    AddrMode, Cond, ExtraOperand, ExtraValue, Instruction, Offset, OffsetValue, Op, Operand,
+    RegSet, Register, ShiftOp, StatusReg, FLAG_C, FLAG_N, FLAG_Q, FLAG_V, FLAG_Z, PSR_MASK_C,
+    PSR_MASK_F, PSR_MASK_S, PSR_MASK_X,
+};
+
+/// All of the condition flags and Q
+pub const FLAGS_ALL: u32 = FLAG_N | FLAG_Z | FLAG_C | FLAG_V | FLAG_Q;
+
+/// Flags tested by a condition code
+fn cond_flags(cond: Cond) -> u32 {
+    match cond {
+        Cond::EQ | Cond::NE => FLAG_Z,
+        Cond::CS | Cond::CC => FLAG_C,
+        Cond::MI | Cond::PL => FLAG_N,
+        Cond::VS | Cond::VC => FLAG_V,
+        Cond::HI | Cond::LS => FLAG_C | FLAG_Z,
+        Cond::GE | Cond::LT => FLAG_N | FLAG_V,
+        Cond::GT | Cond::LE => FLAG_N | FLAG_Z | FLAG_V,
+        Cond::AL | Cond::NV => 0,
+    }
+}
+
+/// Number of leading register operands which are destinations rather than sources. The registers
+/// loaded by LDM/POP are in the register list instead
+fn num_dests(op: Op) -> usize {
+    match op {
+        Op::UMULL
+        | Op::UMLAL
+        | Op::SMULL
+        | Op::SMLAL
+        | Op::SMLALBB
+        | Op::SMLALBT
+        | Op::SMLALTB
+        | Op::SMLALTT
+        | Op::LDRD => 2,
+        Op::TST
+        | Op::TEQ
+        | Op::CMP
+        | Op::CMN
+        | Op::STR
+        | Op::STRB
+        | Op::STRT
+        | Op::STRBT
+        | Op::STRH
+        | Op::STRD
+        | Op::STM
+        | Op::STMIA
+        | Op::STMIB
+        | Op::STMDA
+        | Op::STMDB
+        | Op::PUSH
+        | Op::LDM
+        | Op::LDMIA
+        | Op::LDMIB
+        | Op::LDMDA
+        | Op::LDMDB
+        | Op::POP
+        | Op::B
+        | Op::BL
+        | Op::BX
+        | Op::BLX
+        | Op::MSR
+        | Op::MCR
+        | Op::MCR2
+        | Op::MCRR
+        | Op::PLD => 0,
+        _ => 1,
+    }
+}
+
+fn is_compare(op: Op) -> bool {
+    matches!(op, Op::TST | Op::TEQ | Op::CMP | Op::CMN)
+}
+
+/// Operations whose flag setting form takes C from the shifter, which may leave it unchanged
+fn is_logical(op: Op) -> bool {
+    matches!(
+        op,
+        Op::AND
+            | Op::EOR
+            | Op::TST
+            | Op::TEQ
+            | Op::ORR
+            | Op::MOV
+            | Op::BIC
+            | Op::MVN
+            | Op::LSL
+            | Op::LSR
+            | Op::ASR
+            | Op::ROR
+            | Op::RRX
+    )
+}
+
+fn is_arithmetic(op: Op) -> bool {
+    matches!(op, Op::ADD | Op::ADC | Op::SUB | Op::SBC | Op::RSB | Op::RSC | Op::CMP | Op::CMN)
+}
+
+fn is_multiply(op: Op) -> bool {
+    matches!(op, Op::MUL | Op::MLA | Op::UMULL | Op::UMLAL | Op::SMULL | Op::SMLAL)
+}
+
+/// Operations which set Q on saturation or overflow, and otherwise leave it unchanged
+fn sets_q(op: Op) -> bool {
+    matches!(
+        op,
+        Op::QADD
+            | Op::QSUB
+            | Op::QDADD
+            | Op::QDSUB
+            | Op::SMLABB
+            | Op::SMLABT
+            | Op::SMLATB
+            | Op::SMLATT
+            | Op::SMLAWB
+            | Op::SMLAWT
+    )
+}
+
+fn is_load_multiple(op: Op) -> bool {
+    matches!(op, Op::LDM | Op::LDMIA | Op::LDMIB | Op::LDMDA | Op::LDMDB | Op::POP)
+}
+
+/// Status register bytes selected by an MSR field mask
+fn psr_mask_bits(mask: u32) -> u32 {
+    [PSR_MASK_C, PSR_MASK_X, PSR_MASK_S, PSR_MASK_F]
+        .iter()
+        .enumerate()
+        .filter(|(_, &field)| mask & field != 0)
+        .fold(0, |acc, (i, _)| acc | (0xFF << (i * 8)))
+}
+
+impl Instruction {
+    /// Registers read by the instruction, including the base of any address, the registers
+    /// stored by STM/PUSH, SP for PUSH/POP, PC for PC-relative immediates, and FLAGS if any flag
+    /// is read (see [Instruction::flags_read]). Registers which a conditional instruction leaves
+    /// unchanged when the condition fails aren't counted as read, nor is FLAGS when only some of
+    /// its bits are written
+    pub fn regs_read(&self) -> RegSet {
+        let mut regs = RegSet::default();
+        let dests = num_dests(self.op);
+        for (i, &operand) in self.operands.iter().enumerate() {
+            match operand {
+                // The accumulating long multiplies also read RdLo and RdHi
+                Operand::Reg(reg) if i >= dests || self.accumulates_long() => regs.insert(reg),
+                Operand::RegWb(reg) => regs.insert(reg),
+                Operand::Addr(addr) => regs.insert(addr.base),
+                Operand::RegList(list) if !is_load_multiple(self.op) => {
+                    regs = regs.union(list.regs)
+                }
+                Operand::Imm(_) if matches!(self.op, Op::B | Op::BL | Op::BLX | Op::ADR) => {
+                    regs.insert(Register::PC)
+                }
+                // The link is computed from PC even when the target is absolute
+                Operand::Target(_) if matches!(self.op, Op::BL | Op::BLX) => {
+                    regs.insert(Register::PC)
+                }
+                _ => {}
+            }
+        }
+        match self.extra {
+            Some(ExtraOperand::Shift(shift)) => {
+                if let ExtraValue::Reg(reg) = shift.value {
+                    regs.insert(reg);
+                }
+            }
+            Some(ExtraOperand::Offset(Offset { value: OffsetValue::Reg { reg, .. }, .. })) => {
+                regs.insert(reg)
+            }
+            _ => {}
+        }
+        // MRC and MRRC read nothing from the ARM registers
+        if matches!(self.op, Op::MRC | Op::MRC2 | Op::MRRC) {
+            regs = RegSet::default();
+        }
+        if matches!(self.op, Op::PUSH | Op::POP) {
+            regs.insert(Register::SP);
+        }
+        if self.flags_read() != 0 {
+            regs.insert(Register::FLAGS);
+        }
+        regs
+    }
+
+    /// Registers written by the instruction, including writeback bases, the registers loaded by
+    /// LDM/POP, SP for PUSH/POP, LR for BL/BLX, PC for branches, and FLAGS if any flag is written
+    /// (see [Instruction::flags_written]). A conditional instruction may leave them unchanged.
+    ///
+    /// SVC and BKPT enter an exception, writing CPSR and PC, and the LR of the exception's mode,
+    /// which is the banked copy that becomes current. They also write that mode's SPSR, which
+    /// isn't a [Register]
+    pub fn regs_written(&self) -> RegSet {
+        let mut regs = RegSet::default();
+        let dests = num_dests(self.op);
+        let reg_operands = self.operands.iter().filter_map(|operand| match operand {
+            Operand::Reg(reg) => Some(*reg),
+            _ => None,
+        });
+        match self.op {
+            // A coprocessor to ARM transfer to PC sets the flags instead
+            Op::MRC | Op::MRC2 => reg_operands
+                .filter(|&reg| reg != Register::PC)
+                .for_each(|reg| regs.insert(reg)),
+            Op::MRRC => reg_operands.for_each(|reg| regs.insert(reg)),
+            _ => reg_operands.take(dests).for_each(|reg| regs.insert(reg)),
+        }
+        for &operand in &self.operands {
+            match operand {
+                Operand::RegWb(reg) => regs.insert(reg),
+                Operand::Addr(addr) if addr.mode != AddrMode::Offset => regs.insert(addr.base),
+                Operand::RegList(list) if is_load_multiple(self.op) => regs = regs.union(list.regs),
+                _ => {}
+            }
+        }
+        match self.op {
+            Op::PUSH | Op::POP => regs.insert(Register::SP),
+            Op::B | Op::BX => regs.insert(Register::PC),
+            Op::BL | Op::BLX | Op::SVC | Op::BKPT => {
+                regs.insert(Register::LR);
+                regs.insert(Register::PC);
+            }
+            _ => {}
+        }
+        if self.flags_written() != 0 || self.writes_cpsr() {
+            regs.insert(Register::FLAGS);
+        }
+        regs
+    }
+
+    /// Flags (the FLAG_* bits) whose values before the instruction may affect its result. This
+    /// includes the flags tested by the condition and, as a failed condition leaves them
+    /// unchanged, the flags it would otherwise write. Similarly a flag which the instruction may
+    /// leave unchanged, such as C for a logical operation whose shift doesn't produce a carry or Q
+    /// for the saturating operations, counts as read
+    pub fn flags_read(&self) -> u32 {
+        let mut flags = cond_flags(self.cond);
+        if flags != 0 {
+            flags |= self.flags_written();
+        }
+        if matches!(self.op, Op::ADC | Op::SBC | Op::RSC | Op::RRX)
+            || matches!(self.extra, Some(ExtraOperand::Shift(shift)) if shift.op == ShiftOp::RRX)
+            || matches!(self.extra, Some(ExtraOperand::Offset(Offset {
+                value: OffsetValue::Reg { shift: Some(shift), .. }, ..
+            })) if shift.op == ShiftOp::RRX)
+        {
+            flags |= FLAG_C;
+        }
+        if self.sets_flags() && is_logical(self.op) {
+            flags |= FLAG_C;
+        }
+        if sets_q(self.op) {
+            flags |= FLAG_Q;
+        }
+        if self.operands.contains(&Operand::Psr(StatusReg::CPSR)) {
+            flags |= FLAGS_ALL;
+        }
+        flags
+    }
+
+    /// Flags (the FLAG_* bits) written by the instruction
+    pub fn flags_written(&self) -> u32 {
+        if self.restores_cpsr() {
+            return FLAGS_ALL;
+        }
+        let mut flags = 0;
+        if self.sets_flags() {
+            if is_arithmetic(self.op) {
+                flags |= FLAG_N | FLAG_Z | FLAG_C | FLAG_V;
+            } else if is_logical(self.op) {
+                flags |= FLAG_N | FLAG_Z | FLAG_C;
+            } else if is_multiply(self.op) {
+                flags |= FLAG_N | FLAG_Z;
+            }
+        }
+        match self.op {
+            op if sets_q(op) => flags |= FLAG_Q,
+            Op::MRC | Op::MRC2 if self.operands.get(2) == Some(&Operand::Reg(Register::PC)) => {
+                flags |= FLAG_N | FLAG_Z | FLAG_C | FLAG_V
+            }
+            Op::MSR => {
+                if let Some(Operand::PsrFields(StatusReg::CPSR, mask)) = self.operands.first() {
+                    flags |= psr_mask_bits(*mask) & FLAGS_ALL;
+                }
+            }
+            _ => {}
+        }
+        flags
+    }
+
+    /// True if the instruction may load from memory
+    pub fn reads_memory(&self) -> bool {
+        matches!(
+            self.op,
+            Op::LDR
+                | Op::LDRB
+                | Op::LDRT
+                | Op::LDRBT
+                | Op::LDRH
+                | Op::LDRSB
+                | Op::LDRSH
+                | Op::LDRD
+                | Op::SWP
+                | Op::SWPB
+                | Op::LDC
+                | Op::LDCL
+                | Op::LDC2
+                | Op::LDC2L
+        ) || is_load_multiple(self.op)
+    }
+
+    /// True if the instruction may store to memory
+    pub fn writes_memory(&self) -> bool {
+        matches!(
+            self.op,
+            Op::STR
+                | Op::STRB
+                | Op::STRT
+                | Op::STRBT
+                | Op::STRH
+                | Op::STRD
+                | Op::STM
+                | Op::STMIA
+                | Op::STMIB
+                | Op::STMDA
+                | Op::STMDB
+                | Op::PUSH
+                | Op::SWP
+                | Op::SWPB
+                | Op::STC
+                | Op::STCL
+                | Op::STC2
+                | Op::STC2L
+        )
+    }
+
+    pub fn accesses_memory(&self) -> bool {
+        self.reads_memory() || self.writes_memory()
+    }
+
+    /// True if the instruction may change the flow of control: branches, exceptions, and
+    /// anything else which writes PC
+    pub fn changes_control_flow(&self) -> bool {
+        self.regs_written().contains(Register::PC)
+    }
+
+    /// Compares always set the flags, even without the S suffix
+    fn sets_flags(&self) -> bool {
+        self.set_flags || is_compare(self.op)
+    }
+
+    fn accumulates_long(&self) -> bool {
+        matches!(
+            self.op,
+            Op::UMLAL | Op::SMLAL | Op::SMLALBB | Op::SMLALBT | Op::SMLALTB | Op::SMLALTT
+        )
+    }
+
+    /// A flag setting data processing instruction writing PC, or an LDM which loads PC with
+    /// the "^" suffix, copies SPSR to CPSR when returning from an exception
+    fn restores_cpsr(&self) -> bool {
+        let pc = Operand::Reg(Register::PC);
+        match self.operands.first() {
+            Some(Operand::Reg(_)) if self.set_flags && !is_compare(self.op) => {
+                (is_arithmetic(self.op) || is_logical(self.op)) && self.operands[0] == pc
+            }
+            _ if is_load_multiple(self.op) => self.operands.iter().any(|operand| {
+                matches!(operand, Operand::RegList(list)
+                    if list.user && list.regs.contains(Register::PC))
+            }),
+            _ => false,
+        }
+    }
+
+    /// MSR also writes the control bits of CPSR, which are kept in FLAGS along with the flags,
+    /// interworking branches write its T bit, and exceptions its mode and I bit
+    fn writes_cpsr(&self) -> bool {
+        let msr_control = matches!(
+            self.operands.first(),
+            Some(Operand::PsrFields(StatusReg::CPSR, mask)) if *mask != 0
+        );
+        msr_control
+            || self.restores_cpsr()
+            || self.interworks()
+            || matches!(self.op, Op::SVC | Op::BKPT)
+    }
+
+    /// BX and BLX, and from ARMv5 loads to PC, may switch between the ARM and THUMB instruction
+    /// sets
+    fn interworks(&self) -> bool {
+        match self.op {
+            Op::BX | Op::BLX => true,
+            Op::LDR => self.operands.first() == Some(&Operand::Reg(Register::PC)),
+            _ if is_load_multiple(self.op) => self.operands.iter().any(|operand| {
+                matches!(operand, Operand::RegList(list) if list.regs.contains(Register::PC))
+            }),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::parsing::instruction;
+    use Register::*;
+
+    fn regs(regs: &[Register]) -> RegSet {
+        regs.iter().copied().collect()
+    }
+
+    /// Parses `asm` and checks its registers read/written and flags read/written
+    fn check(asm: &str, read: &[Register], written: &[Register], flags: (u32, u32)) {
+        let (_, instr) = instruction(asm).unwrap();
+        assert_eq!(instr.regs_read(), regs(read), "{asm} reads");
+        assert_eq!(instr.regs_written(), regs(written), "{asm} writes");
+        assert_eq!((instr.flags_read(), instr.flags_written()), flags, "{asm} flags");
+    }
+
+    #[test]
+    fn test_dataflow_regs() {
+        const NZCV: u32 = FLAG_N | FLAG_Z | FLAG_C | FLAG_V;
+        check("ADD r0, r1, r2, LSL r3", &[R1, R2, R3], &[R0], (0, 0));
+        check("ADDS r0, r1, #4", &[R1], &[R0, FLAGS], (0, NZCV));
+        check("ADDEQS r0, r1, #4", &[R1, FLAGS], &[R0, FLAGS], (NZCV, NZCV));
+        check("ADCNE r0, r0, r1", &[R0, R1, FLAGS], &[R0], (FLAG_Z | FLAG_C, 0));
+        check("CMP r0, r1", &[R0, R1], &[FLAGS], (0, NZCV));
+        check("MOVS r0, r1, RRX", &[R1, FLAGS], &[R0, FLAGS], (FLAG_C, FLAG_N | FLAG_Z | FLAG_C));
+        check("MULS r0, r1, r2", &[R1, R2], &[R0, FLAGS], (0, FLAG_N | FLAG_Z));
+        check("UMLAL r0, r1, r2, r3", &[R0, R1, R2, R3], &[R0, R1], (0, 0));
+        check("SMULL r0, r1, r2, r3", &[R2, R3], &[R0, R1], (0, 0));
+        check("QADD r0, r1, r2", &[R1, R2, FLAGS], &[R0, FLAGS], (FLAG_Q, FLAG_Q));
+        check("LDR r0, [r1, r2, LSL #2]!", &[R1, R2], &[R0, R1], (0, 0));
+        check("STRD r2, r3, [sp], #-8", &[R2, R3, SP], &[SP], (0, 0));
+        check("LDRD r2, r3, [r4]", &[R4], &[R2, R3], (0, 0));
+        check("SWP r0, r1, [r2]", &[R1, R2], &[R0], (0, 0));
+        check("PUSH {r4, lr}", &[R4, LR, SP], &[SP], (0, 0));
+        // Loads to PC and BX may switch to THUMB, which writes CPSR
+        check("POP {r4, pc}", &[SP], &[R4, PC, SP, FLAGS], (0, 0));
+        check("LDMIA r0!, {r1, r2}", &[R0], &[R0, R1, R2], (0, 0));
+        check("LDMIA sp, {r0, pc}^", &[SP], &[R0, PC, FLAGS], (0, FLAGS_ALL));
+        check("SUBS pc, lr, #4", &[LR], &[PC, FLAGS], (0, FLAGS_ALL));
+        check("BL #8", &[PC], &[LR, PC], (0, 0));
+        check("BL 0x00000100", &[PC], &[LR, PC], (0, 0));
+        check("B 0x00000100", &[], &[PC], (0, 0));
+        check("BX lr", &[LR], &[PC, FLAGS], (0, 0));
+        // Exceptions switch mode, writing CPSR and the new mode's LR
+        check("SVC #0", &[], &[LR, PC, FLAGS], (0, 0));
+        check("BKPT #1", &[], &[LR, PC, FLAGS], (0, 0));
+        check("LDR pc, [r0]", &[R0], &[PC, FLAGS], (0, 0));
+        check("MRS r0, CPSR", &[FLAGS], &[R0], (FLAGS_ALL, 0));
+        check("MSR CPSR_f, r0", &[R0], &[FLAGS], (0, FLAGS_ALL));
+        check("MSR CPSR_c, r0", &[R0], &[FLAGS], (0, 0));
+        check("MSR SPSR_fsxc, r0", &[R0], &[], (0, 0));
+        check("MRC p15, 0, r0, c1, c0, 0", &[], &[R0], (0, 0));
+        check("MRC p14, 0, pc, c1, c0, 0", &[], &[FLAGS], (0, NZCV));
+        check("MCR p15, 0, r0, c1, c0, 0", &[R0], &[], (0, 0));
+    }
+
+    #[test]
+    fn test_dataflow_memory_and_branches() {
+        let parse = |asm| instruction(asm).unwrap().1;
+        assert!(parse("LDR r0, [r1]").reads_memory());
+        assert!(!parse("LDR r0, [r1]").writes_memory());
+        assert!(parse("SWP r0, r1, [r2]").writes_memory());
+        assert!(parse("PUSH {r0}").accesses_memory());
+        assert!(!parse("PLD [r0]").accesses_memory());
+        assert!(!parse("ADD r0, r1, r2").accesses_memory());
+
+        assert!(parse("BEQ #8").changes_control_flow());
+        assert!(parse("MOV pc, lr").changes_control_flow());
+        assert!(parse("LDR pc, [r0], #4").changes_control_flow());
+        assert!(parse("LDR r0, [pc], #4").changes_control_flow());
+        assert!(parse("SVC #0").changes_control_flow());
+        assert!(parse("BLX r0").changes_control_flow());
+        assert!(parse("UMULL r0, pc, r1, r2").changes_control_flow());
+        assert!(parse("LDRD lr, pc, [r0]").changes_control_flow());
+        assert!(!parse("LDR r0, [pc, #4]").changes_control_flow());
+        assert!(!parse("UMULL r0, r1, pc, r2").changes_control_flow());
+        assert!(!parse("CMP pc, r0").changes_control_flow());
+        assert!(!parse("STMIA r0, {r1, pc}").changes_control_flow());
+        assert!(!parse("MRC p14, 0, pc, c1, c0, 0").changes_control_flow());
+    }
+}
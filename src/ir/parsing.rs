@@ -1,56 +1,133 @@
 use std::str::FromStr;
 
 use super::{
-    AddrMode, Address, Cond, ExtraOperand, ImmShift, Instruction, Offset, Op, Operand, Register,
-    Shift, ShiftOp,
+    AddrMode, Address, Cond, ExtraOperand, ImmShift, Instruction, Offset, Op, Operand, RegList,
+    RegSet, Register, Shift, ShiftOp, StatusReg, MAX_NUM_OPERANDS, PSR_MASK_C, PSR_MASK_F,
+    PSR_MASK_S, PSR_MASK_X,
 };
 use nom::{
     branch::alt,
     bytes::complete::{tag_no_case, take},
     character::complete::{
-        alphanumeric1, char as match_char, multispace0, multispace1, one_of, u32 as match_u32,
+        alphanumeric1, char as match_char, hex_digit1, multispace0, multispace1, one_of,
+        u32 as match_u32,
     },
-    combinator::{map, map_res, opt},
-    error::{context, VerboseError},
-    multi::separated_list1,
-    sequence::{terminated, tuple},
+    combinator::{eof, map, map_res, opt, value, verify},
+    error::{context, ErrorKind, ParseError, VerboseError},
+    multi::{fold_many0, separated_list0},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
 
+mod program;
+
+pub use program::{parse_program, Item, Program, ProgramError, ProgramErrorKind};
+
 pub type ParseResult<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
 
-fn cond(input: &str) -> ParseResult<Cond> {
+fn cond(input: &str) -> ParseResult<'_, Cond> {
     context("Cond", map_res(take(2usize), Cond::from_str))(input)
 }
 
-fn register(input: &str) -> ParseResult<Register> {
+fn register(input: &str) -> ParseResult<'_, Register> {
     context("Register", map_res(alphanumeric1, Register::from_str))(input)
 }
 
-fn shift_op(input: &str) -> ParseResult<ShiftOp> {
+fn shift_op(input: &str) -> ParseResult<'_, ShiftOp> {
     context("ShiftOp", map_res(take(3usize), ShiftOp::from_str))(input)
 }
 
-fn imm_val(i: &str) -> ParseResult<u32> {
-    let (i, (_, val)) = context("imm_val", tuple((match_char('#'), match_u32)))(i)?;
+/// Parses a decimal number, or a hexadecimal one with a "0x" prefix
+fn unsigned(i: &str) -> ParseResult<'_, u32> {
+    let hex = map_res(hex_digit1, |s| u32::from_str_radix(s, 16));
+    alt((preceded(tag_no_case("0x"), hex), match_u32))(i)
+}
+
+/// Parses a number which may be negative, wrapping it to its two's complement representation
+fn number(i: &str) -> ParseResult<'_, u32> {
+    alt((map(preceded(match_char('-'), unsigned), u32::wrapping_neg), unsigned))(i)
+}
+
+fn imm_val(i: &str) -> ParseResult<'_, u32> {
+    let (i, (_, val)) = context("imm_val", tuple((match_char('#'), number)))(i)?;
     Ok((i, val))
 }
 
-/// Parses the instruction mnemonic and the whitespace which must follow it, e.g. "ADDEQS "
-fn mnemonic(i: &str) -> ParseResult<(Op, Cond, bool)> {
+/// Parses the instruction mnemonic and the whitespace which follows it, e.g. "ADDEQS ". Splits
+/// without an "S" suffix are tried first, so that e.g. "BLS" is B with the LS condition rather than
+/// BL with the suffix
+fn mnemonic(i: &str) -> ParseResult<'_, (Op, Cond, bool)> {
     let op = |x: usize| map_res(take(x), Op::from_str);
-    let mnem = |x: usize| terminated(tuple((op(x), opt(cond), opt(one_of("sS")))), multispace1);
+    let end = || alt((multispace1, eof));
+    let plain =
+        |x: usize| map(terminated(tuple((op(x), opt(cond))), end()), |(o, c)| (o, c, false));
+    let with_s = |x: usize| {
+        map(terminated(tuple((op(x), opt(cond), one_of("sS"))), end()), |(o, c, _)| (o, c, true))
+    };
     // Have to go through every possible length of op until we succesfully parse everything, up to
     // the terminating whitespace
-    let mut parse = alt((mnem(8), mnem(7), mnem(6), mnem(5), mnem(4), mnem(3), mnem(2), mnem(1)));
-    let (i, (op, cond, s)) = parse(i)?;
-    Ok((i, (op, cond.unwrap_or(Cond::AL), s.is_some())))
+    let parse = alt((
+        alt((plain(8), plain(7), plain(6), plain(5), plain(4), plain(3), plain(2), plain(1))),
+        alt((
+            with_s(8),
+            with_s(7),
+            with_s(6),
+            with_s(5),
+            with_s(4),
+            with_s(3),
+            with_s(2),
+            with_s(1),
+        )),
+    ));
+    let (i, (op, cond, s)) = alt((parse, legacy_mnemonic))(i)?;
+    Ok((i, (op, cond.unwrap_or(Cond::AL), s)))
+}
+
+/// Parses the pre-UAL spellings of loads, stores and swaps, where the condition comes before the
+/// size or addressing mode suffix, e.g. "LDREQB" or "STMNEFD". The stack-oriented LDM/STM suffixes
+/// are mapped onto the equivalent addressing modes
+fn legacy_mnemonic(i: &str) -> ParseResult<'_, (Op, Option<Cond>, bool)> {
+    let (rest, word) = terminated(alphanumeric1, alt((multispace1, eof)))(i)?;
+    let word = word.to_ascii_uppercase();
+    let op = |base: &str, suffix: &str| {
+        let suffix = match (base, suffix) {
+            ("LDM", "FD") | ("STM", "EA") => "IA",
+            ("LDM", "ED") | ("STM", "FA") => "IB",
+            ("LDM", "FA") | ("STM", "ED") => "DA",
+            ("LDM", "EA") | ("STM", "FD") => "DB",
+            _ => suffix,
+        };
+        match base {
+            "LDR" | "STR" | "LDM" | "STM" | "SWP" if !suffix.is_empty() => {
+                Op::from_str(&format!("{base}{suffix}")).ok()
+            }
+            _ => None,
+        }
+    };
+    if word.len() > 3 {
+        let (base, tail) = word.split_at(3);
+        // Either the condition precedes the suffix, or it follows a stack-oriented suffix
+        if tail.len() > 2 {
+            let (cond, suffix) = tail.split_at(2);
+            if let (Ok(cond), Some(op)) = (Cond::from_str(cond), op(base, suffix)) {
+                return Ok((rest, (op, Some(cond), false)));
+            }
+            let (suffix, cond) = tail.split_at(tail.len() - 2);
+            if let (Some(op), Ok(cond)) = (op(base, suffix), Cond::from_str(cond)) {
+                return Ok((rest, (op, Some(cond), false)));
+            }
+        }
+        if let Some(op) = op(base, tail) {
+            return Ok((rest, (op, None, false)));
+        }
+    }
+    Err(nom::Err::Error(VerboseError::from_error_kind(i, ErrorKind::Verify)))
 }
 
 /// Parses an immediate shift, starting from the comma following a base register
 /// e.g. [r0, r1, lsl #123]!
 ///             ^--------^ parses this span
-fn imm_shift(i: &str) -> ParseResult<ImmShift> {
+fn imm_shift(i: &str) -> ParseResult<'_, ImmShift> {
     let (i, _) = match_char(',')(i)?;
     let (i, _) = multispace0(i)?;
     let (i, op) = shift_op(i)?;
@@ -60,7 +137,7 @@ fn imm_shift(i: &str) -> ParseResult<ImmShift> {
 }
 
 /// Isn't followed by another operand, unlike other shifts
-fn rrx_shift(i: &str) -> ParseResult<ImmShift> {
+fn rrx_shift(i: &str) -> ParseResult<'_, ImmShift> {
     let (i, _) = match_char(',')(i)?;
     let (i, _) = multispace0(i)?;
     let (i, op) = tag_no_case("RRX")(i)?;
@@ -70,7 +147,7 @@ fn rrx_shift(i: &str) -> ParseResult<ImmShift> {
 /// Parses a register shift, starting from the comma following a base register
 /// e.g. ADR r0, r1, r2, lsl r3
 ///                    ^------^ parses this span
-fn reg_shift(i: &str) -> ParseResult<Shift> {
+fn reg_shift(i: &str) -> ParseResult<'_, Shift> {
     let (i, _) = match_char(',')(i)?;
     let (i, _) = multispace0(i)?;
     let (i, op) = shift_op(i)?;
@@ -80,7 +157,7 @@ fn reg_shift(i: &str) -> ParseResult<Shift> {
 }
 
 /// Parse an index register offset with optional shift
-fn reg_offset(i: &str) -> ParseResult<Offset> {
+fn reg_offset(i: &str) -> ParseResult<'_, Offset> {
     let (i, neg) = opt(match_char('-'))(i)?;
     let (i, _) = multispace0(i)?;
     let (i, reg) = register(i)?;
@@ -89,12 +166,12 @@ fn reg_offset(i: &str) -> ParseResult<Offset> {
 }
 
 // Parse an immediate address offset value (signed 32-bit)
-fn imm_offset(i: &str) -> ParseResult<Offset> {
+fn imm_offset(i: &str) -> ParseResult<'_, Offset> {
     let (i, _) = match_char('#')(i)?;
     let (i, _) = multispace0(i)?;
     let (i, neg) = opt(match_char('-'))(i)?;
     let (i, _) = multispace0(i)?;
-    let (i, imm) = match_u32(i)?;
+    let (i, imm) = unsigned(i)?;
     Ok((i, Offset::imm(imm, neg.is_none())))
 }
 
@@ -102,7 +179,7 @@ fn imm_offset(i: &str) -> ParseResult<Offset> {
 /// mode
 /// e.g. [r0, r1, lsl #123]!
 ///         ^ -------------^ parses this span
-fn pre_offset(i: &str) -> ParseResult<(ExtraOperand, AddrMode)> {
+fn pre_offset(i: &str) -> ParseResult<'_, (ExtraOperand, AddrMode)> {
     let (i, _) = match_char(',')(i)?;
     let (i, _) = multispace0(i)?;
     let (i, offset) =
@@ -120,7 +197,7 @@ fn pre_offset(i: &str) -> ParseResult<(ExtraOperand, AddrMode)> {
 /// Parse a post-index offset, i.e. one appearing after the square brackets
 /// e.g. [r0], r1, ROR #32
 ///         ^------------^ parses this span
-fn post_offset(i: &str) -> ParseResult<(ExtraOperand, AddrMode)> {
+fn post_offset(i: &str) -> ParseResult<'_, (ExtraOperand, AddrMode)> {
     let (i, _) = match_char(']')(i)?;
     let (i, _) = multispace0(i)?;
     let (i, _) = match_char(',')(i)?;
@@ -130,7 +207,7 @@ fn post_offset(i: &str) -> ParseResult<(ExtraOperand, AddrMode)> {
     Ok((i, (offset, AddrMode::PostIndex)))
 }
 
-fn address(input: &str) -> ParseResult<(Address, Option<ExtraOperand>)> {
+fn address(input: &str) -> ParseResult<'_, (Address, Option<ExtraOperand>)> {
     let (i, _) = match_char('[')(input)?;
     let (i, _) = multispace0(i)?;
     let (i, base) = register(i)?;
@@ -147,7 +224,7 @@ fn address(input: &str) -> ParseResult<(Address, Option<ExtraOperand>)> {
     Ok((i, (Address { base, mode }, offset)))
 }
 
-fn shifted_reg(i: &str) -> ParseResult<(Register, Option<ExtraOperand>)> {
+fn shifted_reg(i: &str) -> ParseResult<'_, (Register, Option<ExtraOperand>)> {
     let shift = alt((
         map(reg_shift, ExtraOperand::from),
         map(imm_shift, ExtraOperand::from),
@@ -158,26 +235,116 @@ fn shifted_reg(i: &str) -> ParseResult<(Register, Option<ExtraOperand>)> {
     Ok((i, (reg, shift)))
 }
 
-fn operand(i: &str) -> ParseResult<(Operand, Option<ExtraOperand>)> {
-    let reg = map(shifted_reg, |(r, s)| (Operand::Reg(r), s.map(ExtraOperand::from)));
-    let addr = map(address, |(a, o)| (Operand::Addr(a), o.map(ExtraOperand::from)));
-    let imm = map(imm_val, |i| (Operand::Imm(i), None));
-    context("Operand", alt((reg, addr, imm)))(i)
+/// Parses a single register or an inclusive range of registers within a register list, e.g. "r4"
+/// or "r0-r3"
+fn reg_range(i: &str) -> ParseResult<'_, u32> {
+    let (i, start) = register(i)?;
+    let (i, end) = opt(preceded(tuple((multispace0, match_char('-'), multispace0)), register))(i)?;
+    let (start, end) = (start as u32, end.unwrap_or(start) as u32);
+    if end < start || end > 15 {
+        return Err(nom::Err::Error(VerboseError::from_error_kind(i, ErrorKind::Verify)));
+    }
+    let regs = (start..=end).fold(0, |acc, r| acc | (1 << r));
+    Ok((i, regs))
+}
+
+/// Parses a register list with an optional "^" suffix, e.g. "{r0-r3, lr}^". The list may be empty,
+/// as the (unpredictable) empty encodings still disassemble to one
+fn reg_list(i: &str) -> ParseResult<'_, RegList> {
+    let sep = tuple((multispace0, match_char(','), multispace0));
+    let (i, _) = match_char('{')(i)?;
+    let (i, _) = multispace0(i)?;
+    let (i, ranges) = separated_list0(sep, reg_range)(i)?;
+    let (i, _) = multispace0(i)?;
+    let (i, _) = match_char('}')(i)?;
+    let (i, user) = opt(match_char('^'))(i)?;
+    let regs = ranges.iter().fold(0, |acc, r| acc | r);
+    Ok((i, RegList { regs: RegSet::new(regs), user: user.is_some() }))
+}
+
+fn status_reg(i: &str) -> ParseResult<'_, StatusReg> {
+    context("StatusReg", map_res(take(4usize), StatusReg::from_str))(i)
+}
+
+/// Parses an MSR destination, i.e. a status register followed by its field mask, e.g. "CPSR_fc"
+fn psr_fields(i: &str) -> ParseResult<'_, (StatusReg, u32)> {
+    let field = alt((
+        value(PSR_MASK_F, one_of("fF")),
+        value(PSR_MASK_S, one_of("sS")),
+        value(PSR_MASK_X, one_of("xX")),
+        value(PSR_MASK_C, one_of("cC")),
+    ));
+    let (i, reg) = status_reg(i)?;
+    let (i, _) = match_char('_')(i)?;
+    let (i, mask) = fold_many0(field, || 0, |acc, f| acc | f)(i)?;
+    Ok((i, (reg, mask)))
+}
+
+fn coproc(i: &str) -> ParseResult<'_, u32> {
+    context("Coproc", preceded(one_of("pP"), match_u32))(i)
+}
+
+fn coproc_reg(i: &str) -> ParseResult<'_, u32> {
+    context("CoprocReg", preceded(one_of("cC"), match_u32))(i)
+}
+
+fn coproc_option(i: &str) -> ParseResult<'_, u32> {
+    let (i, _) = match_char('{')(i)?;
+    let (i, option) = delimited(multispace0, match_u32, multispace0)(i)?;
+    let (i, _) = match_char('}')(i)?;
+    Ok((i, option))
+}
+
+fn target(i: &str) -> ParseResult<'_, u32> {
+    let hex = map_res(hex_digit1, |s| u32::from_str_radix(s, 16));
+    context("Target", preceded(tag_no_case("0x"), hex))(i)
+}
+
+fn operand(i: &str) -> ParseResult<'_, (Operand, Option<ExtraOperand>)> {
+    let reg_wb = map(terminated(register, match_char('!')), |r| (Operand::RegWb(r), None));
+    let reg = map(shifted_reg, |(r, s)| (Operand::Reg(r), s));
+    let addr = map(address, |(a, o)| (Operand::Addr(a), o));
+    // An explicitly rotated immediate, e.g. "#4, ROR #2"
+    let imm =
+        map(pair(imm_val, opt(imm_shift)), |(i, s)| (Operand::Imm(i), s.map(ExtraOperand::from)));
+    let list = map(reg_list, |l| (Operand::RegList(l), None));
+    let fields = map(psr_fields, |(r, m)| (Operand::PsrFields(r, m), None));
+    let psr = map(status_reg, |r| (Operand::Psr(r), None));
+    let coproc = map(coproc, |c| (Operand::Coproc(c), None));
+    let coproc_reg = map(coproc_reg, |c| (Operand::CoprocReg(c), None));
+    let target = map(target, |t| (Operand::Target(t), None));
+    let opcode = map(match_u32, |o| (Operand::CoprocOpcode(o), None));
+    let option = map(coproc_option, |o| (Operand::CoprocOption(o), None));
+    context(
+        "Operand",
+        alt((
+            reg_wb, reg, addr, imm, list, fields, psr, coproc, coproc_reg, target, opcode, option,
+        )),
+    )(i)
 }
 
 /// Parses a single ARM instruction (in UAL syntax) into structured format
-pub fn instruction(i: &str) -> ParseResult<Instruction> {
+pub fn instruction(i: &str) -> ParseResult<'_, Instruction> {
     let (i, (op, cond, mut set_flags)) = mnemonic(i)?;
 
     if [Op::TEQ, Op::TST, Op::CMN, Op::CMP].contains(&op) {
         set_flags = true;
     }
+    // LDM/STM without an addressing mode suffix are increment-after
+    let op = match op {
+        Op::LDM => Op::LDMIA,
+        Op::STM => Op::STMIA,
+        _ => op,
+    };
 
     let sep = tuple((multispace0, match_char(','), multispace0));
 
-    // NOTE - issue with parsing: currently having more than 1 extra operand is considered a valid
-    // parse. Not sure if that can be detected
-    let (i, res) = separated_list1(sep, operand)(i)?;
+    // An instruction has at most one shift or offset, e.g. "ADD r0, r1, r2, LSL #1, r3, LSL #2"
+    // is rejected rather than silently dropping the second shift
+    let fits = |res: &Vec<(Operand, Option<ExtraOperand>)>| {
+        res.len() <= MAX_NUM_OPERANDS && res.iter().filter(|x| x.1.is_some()).count() <= 1
+    };
+    let (i, res) = context("Operands", verify(separated_list0(sep, operand), fits))(i)?;
     let operands = res.iter().map(|x| x.0).collect();
     let extra = res.iter().map(|x| x.1).find(Option::is_some).flatten();
 
@@ -187,7 +354,14 @@ pub fn instruction(i: &str) -> ParseResult<Instruction> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::disasm::{
+        disassemble_arm, disassemble_arm_at, disassemble_thumb, disassemble_thumb_at,
+        disassemble_thumb_bl, is_thumb_bl_prefix, Arch, DisasmError,
+    };
+    use crate::encode::{validate_arm_at, EncodeError};
     use crate::ir::{Cond::*, Op::*, Operand::*, Register::*};
+    use crate::operands;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
 
     #[test]
     fn test_parse_mnemonic() {
@@ -195,6 +369,21 @@ mod tests {
         assert_eq!(mnemonic("MLSLS REST"), Ok(("REST", (Op::MLS, Cond::LS, false))));
         assert_eq!(mnemonic("MLSLSS ..."), Ok(("...", (Op::MLS, Cond::LS, true))));
         assert_eq!(mnemonic("LDRHI "), Ok(("", (Op::LDR, Cond::HI, false))));
+        assert_eq!(mnemonic("BLS lr"), Ok(("lr", (Op::B, Cond::LS, false))));
+        assert_eq!(mnemonic("STCLS p1"), Ok(("p1", (Op::STC, Cond::LS, false))));
+        assert_eq!(mnemonic("SMLALS r0"), Ok(("r0", (Op::SMLAL, Cond::AL, true))));
+        assert_eq!(mnemonic("NOP"), Ok(("", (Op::NOP, Cond::AL, false))));
+        assert_eq!(mnemonic("EORS r0"), Ok(("r0", (Op::EOR, Cond::AL, true))));
+        assert_eq!(mnemonic("SBCS r0"), Ok(("r0", (Op::SBC, Cond::AL, true))));
+        // Pre-UAL spellings
+        assert_eq!(mnemonic("ldreqb r0"), Ok(("r0", (Op::LDRB, Cond::EQ, false))));
+        assert!(mnemonic("STRNESH r0").is_err());
+        assert_eq!(mnemonic("LDRLTSH r0"), Ok(("r0", (Op::LDRSH, Cond::LT, false))));
+        assert_eq!(mnemonic("stmfd sp!"), Ok(("sp!", (Op::STMDB, Cond::AL, false))));
+        assert_eq!(mnemonic("LDMFD sp!"), Ok(("sp!", (Op::LDMIA, Cond::AL, false))));
+        assert_eq!(mnemonic("LDMNEEA r0"), Ok(("r0", (Op::LDMDB, Cond::NE, false))));
+        assert_eq!(mnemonic("STMEDGT r0"), Ok(("r0", (Op::STMDA, Cond::GT, false))));
+        assert_eq!(mnemonic("SWPCSB r0"), Ok(("r0", (Op::SWPB, Cond::CS, false))));
     }
 
     #[test]
@@ -282,13 +471,19 @@ mod tests {
 
     #[test]
     fn test_parse_instr() {
+        // The parser only checks syntax, it's validation which rejects the out of range shift
         let (_, instr) = instruction("LDRLE r0, [r1, r2, LSL #92]!").unwrap();
+        let shift = ImmShift { op: ShiftOp::LSL, imm: 92 };
+        assert_eq!(
+            validate_arm_at(0, &instr, Arch::ARMv5TE),
+            Err(EncodeError::ShiftOutOfRange { op: LDR, shift })
+        );
         assert_eq!(
             instr,
             Instruction {
                 cond: LE,
                 op: LDR,
-                operands: vec![
+                operands: operands![
                     Reg(R0),
                     Addr(Address { base: R1, mode: AddrMode::PreIndex },),
                 ],
@@ -304,7 +499,7 @@ mod tests {
             Instruction {
                 cond: HI,
                 op: UMAAL,
-                operands: vec![Reg(R0), Reg(R1), Reg(LR), Reg(SP)],
+                operands: operands![Reg(R0), Reg(R1), Reg(LR), Reg(SP)],
                 extra: None,
                 set_flags: false,
             }
@@ -315,21 +510,174 @@ mod tests {
             Instruction {
                 cond: AL,
                 op: ADD,
-                operands: vec![Reg(R1), Reg(R2), Operand::Imm(9393)],
+                operands: operands![Reg(R1), Reg(R2), Operand::Imm(9393)],
                 extra: None,
                 set_flags: true,
             }
         );
+        let (_, instr) = instruction("LDMIANE r0!, {r1-r3, LR}^").unwrap();
+        assert_eq!(
+            instr,
+            Instruction {
+                cond: NE,
+                op: LDMIA,
+                operands: operands![
+                    RegWb(R0),
+                    RegList(crate::ir::RegList { regs: RegSet::new(0x400e), user: true })
+                ],
+                extra: None,
+                set_flags: false,
+            }
+        );
+        let (_, instr) = instruction("STM sp, { r0 , r2-r3 }").unwrap();
+        assert_eq!(
+            instr,
+            Instruction {
+                cond: AL,
+                op: STMIA,
+                operands: operands![Reg(SP), RegList(crate::ir::RegList::new(0b1101))],
+                extra: None,
+                set_flags: false,
+            }
+        );
+        let (_, instr) = instruction("PUSH {r4-r11, lr}").unwrap();
+        assert_eq!(
+            instr,
+            Instruction {
+                cond: AL,
+                op: PUSH,
+                operands: operands![RegList(crate::ir::RegList::new(0x4ff0))],
+                extra: None,
+                set_flags: false,
+            }
+        );
+        assert!(reg_list("{r3-r1}").is_err());
+        assert!(instruction("ADD r0, r1, LSL #1, r2, LSL #2").is_err());
+        assert!(instruction("LDR r0, [r1], r2, r3, LSL #1").is_err());
+        assert!(instruction("CDP p1, 2, c3, c4, c5, 6, 7").is_err());
+        let (_, instr) = instruction("MSRNE spsr_cxf, r3").unwrap();
+        assert_eq!(
+            instr,
+            Instruction {
+                cond: NE,
+                op: MSR,
+                operands: operands![PsrFields(StatusReg::SPSR, 0b1011), Reg(R3)],
+                extra: None,
+                set_flags: false,
+            }
+        );
+        let (_, instr) = instruction("MRS r0, CPSR").unwrap();
+        assert_eq!(
+            instr,
+            Instruction {
+                cond: AL,
+                op: MRS,
+                operands: operands![Reg(R0), Psr(StatusReg::CPSR)],
+                extra: None,
+                set_flags: false,
+            }
+        );
+        let (_, instr) = instruction("MCR p15, 0, r0, c1, c0, 0").unwrap();
+        assert_eq!(
+            instr,
+            Instruction {
+                cond: AL,
+                op: MCR,
+                operands: operands![
+                    Coproc(15),
+                    CoprocOpcode(0),
+                    Reg(R0),
+                    CoprocReg(1),
+                    CoprocReg(0),
+                    CoprocOpcode(0)
+                ],
+                extra: None,
+                set_flags: false,
+            }
+        );
+        let (_, instr) = instruction("LDCL p14, c5, [r0], {8}").unwrap();
+        assert_eq!(
+            instr,
+            Instruction {
+                cond: AL,
+                op: LDCL,
+                operands: operands![
+                    Coproc(14),
+                    CoprocReg(5),
+                    Addr(Address { base: R0, mode: AddrMode::Offset }),
+                    CoprocOption(8)
+                ],
+                extra: None,
+                set_flags: false,
+            }
+        );
         let (_, instr) = instruction("ADDS r1, r2, r3, RRX").unwrap();
         assert_eq!(
             instr,
             Instruction {
                 cond: AL,
                 op: ADD,
-                operands: vec![Reg(R1), Reg(R2), Reg(R3)],
+                operands: operands![Reg(R1), Reg(R2), Reg(R3)],
                 extra: Some(ImmShift { imm: 1, op: ShiftOp::RRX }.into()),
                 set_flags: true,
             }
         );
     }
+
+    #[test]
+    fn test_parse_legacy_syntax() {
+        let parse = |text| instruction(text).map(|(rest, instr)| (rest, instr.operands[1]));
+        assert_eq!(parse("mov r0, #0x04000000"), Ok(("", Imm(0x0400_0000))));
+        assert_eq!(parse("MOV r0, #0XfF"), Ok(("", Imm(0xFF))));
+        assert_eq!(parse("cmp r0, #-1"), Ok(("", Imm(0xFFFF_FFFF))));
+        assert_eq!(parse("mvn r0, #-0x10"), Ok(("", Imm(0xFFFF_FFF0))));
+        let (_, instr) = instruction("ldr r0, [r1, #-0x10]").unwrap();
+        assert_eq!(instr.extra, Some(Offset::imm(0x10, false).into()));
+
+        let same = |legacy, ual| assert_eq!(instruction(legacy), instruction(ual), "{legacy}");
+        same("ldreqb r0, [r1], #1", "ldrbeq r0, [r1], #1");
+        same("strneh r2, [r3]", "strhne r2, [r3]");
+        same("stmfd sp!, {r4-r6, lr}", "stmdb sp!, {r4-r6, lr}");
+        same("ldmfd sp!, {r4-r6, pc}", "ldmia sp!, {r4-r6, pc}");
+        same("ldmeqfd sp!, {r0}", "ldmiaeq sp!, {r0}");
+        same("stmfdeq sp!, {r0}", "stmdbeq sp!, {r0}");
+        same("swpneb r0, r1, [r2]", "swpbne r0, r1, [r2]");
+    }
+
+    fn assert_round_trip(result: Result<Instruction, DisasmError>) {
+        let instr = match result {
+            Ok(instr) => instr,
+            Err(DisasmError::Unpredictable { decoded, .. }) => *decoded,
+            Err(_) => return,
+        };
+        let text = instr.to_string();
+        assert_eq!(instruction(&text), Ok(("", instr)), "{text}");
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        // Everything the disassembler produces must print as text which parses back to the same
+        // instruction. The seed is fixed so that a failure can be reproduced
+        let mut rng = StdRng::seed_from_u64(0);
+        for arch in [Arch::ARMv4T, Arch::ARMv5TE] {
+            for selector in 0..=u16::MAX as u32 {
+                let cond = (selector >> 12) << 28;
+                let op1 = ((selector >> 4) & 0xFF) << 20;
+                let op2 = (selector & 0xF) << 4;
+                for _ in 0..2 {
+                    let encoding = cond | op1 | op2 | (rng.gen::<u32>() & 0x000F_FF0F);
+                    assert_round_trip(disassemble_arm(encoding, arch));
+                    assert_round_trip(disassemble_arm_at(rng.gen::<u32>() & !0b11, encoding, arch));
+                }
+            }
+            for encoding in 0..=u16::MAX {
+                assert_round_trip(disassemble_thumb(encoding, arch));
+                assert_round_trip(disassemble_thumb_at(rng.gen::<u32>() & !0b1, encoding, arch));
+                if is_thumb_bl_prefix(encoding) {
+                    let suffix = 0xF800 | rng.gen::<u16>() & 0x7FF;
+                    assert_round_trip(disassemble_thumb_bl(encoding, suffix, arch));
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,706 @@
+//! Parser for whole assembly source files, as used for the test programs. Each line holds any
+//! number of labels ("loop:"), followed by an instruction or a directive, and an optional comment
+//! starting with "@" or ";". Lines starting with "#" are also comments.
+//!
+//! The supported directives are:
+//! - `.arm` and `.thumb`, which select the instruction set of the instructions which follow
+//! - `.word`, `.hword` and `.byte`, followed by a comma separated list of values
+//! - `.org ADDR`, which moves on to an absolute address
+//! - `.align N`, which aligns to a multiple of 2^N bytes (4 bytes without N)
+//! - `.ltorg` (or `.pool`), which places the pending literal pool
+//!
+//! Branches, `adr` and PC-relative loads may name a label instead of giving the target address,
+//! and data values may be numbers (decimal, or hexadecimal with a 0x prefix, optionally negated) or
+//! labels. `ldr rX, =value` loads a value from the literal pool, which is placed at the next
+//! `.ltorg` or at the end of the program.
+//!
+//! ARM instructions are checked to be encodable, and to decode without being UNPREDICTABLE on the
+//! architecture the program is parsed for, so that mistakes in a program are reported rather than
+//! giving surprising results when it's run. THUMB instructions can't be checked yet, as there is no
+//! THUMB encoder, so their addresses are listed in [Program::unvalidated] instead.
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+
+use nom::{
+    bytes::complete::{take_while, take_while1},
+    character::complete::char as match_char,
+    combinator::{eof, recognize},
+    error::VerboseError,
+    sequence::{pair, terminated},
+    Offset,
+};
+
+use super::{instruction, mnemonic, number, register, ParseResult};
+use crate::disasm::{Arch, InstrSet};
+use crate::encode::{validate_arm_at, EncodeError};
+use crate::ir::{Instruction, Op, Operand};
+use crate::operands;
+
+/// An instruction or data at an address in a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Instruction {
+        addr: u32,
+        instr_set: InstrSet,
+        instr: Instruction,
+    },
+    Data {
+        addr: u32,
+        bytes: Vec<u8>,
+    },
+}
+
+/// The items of a program in source order, and the address of each label
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    pub items: Vec<Item>,
+    pub labels: HashMap<String, u32>,
+    /// Addresses of the instructions which weren't validated, i.e. the THUMB ones
+    pub unvalidated: Vec<u32>,
+}
+
+impl Program {
+    /// The instructions of the program, in source order
+    pub fn instructions(&self) -> impl Iterator<Item = (u32, InstrSet, &Instruction)> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Instruction { addr, instr_set, instr } => Some((*addr, *instr_set, instr)),
+            Item::Data { .. } => None,
+        })
+    }
+}
+
+/// An error from parsing a program, pointing at the offending part of a source line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramError {
+    /// Line number, counted from 1
+    pub line: usize,
+    /// Column number in characters, counted from 1
+    pub column: usize,
+    /// The whole source line, which is shown with a caret under the column
+    pub source_line: String,
+    pub kind: ProgramErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgramErrorKind {
+    /// Text which doesn't parse as an instruction, pointing at the furthest the parser reached
+    Syntax,
+    /// Text following a complete instruction
+    TrailingInput {
+        text: String,
+    },
+    UnknownDirective {
+        name: String,
+    },
+    /// A directive argument which isn't a number or label, or is out of range
+    InvalidValue {
+        text: String,
+    },
+    UndefinedLabel {
+        label: String,
+    },
+    DuplicateLabel {
+        label: String,
+    },
+    /// An `.org` to an address before the current one
+    OrgBackwards {
+        addr: u32,
+    },
+    /// A literal load which can't reach the pool holding its value at `addr`
+    LiteralOutOfRange {
+        addr: u32,
+    },
+    /// An ARM instruction which can't be encoded, or whose encoding is UNPREDICTABLE
+    Invalid {
+        error: EncodeError,
+    },
+}
+
+impl Display for ProgramErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Syntax => write!(f, "invalid syntax"),
+            Self::TrailingInput { text } => write!(f, "unexpected '{}'", text),
+            Self::UnknownDirective { name } => write!(f, "unknown directive '.{}'", name),
+            Self::InvalidValue { text } => write!(f, "invalid value '{}'", text),
+            Self::UndefinedLabel { label } => write!(f, "undefined label '{}'", label),
+            Self::DuplicateLabel { label } => write!(f, "label '{}' is already defined", label),
+            Self::OrgBackwards { addr } => {
+                write!(f, ".org {:#x} is before the current address", addr)
+            }
+            Self::LiteralOutOfRange { addr } => write!(f, "literal at {:#x} is out of range", addr),
+            Self::Invalid { error } => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Display for ProgramError {
+    /// Formats the error followed by the source line, with a caret under the column, e.g.
+    ///
+    /// ```text
+    /// line 2, column 7: undefined label 'nowhere'
+    ///     b nowhere
+    ///       ^
+    /// ```
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Keep tabs so that the caret lines up however they're displayed
+        let indent: String = self
+            .source_line
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        writeln!(f, "line {}, column {}: {}", self.line, self.column, self.kind)?;
+        writeln!(f, "{}", self.source_line)?;
+        write!(f, "{}^", indent)
+    }
+}
+
+impl Error for ProgramError {}
+
+type ProgramResult<T> = Result<T, ProgramError>;
+
+/// A line of the source, which errors point into
+#[derive(Clone, Copy)]
+struct Line<'a> {
+    num: usize,
+    text: &'a str,
+}
+
+impl Line<'_> {
+    /// Creates an error pointing at `at`, which must be a slice of this line
+    fn error(&self, at: &str, kind: ProgramErrorKind) -> ProgramError {
+        let offset = self.text.offset(at);
+        ProgramError {
+            line: self.num,
+            column: self.text[..offset].chars().count() + 1,
+            source_line: self.text.to_string(),
+            kind,
+        }
+    }
+
+    /// Creates an error from a failure to parse `text`, which must be a slice of this line
+    fn syntax_error(&self, text: &str, err: nom::Err<VerboseError<&str>>) -> ProgramError {
+        let at = match err {
+            nom::Err::Error(e) | nom::Err::Failure(e) => {
+                e.errors.into_iter().map(|(i, _)| i).min_by_key(|i| i.len())
+            }
+            nom::Err::Incomplete(_) => None,
+        };
+        self.error(at.unwrap_or(text), ProgramErrorKind::Syntax)
+    }
+}
+
+/// A line which produces an item, once the labels are known
+enum Statement<'a> {
+    Instruction(&'a str),
+    /// A literal load, and the index of the pool slot holding its value
+    Literal {
+        text: &'a str,
+        slot: usize,
+    },
+    Data {
+        size: usize,
+        values: Vec<&'a str>,
+    },
+}
+
+type Statements<'a> = Vec<(Line<'a>, u32, InstrSet, Statement<'a>)>;
+
+/// The values of literal loads, which are gathered until the pool is placed
+#[derive(Default)]
+struct LiteralPool<'a> {
+    /// Address of each slot, which is only known once its pool is placed
+    addrs: Vec<u32>,
+    /// The slot, value and line of each literal which hasn't been placed yet
+    pending: Vec<(usize, &'a str, Line<'a>)>,
+}
+
+impl<'a> LiteralPool<'a> {
+    /// Returns the slot for a value, sharing slots between identical values within a pool
+    fn add(&mut self, value: &'a str, line: Line<'a>) -> usize {
+        if let Some(&(slot, ..)) = self.pending.iter().find(|(_, v, _)| *v == value) {
+            return slot;
+        }
+        self.addrs.push(0);
+        self.pending.push((self.addrs.len() - 1, value, line));
+        self.addrs.len() - 1
+    }
+
+    /// Places the pending values as words at `addr`, returning the address following the pool
+    fn place(&mut self, statements: &mut Statements<'a>, addr: u32, instr_set: InstrSet) -> u32 {
+        if self.pending.is_empty() {
+            return addr;
+        }
+        let mut addr = addr.wrapping_add(3) & !3;
+        for (slot, value, line) in self.pending.drain(..) {
+            self.addrs[slot] = addr;
+            let data = Statement::Data { size: 4, values: vec![value] };
+            statements.push((line, addr, instr_set, data));
+            addr = addr.wrapping_add(4);
+        }
+        addr
+    }
+}
+
+fn label_name(i: &str) -> ParseResult<'_, &str> {
+    let start = |c: char| c.is_ascii_alphabetic() || c == '_' || c == '.';
+    let rest = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$';
+    recognize(pair(take_while1(start), take_while(rest)))(i)
+}
+
+fn is_label_name(s: &str) -> bool {
+    terminated(label_name, eof)(s).is_ok()
+}
+
+/// Strips a trailing comment
+fn strip_comment(line: &str) -> &str {
+    if line.trim_start().starts_with('#') {
+        return "";
+    }
+    match line.find(['@', ';']) {
+        Some(end) => &line[..end],
+        None => line,
+    }
+}
+
+/// Size in bytes of an instruction, which is needed to lay out the program before its labels are
+/// known. THUMB BL and BLX (immediate) are a pair of halfwords
+fn instruction_size(text: &str, instr_set: InstrSet, line: Line) -> ProgramResult<u32> {
+    let (operands, (op, _, _)) = mnemonic(text).map_err(|e| line.syntax_error(text, e))?;
+    let is_reg = terminated(register, eof)(operands.trim()).is_ok();
+    Ok(match instr_set {
+        InstrSet::ARM => 4,
+        InstrSet::THUMB if matches!(op, Op::BL | Op::BLX) && !is_reg => 4,
+        InstrSet::THUMB => 2,
+    })
+}
+
+fn is_register(s: &str) -> bool {
+    terminated(register, eof)(s).is_ok()
+}
+
+/// Returns the value of a literal load, e.g. "0x04000000" in "ldr r0, =0x04000000"
+fn literal_value(text: &str) -> Option<&str> {
+    let (operands, (op, _, _)) = mnemonic(text).ok()?;
+    let (_, value) = operands.split_once('=')?;
+    (op == Op::LDR).then(|| value.trim())
+}
+
+/// Builds an instruction whose operands are an optional register followed by a target address
+fn with_target(text: &str, reg: Option<&str>, addr: u32, line: Line) -> ProgramResult<Instruction> {
+    let (_, (op, cond, set_flags)) = mnemonic(text).map_err(|e| line.syntax_error(text, e))?;
+    let operands = match reg {
+        None => operands![Operand::Target(addr)],
+        Some(reg) => {
+            let (_, reg) = terminated(register, eof)(reg).map_err(|e| line.syntax_error(reg, e))?;
+            operands![Operand::Reg(reg), Operand::Target(addr)]
+        }
+    };
+    Ok(Instruction { op, cond, set_flags, operands, ..Default::default() })
+}
+
+fn parse_instruction(
+    text: &str,
+    labels: &HashMap<String, u32>,
+    line: Line,
+) -> ProgramResult<Instruction> {
+    let (operands, (op, _, _)) = mnemonic(text).map_err(|e| line.syntax_error(text, e))?;
+    // Branches take a lone label, while ADR and PC-relative loads take a register and then a label
+    let (reg, target) = match operands.rsplit_once(',') {
+        Some((reg, target))
+            if matches!(op, Op::ADR | Op::LDR | Op::LDRB | Op::LDRH | Op::LDRSB | Op::LDRSH) =>
+        {
+            (Some(reg.trim()), target.trim())
+        }
+        None if matches!(op, Op::B | Op::BL | Op::BLX) => (None, operands.trim()),
+        _ => return parse_full_instruction(text, line),
+    };
+    if !is_label_name(target) {
+        return parse_full_instruction(text, line);
+    }
+    match labels.get(target) {
+        Some(&addr) => with_target(text, reg, addr, line),
+        // e.g. BLX Rm names a register rather than a label
+        None if is_register(target) => parse_full_instruction(text, line),
+        None => {
+            let label = target.to_string();
+            Err(line.error(target, ProgramErrorKind::UndefinedLabel { label }))
+        }
+    }
+}
+
+/// Parses a literal load of a value placed at `slot_addr`, checking that the slot is in range
+fn parse_literal(
+    text: &str,
+    addr: u32,
+    instr_set: InstrSet,
+    slot_addr: u32,
+    line: Line,
+) -> ProgramResult<Instruction> {
+    let syntax = |at| line.error(at, ProgramErrorKind::Syntax);
+    let (before, value) = text.split_once('=').ok_or_else(|| syntax(text))?;
+    let (operands, _) = mnemonic(before).map_err(|e| line.syntax_error(before, e))?;
+    let reg = operands
+        .trim()
+        .strip_suffix(',')
+        .ok_or_else(|| syntax(value))?
+        .trim_end();
+    let offset = match instr_set {
+        InstrSet::ARM => slot_addr.wrapping_sub(addr.wrapping_add(8)) as i32,
+        InstrSet::THUMB => slot_addr.wrapping_sub(addr.wrapping_add(4) & !3) as i32,
+    };
+    let in_range = match instr_set {
+        InstrSet::ARM => (-4095..=4095).contains(&offset),
+        InstrSet::THUMB => (0..=1020).contains(&offset),
+    };
+    if !in_range {
+        let kind = ProgramErrorKind::LiteralOutOfRange { addr: slot_addr };
+        return Err(line.error(value.trim_start(), kind));
+    }
+    with_target(before, Some(reg), slot_addr, line)
+}
+
+/// Parses an instruction written out in full, which must take up the whole of `text`
+fn parse_full_instruction(text: &str, line: Line) -> ProgramResult<Instruction> {
+    match instruction(text) {
+        Ok((rest, instr)) if rest.trim().is_empty() => Ok(instr),
+        Ok((rest, _)) => {
+            let rest = rest.trim();
+            // The operand list stops at the first operand which doesn't parse
+            if let Some(operand) = rest.strip_prefix(',') {
+                return Err(line.error(operand.trim_start(), ProgramErrorKind::Syntax));
+            }
+            Err(line.error(rest, ProgramErrorKind::TrailingInput { text: rest.to_string() }))
+        }
+        Err(e) => Err(line.syntax_error(text, e)),
+    }
+}
+
+/// Evaluates a directive argument, which is a number or label
+fn value(text: &str, labels: &HashMap<String, u32>, line: Line) -> ProgramResult<u32> {
+    if is_label_name(text) {
+        let label = text.to_string();
+        return labels
+            .get(text)
+            .copied()
+            .ok_or_else(|| line.error(text, ProgramErrorKind::UndefinedLabel { label }));
+    }
+    match terminated(number, eof)(text) {
+        Ok((_, value)) => Ok(value),
+        Err(_) => Err(line.error(text, ProgramErrorKind::InvalidValue { text: text.to_string() })),
+    }
+}
+
+/// Parses a program whose first item is at `base`, validating its ARM instructions for `arch`
+pub fn parse_program(src: &str, base: u32, arch: Arch) -> ProgramResult<Program> {
+    let mut program = Program::default();
+    let mut statements = vec![];
+    let mut addr = base;
+    let mut instr_set = InstrSet::ARM;
+    let mut pool = LiteralPool::default();
+
+    // Lay out the program and find the labels
+    for (i, line) in src.lines().enumerate() {
+        let mut text = strip_comment(line).trim();
+        let line = Line { num: i + 1, text: line };
+        while let Ok((rest, label)) = terminated(label_name, match_char(':'))(text) {
+            if program.labels.insert(label.to_string(), addr).is_some() {
+                let kind = ProgramErrorKind::DuplicateLabel { label: label.to_string() };
+                return Err(line.error(label, kind));
+            }
+            text = rest.trim_start();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let Some(directive) = text.strip_prefix('.') else {
+            let statement = match literal_value(text) {
+                Some(value) => Statement::Literal { text, slot: pool.add(value, line) },
+                None => Statement::Instruction(text),
+            };
+            statements.push((line, addr, instr_set, statement));
+            addr = addr.wrapping_add(instruction_size(text, instr_set, line)?);
+            continue;
+        };
+        let (name, args) = directive
+            .split_once(char::is_whitespace)
+            .unwrap_or((directive, ""));
+        let args = args.trim();
+        match name.to_lowercase().as_str() {
+            "arm" => instr_set = InstrSet::ARM,
+            "thumb" => instr_set = InstrSet::THUMB,
+            "word" | "hword" | "byte" => {
+                let size = match name.to_lowercase().as_str() {
+                    "word" => 4,
+                    "hword" => 2,
+                    _ => 1,
+                };
+                let values: Vec<_> = args.split(',').map(str::trim).collect();
+                let len = (size * values.len()) as u32;
+                statements.push((line, addr, instr_set, Statement::Data { size, values }));
+                addr = addr.wrapping_add(len);
+            }
+            "org" => {
+                let org = value(args, &program.labels, line)?;
+                if org < addr {
+                    return Err(line.error(args, ProgramErrorKind::OrgBackwards { addr: org }));
+                }
+                addr = org;
+            }
+            "align" => {
+                let align = match args {
+                    "" => 2,
+                    _ => value(args, &program.labels, line)?,
+                };
+                if align >= 32 {
+                    let kind = ProgramErrorKind::InvalidValue { text: args.to_string() };
+                    return Err(line.error(args, kind));
+                }
+                let mask = (1 << align) - 1;
+                addr = addr.wrapping_add(mask) & !mask;
+            }
+            "ltorg" | "pool" => addr = pool.place(&mut statements, addr, instr_set),
+            _ => {
+                let kind = ProgramErrorKind::UnknownDirective { name: name.to_string() };
+                return Err(line.error(name, kind));
+            }
+        }
+    }
+    pool.place(&mut statements, addr, instr_set);
+
+    // Then parse each statement, now that every label has an address
+    for (line, addr, instr_set, statement) in statements {
+        let item = match statement {
+            Statement::Instruction(text) | Statement::Literal { text, .. } => {
+                let instr = match statement {
+                    Statement::Literal { slot, .. } => {
+                        parse_literal(text, addr, instr_set, pool.addrs[slot], line)?
+                    }
+                    _ => parse_instruction(text, &program.labels, line)?,
+                };
+                match instr_set {
+                    InstrSet::ARM => {
+                        validate_arm_at(addr, &instr, arch).map_err(|error| {
+                            line.error(text, ProgramErrorKind::Invalid { error })
+                        })?;
+                    }
+                    InstrSet::THUMB => program.unvalidated.push(addr),
+                }
+                Item::Instruction { addr, instr_set, instr }
+            }
+            Statement::Data { size, values } => {
+                let mut bytes = vec![];
+                for text in values {
+                    let value = value(text, &program.labels, line)?;
+                    // Values must fit in the size either as unsigned or signed numbers
+                    let bits = 8 * size as u32;
+                    let fits =
+                        bits == 32 || value >> bits == 0 || (value as i32) >> (bits - 1) == -1;
+                    if !fits {
+                        let kind = ProgramErrorKind::InvalidValue { text: text.to_string() };
+                        return Err(line.error(text, kind));
+                    }
+                    bytes.extend(&value.to_le_bytes()[..size]);
+                }
+                Item::Data { addr, bytes }
+            }
+        };
+        program.items.push(item);
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_program, Item, ProgramErrorKind};
+    use crate::disasm::{Arch::*, DisasmError, InstrSet, Unpredictable};
+    use crate::encode::EncodeError;
+    use crate::ir::{Cond, Op, Operand, Operand::*, Register::*};
+    use crate::operands;
+
+    const SRC: &str = "
+        @ Sum the table
+        start:  mov r0, #0          ; total
+                mov r1, #3
+        loop:   subs r1, r1, #1
+                bne loop
+                bl  func
+        # whole line comments are also allowed
+        .align 3
+        table:  .word 1, 0x20, -1, table
+                .hword 0xFFFF, -2
+                .byte 1
+        .thumb
+        .org 0x02000100
+        func:   movs r0, #1
+                bl start
+                blx r1
+                bx lr
+    ";
+
+    #[test]
+    fn test_parse_program() {
+        let program = parse_program(SRC, 0x0200_0000, ARMv5TE).unwrap();
+        let label = |name: &str| program.labels[name];
+        assert_eq!(label("start"), 0x0200_0000);
+        assert_eq!(label("loop"), 0x0200_0008);
+        assert_eq!(label("table"), 0x0200_0018);
+        assert_eq!(label("func"), 0x0200_0100);
+
+        let instrs: Vec<_> = program.instructions().collect();
+        assert_eq!(instrs.len(), 9);
+        let (addr, instr_set, bne) = instrs[3];
+        assert_eq!((addr, instr_set, bne.op), (0x0200_000C, InstrSet::ARM, Op::B));
+        assert_eq!(bne.operands[0], Operand::Target(0x0200_0008));
+        assert_eq!(instrs[4].2.operands[0], Operand::Target(0x0200_0100));
+
+        // THUMB BL is 4 bytes, but BLX Rm is 2
+        let thumb: Vec<_> = instrs[5..]
+            .iter()
+            .map(|&(addr, set, _)| (addr, set))
+            .collect();
+        let t = InstrSet::THUMB;
+        assert_eq!(
+            thumb,
+            vec![
+                (0x0200_0100, t),
+                (0x0200_0102, t),
+                (0x0200_0106, t),
+                (0x0200_0108, t)
+            ]
+        );
+        assert_eq!(instrs[6].2.operands[0], Operand::Target(0x0200_0000));
+        assert_eq!(instrs[7].2.op, Op::BLX);
+        assert_eq!(program.unvalidated, vec![0x0200_0100, 0x0200_0102, 0x0200_0106, 0x0200_0108]);
+
+        let data: Vec<_> = program
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Data { addr, bytes } => Some((*addr, bytes.clone())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            data,
+            vec![
+                (
+                    0x0200_0018,
+                    vec![1, 0, 0, 0, 0x20, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0x18, 0, 0, 2]
+                ),
+                (0x0200_0028, vec![0xFF, 0xFF, 0xFE, 0xFF]),
+                (0x0200_002C, vec![1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_literal_pool() {
+        let src = "
+                    ldr r0, =0x04000000
+                    ldr r1, =table
+                    ldreq r2, =0x04000000   @ shares the first slot
+                    adr r3, table
+                    ldrb r4, table
+                    b next
+                    .ltorg
+            next:   ldr r5, =-1
+            table:  .word 1
+            .thumb
+                    ldr r6, =table
+        ";
+        let program = parse_program(src, 0, ARMv5TE).unwrap();
+        let operands: Vec<_> = program
+            .instructions()
+            .map(|(_, _, instr)| instr.operands)
+            .collect();
+        assert_eq!(
+            operands,
+            vec![
+                operands![Reg(R0), Target(0x18)],
+                operands![Reg(R1), Target(0x1C)],
+                operands![Reg(R2), Target(0x18)],
+                operands![Reg(R3), Target(0x24)],
+                operands![Reg(R4), Target(0x24)],
+                operands![Target(0x20)],
+                operands![Reg(R5), Target(0x2C)],
+                operands![Reg(R6), Target(0x30)],
+            ]
+        );
+        let instrs: Vec<_> = program.instructions().collect();
+        assert_eq!((instrs[2].2.op, instrs[2].2.cond), (Op::LDR, Cond::EQ));
+        assert_eq!(instrs[3].2.op, Op::ADR);
+        assert_eq!(instrs[4].2.op, Op::LDRB);
+
+        let data: Vec<_> = program
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Data { addr, bytes } => Some((*addr, bytes.clone())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            data,
+            vec![
+                (0x18, vec![0, 0, 0, 4]),
+                (0x1C, vec![0x24, 0, 0, 0]),
+                (0x24, vec![1, 0, 0, 0]),
+                (0x2C, vec![0xFF; 4]),
+                (0x30, vec![0x24, 0, 0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_program_errors() {
+        use ProgramErrorKind::*;
+        let err = |src: &str| {
+            let err = parse_program(src, 0, ARMv5TE).unwrap_err();
+            (err.line, err.column, err.kind)
+        };
+        let s = String::from;
+        assert_eq!(err("mov r0, #1\nb nowhere"), (2, 3, UndefinedLabel { label: s("nowhere") }));
+        assert_eq!(err("a: nop\n  a: nop"), (2, 3, DuplicateLabel { label: s("a") }));
+        assert_eq!(err("mov r0, r1 r2"), (1, 12, TrailingInput { text: s("r2") }));
+        assert_eq!(err("mov r0, [r1"), (1, 9, Syntax));
+        assert_eq!(err("ldr r0, [r1, #4]]"), (1, 17, TrailingInput { text: s("]") }));
+        assert_eq!(err("frob r0"), (1, 1, Syntax));
+        assert_eq!(err("add r0, r1, lsl #1, r2, lsl #2"), (1, 5, Syntax));
+        assert_eq!(err(".section .text"), (1, 2, UnknownDirective { name: s("section") }));
+        assert_eq!(err(".byte 1, 256"), (1, 10, InvalidValue { text: s("256") }));
+        assert_eq!(err(".org 8\n.org 4"), (2, 6, OrgBackwards { addr: 4 }));
+        let invalid = |error| Invalid { error };
+        assert_eq!(
+            err("  add r0, r1, #257"),
+            (1, 3, invalid(EncodeError::InvalidModifiedImm { op: Op::ADD, imm: 257 }))
+        );
+        let reason = Unpredictable::WritebackBase;
+        assert_eq!(
+            err("nop\nldr r0, [r0], #4"),
+            (2, 1, invalid(EncodeError::Unpredictable { op: Op::LDR, reason }))
+        );
+        assert_eq!(err("ldr r0, =1\n.org 0x2000"), (1, 10, LiteralOutOfRange { addr: 0x2000 }));
+
+        // ARMv5TE instructions are only valid when parsing for ARMv5TE
+        assert!(parse_program("clz r0, r1", 0, ARMv5TE).is_ok());
+        let err = parse_program("nop\nclz r0, r1", 0, ARMv4T).unwrap_err();
+        let error = DisasmError::Unsupported { instr: 0xE16F_0F11, arch: ARMv4T };
+        assert_eq!(
+            (err.line, err.kind),
+            (2, invalid(EncodeError::Undecodable { op: Op::CLZ, error }))
+        );
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err =
+            parse_program("start:\n\tmov r0, #1\n\tb  nowhere @ comment", 0, ARMv5TE).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 3, column 5: undefined label 'nowhere'\n\tb  nowhere @ comment\n\t   ^"
+        );
+    }
+}
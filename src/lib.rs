@@ -1,5 +1,7 @@
 #![allow(dead_code, unused_variables)]
 pub mod disasm;
+pub mod encode;
 pub mod ir;
+pub mod rom;
 pub mod translate;
 pub mod vm;
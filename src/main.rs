@@ -1,22 +1,15 @@
 use std::{error::Error, mem, ptr};
 
-use ndsjit::translate::block_translator::BlockTranslator;
-
-#[repr(C, packed)]
-#[derive(Default, Copy, Clone, Debug)]
-struct Regs {
-    r0: u32,
-    r1: u32,
-    r2: u32,
-    r3: u32,
-}
+use ndsjit::{
+    disasm::{Arch, InstrSet},
+    translate::block_translator::BlockTranslator,
+};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut translator = BlockTranslator::new();
+    let mut translator = BlockTranslator::new(Arch::ARMv5TE);
     let code = vec![];
-    let func_ptr = translator.translate(&code)?;
+    let func_ptr = translator.translate(&code, InstrSet::ARM)?;
 
-    // let mut vm_state = Regs::default();
     let mut vm_state = [0u32; 17];
     vm_state[16] = 0;
     dbg!(vm_state);
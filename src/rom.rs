@@ -0,0 +1,135 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::ops::Range;
+
+/// Size of the .nds cartridge header
+pub const HEADER_SIZE: usize = 0x200;
+
+/// One of the ARM9/ARM7 binaries in a .nds image, which the firmware copies from `rom_offset` in
+/// the cartridge to `ram_address` before jumping to `entry`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CodeSection {
+    pub rom_offset: u32,
+    pub entry: u32,
+    pub ram_address: u32,
+    pub size: u32,
+}
+
+impl CodeSection {
+    fn parse(header: &[u8]) -> Self {
+        let word = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+        Self {
+            rom_offset: word(0),
+            entry: word(1),
+            ram_address: word(2),
+            size: word(3),
+        }
+    }
+
+    /// Range of the section's bytes within the image
+    pub fn range(&self) -> Range<usize> {
+        let start = self.rom_offset as usize;
+        start..start + self.size as usize
+    }
+}
+
+/// The parts of the .nds header needed to locate the code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomHeader {
+    pub title: String,
+    pub arm9: CodeSection,
+    pub arm7: CodeSection,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomError {
+    /// The image is smaller than the header
+    TooShort { len: usize },
+    /// A code section extends past the end of the image
+    SectionOutOfBounds { section: CodeSection, len: usize },
+}
+
+impl Display for RomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooShort { len } => {
+                write!(f, "image of {:#x} bytes is too short for the .nds header", len)
+            }
+            Self::SectionOutOfBounds { section, len } => write!(
+                f,
+                "code section at {:#x}..{:#x} is outside the image of {:#x} bytes",
+                section.rom_offset,
+                section.range().end,
+                len
+            ),
+        }
+    }
+}
+
+impl Error for RomError {}
+
+type RomResult<T> = Result<T, RomError>;
+
+impl RomHeader {
+    /// Parse the header at the start of a .nds image, checking that both code sections are inside
+    /// the image
+    pub fn parse(rom: &[u8]) -> RomResult<Self> {
+        if rom.len() < HEADER_SIZE {
+            return Err(RomError::TooShort { len: rom.len() });
+        }
+        let title = rom[..12]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect();
+        let header = Self {
+            title,
+            arm9: CodeSection::parse(&rom[0x20..0x30]),
+            arm7: CodeSection::parse(&rom[0x30..0x40]),
+        };
+        for section in [header.arm9, header.arm7] {
+            if section.range().end > rom.len() {
+                return Err(RomError::SectionOutOfBounds { section, len: rom.len() });
+            }
+        }
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_image(arm9: [u32; 4], arm7: [u32; 4], len: usize) -> Vec<u8> {
+        let mut rom = vec![0; len];
+        rom[..8].copy_from_slice(b"HOMEBREW");
+        for (i, word) in arm9.iter().chain(arm7.iter()).enumerate() {
+            rom[0x20 + i * 4..0x24 + i * 4].copy_from_slice(&word.to_le_bytes());
+        }
+        rom
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let rom =
+            rom_image([0x200, 0x0200_0000, 0x0200_0000, 0x100], [0x300, 0, 0x0380_0000, 8], 0x308);
+        let header = RomHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "HOMEBREW");
+        assert_eq!(
+            header.arm9,
+            CodeSection {
+                rom_offset: 0x200,
+                entry: 0x0200_0000,
+                ram_address: 0x0200_0000,
+                size: 0x100
+            }
+        );
+        assert_eq!(header.arm7.range(), 0x300..0x308);
+
+        assert_eq!(RomHeader::parse(&rom[..0x100]), Err(RomError::TooShort { len: 0x100 }));
+        assert_eq!(
+            RomHeader::parse(&rom[..0x304]),
+            Err(RomError::SectionOutOfBounds { section: header.arm7, len: 0x304 })
+        );
+    }
+}
@@ -3,6 +3,8 @@ use cranelift_module::ModuleError;
 
 pub mod block_translator;
 pub mod instruction_translator;
+pub mod interpreter;
+pub mod uop;
 
 use std::{error::Error, fmt::Display};
 
@@ -12,7 +14,7 @@ use crate::ir::Instruction;
 pub enum TranslationError {
     Unimplemented(Instruction),
     Invalid(Instruction),
-    CraneliftModuleError(ModuleError),
+    CraneliftModuleError(Box<ModuleError>),
     CraneliftVerifierError(VerifierErrors),
 }
 
@@ -26,7 +28,7 @@ impl Error for TranslationError {}
 
 impl From<ModuleError> for TranslationError {
     fn from(err: ModuleError) -> Self {
-        Self::CraneliftModuleError(err)
+        Self::CraneliftModuleError(Box::new(err))
     }
 }
 impl From<VerifierErrors> for TranslationError {
@@ -11,11 +11,12 @@ use cranelift_module::Module;
 use strum::IntoEnumIterator;
 
 use super::TranslationError;
-use std::mem;
 
 use crate::{
-    ir::{Instruction, Register},
+    disasm::{Arch, InstrSet},
+    ir::{Instruction, Op, RegSet, Register, FLAGS_ALL},
     translate::instruction_translator::translate_instruction,
+    vm::VMState,
 };
 
 use super::instruction_translator::TranslationState;
@@ -28,15 +29,24 @@ pub struct BlockTranslator {
 }
 
 impl BlockTranslator {
-    pub fn new() -> Self {
+    /// A translator for code running on `arch`
+    pub fn new(arch: Arch) -> Self {
         Self {
             builder_ctx: FunctionBuilderContext::new(),
-            state: TranslationState { register_vars: vec![] },
+            state: TranslationState { register_vars: vec![], arch, instr_set: InstrSet::ARM },
         }
     }
 
+    /// Translates a block of `instr_set` code into a function which takes a pointer to the
+    /// [VMState] it runs on, and uses the registers of whichever mode is current
+    ///
     /// TODO - more specific error type?
-    pub fn translate(&mut self, code: &Vec<Instruction>) -> Result<*const u8, TranslationError> {
+    pub fn translate(
+        &mut self,
+        code: &[Instruction],
+        instr_set: InstrSet,
+    ) -> Result<*const u8, TranslationError> {
+        self.state.instr_set = instr_set;
         let jit_builder = JITBuilder::new(cranelift_module::default_libcall_names()).unwrap();
         let mut module = JITModule::new(jit_builder);
         let mut ctx = module.make_context();
@@ -54,15 +64,30 @@ impl BlockTranslator {
         builder.append_block_params_for_function_params(entry_block);
         builder.switch_to_block(entry_block);
 
+        // Only the registers the block uses have to be loaded, and only those it writes stored
+        let (read, written) = block_regs(code);
         let vmctx = builder.create_global_value(GlobalValueData::VMContext);
-        gen_prologue(vmctx, &mut self.state, &mut builder);
+        gen_prologue(vmctx, &mut self.state, &mut builder, read.union(written));
 
         // Start loop
-        for instr in code.iter() {
+        for (instr, live) in code.iter().zip(live_flags(code)) {
+            // Skip computing flags which are overwritten before anything reads them
+            let flags = instr.flags_written();
+            if flags != 0 && flags & live == 0 {
+                match instr.op {
+                    Op::TST | Op::TEQ | Op::CMP | Op::CMN => continue,
+                    _ if instr.set_flags && !instr.changes_control_flow() => {
+                        let instr = Instruction { set_flags: false, ..*instr };
+                        translate_instruction(&instr, &self.state, &mut builder)?;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
             translate_instruction(instr, &self.state, &mut builder)?;
         }
 
-        gen_epilogue(vmctx, &self.state, &mut builder);
+        gen_epilogue(vmctx, &self.state, &mut builder, written);
         builder.seal_all_blocks();
         builder.finalize();
 
@@ -80,7 +105,34 @@ impl BlockTranslator {
     }
 }
 
-fn gen_prologue(vmctx: GlobalValue, state: &mut TranslationState, builder: &mut FunctionBuilder) {
+/// Registers read and written by a block of instructions
+fn block_regs(code: &[Instruction]) -> (RegSet, RegSet) {
+    code.iter()
+        .fold((RegSet::default(), RegSet::default()), |(read, written), instr| {
+            (read.union(instr.regs_read()), written.union(instr.regs_written()))
+        })
+}
+
+/// Flags which are live after each instruction of a block, i.e. which may be read before they are
+/// next written. Every flag is live at the end of the block
+fn live_flags(code: &[Instruction]) -> Vec<u32> {
+    let mut live = vec![0; code.len()];
+    let mut live_after = FLAGS_ALL;
+    for (i, instr) in code.iter().enumerate().rev() {
+        live[i] = live_after;
+        // A conditional instruction may leave the flags it writes unchanged, but it also counts
+        // them as read in that case
+        live_after = (live_after & !instr.flags_written()) | instr.flags_read();
+    }
+    live
+}
+
+fn gen_prologue(
+    vmctx: GlobalValue,
+    state: &mut TranslationState,
+    builder: &mut FunctionBuilder,
+    used: RegSet,
+) {
     // TODO some kind of trait that governs access to CPU state
     // Create a re-usable variable for each of the CPU registers
     // TODO - some sort of context/environment managing this ptr type and other things like it
@@ -89,23 +141,28 @@ fn gen_prologue(vmctx: GlobalValue, state: &mut TranslationState, builder: &mut
         let var = Variable::new(i);
         builder.declare_var(var, I32);
         state.register_vars.push(var);
-        let tmp = builder.ins().load(
-            I32,
-            MemFlags::new(),
-            base,
-            (reg as usize * mem::size_of::<u32>()) as i32,
-        );
+        if !used.contains(reg) {
+            continue;
+        }
+        let tmp = builder
+            .ins()
+            .load(I32, MemFlags::new(), base, VMState::reg_offset(reg));
         builder.def_var(var, tmp);
     }
 }
 
-fn gen_epilogue(vmctx: GlobalValue, state: &TranslationState, builder: &mut FunctionBuilder) {
+fn gen_epilogue(
+    vmctx: GlobalValue,
+    state: &TranslationState,
+    builder: &mut FunctionBuilder,
+    written: RegSet,
+) {
     let base = builder.ins().global_value(I64, vmctx);
-    for (i, &var) in state.register_vars.iter().enumerate() {
-        let arg = builder.use_var(var);
+    for reg in written.iter() {
+        let arg = builder.use_var(state.get_var(reg));
         builder
             .ins()
-            .store(MemFlags::new(), arg, base, (i * mem::size_of::<u32>()) as i32);
+            .store(MemFlags::new(), arg, base, VMState::reg_offset(reg));
     }
     let const_ = builder.ins().iconst(I32, 0);
     builder.ins().return_(&[const_]);
@@ -114,8 +171,12 @@ fn gen_epilogue(vmctx: GlobalValue, state: &TranslationState, builder: &mut Func
 #[cfg(test)]
 #[allow(non_snake_case)]
 mod tests {
-    use super::BlockTranslator;
-    use crate::ir::*;
+    use super::{block_regs, live_flags, BlockTranslator};
+    use crate::disasm::{Arch, InstrSet};
+    use crate::ir::{parsing::instruction, *};
+    use crate::operands;
+    use crate::vm::{Mode, VMState};
+    use itertools::iproduct;
     use std::{mem, ptr};
 
     type Func = unsafe extern "C" fn(*mut [u32; 17]) -> i32;
@@ -124,11 +185,164 @@ mod tests {
     const Z: u32 = 1 << 30;
     const N: u32 = 1 << 31;
 
-    fn add_with_cond_test(cond: Cond, true_patterns: &Vec<u32>) {
+    fn parse_block(asm: &[&str]) -> Vec<Instruction> {
+        asm.iter()
+            .map(|line| instruction(line).unwrap().1)
+            .collect()
+    }
+
+    #[test]
+    fn test_block_regs() {
+        let code = parse_block(&["ADD r0, r1, r2", "STR r0, [sp, #-4]!", "MULEQ r3, r0, r4"]);
+        let (read, written) = block_regs(&code);
+        let regs = |regs: &[Register]| regs.iter().copied().collect::<RegSet>();
+        use Register::*;
+        assert_eq!(read, regs(&[R0, R1, R2, R4, SP, FLAGS]));
+        assert_eq!(written, regs(&[R0, R3, SP]));
+    }
+
+    #[test]
+    fn test_live_flags() {
+        const NZCV: u32 = FLAG_N | FLAG_Z | FLAG_C | FLAG_V;
+        let code = parse_block(&[
+            "ADDS r0, r0, #1",
+            "CMP r0, r1",
+            "ADCS r2, r2, #0",
+            "MOVEQ r3, r4",
+            "MULS r5, r5, r5",
+            "SUBS r6, r6, #1",
+        ]);
+        assert_eq!(
+            live_flags(&code),
+            vec![
+                // The CMP overwrites everything the ADDS sets
+                FLAG_Q,
+                FLAG_C | FLAG_Q,
+                FLAG_Z | FLAG_Q,
+                FLAG_Q,
+                FLAG_Q,
+                FLAGS_ALL,
+            ]
+        );
+        let dead = |i: usize| code[i].flags_written() & live_flags(&code)[i] == 0;
+        assert!(dead(0));
+        assert!(!dead(1) && !dead(2));
+        assert_eq!(code[2].flags_written(), NZCV);
+    }
+
+    #[test]
+    fn test_banked_regs() {
+        let code = parse_block(&["ADD sp, sp, #4", "MOV r8, sp"]);
+        let mut translator = BlockTranslator::new(Arch::ARMv5TE);
+        let func_ptr = translator.translate(&code, InstrSet::ARM).unwrap();
+        let func: unsafe extern "C" fn(*mut VMState) -> i32 = unsafe { mem::transmute(func_ptr) };
+
+        // The same code uses the registers of the mode it is called in
+        let mut state = VMState::new();
+        for mode in [Mode::IRQ, Mode::FIQ] {
+            state.switch_mode(mode);
+            state.set_reg(Register::SP, 0x100 * mode as u32);
+            unsafe { func(&mut state) };
+        }
+        state.switch_mode(Mode::SVC);
+        assert_eq!(state.reg(Register::SP), 0);
+        assert_eq!(state.banked_reg(Mode::IRQ, Register::SP), 0x1204);
+        assert_eq!(state.banked_reg(Mode::FIQ, Register::SP), 0x1104);
+        // Only FIQ mode banks R8
+        assert_eq!(state.reg(Register::R8), 0x1204);
+        assert_eq!(state.banked_reg(Mode::FIQ, Register::R8), 0x1104);
+    }
+
+    /// NZCV for a result, carry out and overflow
+    fn nzcv(result: u32, carry: bool, overflow: bool) -> u32 {
+        let flag = |set: bool, flag: u32| if set { flag } else { 0 };
+        (result & N) | flag(result == 0, Z) | flag(carry, C) | flag(overflow, V)
+    }
+
+    /// Reference `a + b + carry`, computed with wider integers
+    fn add_ref(a: u32, b: u32, carry: u32) -> (u32, u32) {
+        let wide = a as u64 + b as u64 + carry as u64;
+        let signed = a as i32 as i64 + b as i32 as i64 + carry as i64;
+        let result = wide as u32;
+        (result, nzcv(result, wide > u32::MAX as u64, signed != result as i32 as i64))
+    }
+
+    /// Reference `a - b - NOT(carry)`, where the carry out is NOT(borrow)
+    fn sub_ref(a: u32, b: u32, carry: u32) -> (u32, u32) {
+        let borrow = 1 - carry;
+        let result = a.wrapping_sub(b).wrapping_sub(borrow);
+        let signed = a as i32 as i64 - b as i32 as i64 - borrow as i64;
+        let no_borrow = a as u64 >= b as u64 + borrow as u64;
+        (result, nzcv(result, no_borrow, signed != result as i32 as i64))
+    }
+
+    /// The C flag as 0 or 1
+    fn carry(flags: u32) -> u32 {
+        (flags >> 29) & 1
+    }
+
+    /// Reference logical operation, which leaves V unchanged
+    fn logical_ref(result: u32, carry: u32, flags: u32) -> (u32, u32) {
+        (result, nzcv(result, carry == 1, flags & V != 0))
+    }
+
+    #[test]
+    fn test_flags() {
+        type Reference = fn(u32, u32, u32) -> (u32, u32);
+        // Each instruction reads r0 and r1, and writes r2. The reference gives r2 and the flags
+        // from r0, r1 and the flags before
+        let cases: [(&str, Reference); 20] = [
+            ("ADDS r2, r0, r1", |a, b, _| add_ref(a, b, 0)),
+            ("ADCS r2, r0, r1", |a, b, f| add_ref(a, b, carry(f))),
+            ("SUBS r2, r0, r1", |a, b, _| sub_ref(a, b, 1)),
+            ("SBCS r2, r0, r1", |a, b, f| sub_ref(a, b, carry(f))),
+            ("RSBS r2, r0, r1", |a, b, _| sub_ref(b, a, 1)),
+            ("RSCS r2, r0, r1", |a, b, f| sub_ref(b, a, carry(f))),
+            ("CMP r0, r1", |a, b, _| (0, sub_ref(a, b, 1).1)),
+            ("CMN r0, r1", |a, b, _| (0, add_ref(a, b, 0).1)),
+            ("ANDS r2, r0, r1", |a, b, f| logical_ref(a & b, carry(f), f)),
+            ("EORS r2, r0, r1", |a, b, f| logical_ref(a ^ b, carry(f), f)),
+            ("ORRS r2, r0, r1", |a, b, f| logical_ref(a | b, carry(f), f)),
+            ("BICS r2, r0, r1", |a, b, f| logical_ref(a & !b, carry(f), f)),
+            ("MOVS r2, r1", |_, b, f| logical_ref(b, carry(f), f)),
+            ("MVNS r2, r1", |_, b, f| logical_ref(!b, carry(f), f)),
+            ("TST r0, r1", |a, b, f| (0, logical_ref(a & b, carry(f), f).1)),
+            ("TEQ r0, r1", |a, b, f| (0, logical_ref(a ^ b, carry(f), f).1)),
+            // The carry out of the shifter
+            ("ANDS r2, r0, r1, LSL #1", |a, b, f| logical_ref(a & (b << 1), b >> 31, f)),
+            ("MOVS r2, r1, LSR #32", |_, b, f| logical_ref(0, b >> 31, f)),
+            ("ORRS r2, r0, #0x80000000", |a, _, f| logical_ref(a | 0x8000_0000, 1, f)),
+            ("EORS r2, r0, #1", |a, _, f| logical_ref(a ^ 1, carry(f), f)),
+        ];
+        const EDGES: [u32; 4] = [0, 0x7FFF_FFFF, 0x8000_0000, 0xFFFF_FFFF];
+        let mut translator = BlockTranslator::new(Arch::ARMv5TE);
+        for (asm, reference) in cases {
+            let func_ptr = translator
+                .translate(&parse_block(&[asm]), InstrSet::ARM)
+                .unwrap();
+            let func: Func = unsafe { mem::transmute(func_ptr) };
+            for (a, b, flags) in iproduct!(EDGES, EDGES, [0, C | V, N | Z | C | V]) {
+                let mut regs = [0u32; 17];
+                (regs[0], regs[1], regs[16]) = (a, b, flags);
+                unsafe { func(ptr::addr_of_mut!(regs)) };
+                assert_eq!(
+                    (regs[2], regs[16]),
+                    reference(a, b, flags),
+                    "{} with r0 = {:#x}, r1 = {:#x}, flags = {:#x}",
+                    asm,
+                    a,
+                    b,
+                    flags
+                );
+            }
+        }
+    }
+
+    fn add_with_cond_test(cond: Cond, true_patterns: &[u32]) {
         let code = vec![Instruction {
             cond,
             op: Op::ADD,
-            operands: vec![
+            operands: operands![
                 Operand::Reg(Register::R2),
                 Operand::Reg(Register::R2),
                 Operand::Imm(99),
@@ -136,8 +350,8 @@ mod tests {
             extra: None,
             set_flags: false,
         }];
-        let mut translator = BlockTranslator::new();
-        let func_ptr = translator.translate(&code).unwrap();
+        let mut translator = BlockTranslator::new(Arch::ARMv5TE);
+        let func_ptr = translator.translate(&code, InstrSet::ARM).unwrap();
         for mask in 0..16 {
             let mut regs = [0u32; 17];
             regs[16] = mask << 28;
@@ -156,17 +370,24 @@ mod tests {
         add_with_cond_test(
             Cond::AL,
             // NZCV
-            &vec![
+            &[
                 0b0000, 0b0001, 0b0010, 0b0011, 0b0100, 0b0101, 0b0110, 0b0111, 0b1000, 0b1001,
                 0b1010, 0b1011, 0b1100, 0b1101, 0b1110, 0b1111,
             ],
         );
     }
+
+    #[test]
+    fn test_NV() {
+        // Never passes, as on ARMv4T
+        add_with_cond_test(Cond::NV, &[]);
+    }
+
     #[test]
     fn test_EQ() {
         add_with_cond_test(
             Cond::EQ,
-            &vec![
+            &[
                 0b0100, 0b0101, 0b0110, 0b0111, 0b1100, 0b1101, 0b1110, 0b1111,
             ],
         );
@@ -176,7 +397,7 @@ mod tests {
     fn test_NE() {
         add_with_cond_test(
             Cond::NE,
-            &vec![
+            &[
                 0b0000, 0b0001, 0b0010, 0b0011, 0b1000, 0b1001, 0b1010, 0b1011,
             ],
         );
@@ -186,7 +407,7 @@ mod tests {
     fn test_CS() {
         add_with_cond_test(
             Cond::CS,
-            &vec![
+            &[
                 0b0010, 0b0011, 0b0110, 0b0111, 0b1010, 0b1011, 0b1110, 0b1111,
             ],
         );
@@ -196,7 +417,7 @@ mod tests {
     fn test_CC() {
         add_with_cond_test(
             Cond::CC,
-            &vec![
+            &[
                 0b0000, 0b0001, 0b0100, 0b0101, 0b1000, 0b1001, 0b1100, 0b1101,
             ],
         );
@@ -206,7 +427,7 @@ mod tests {
     fn test_MI() {
         add_with_cond_test(
             Cond::MI,
-            &vec![
+            &[
                 0b1000, 0b1001, 0b1010, 0b1011, 0b1100, 0b1101, 0b1110, 0b1111,
             ],
         );
@@ -216,7 +437,7 @@ mod tests {
     fn test_PL() {
         add_with_cond_test(
             Cond::PL,
-            &vec![
+            &[
                 0b0000, 0b0001, 0b0010, 0b0011, 0b0100, 0b0101, 0b0110, 0b0111,
             ],
         );
@@ -226,7 +447,7 @@ mod tests {
     fn test_VS() {
         add_with_cond_test(
             Cond::VS,
-            &vec![
+            &[
                 0b0001, 0b0011, 0b0101, 0b0111, 0b1001, 0b1011, 0b1101, 0b1111,
             ],
         );
@@ -236,7 +457,7 @@ mod tests {
     fn test_VC() {
         add_with_cond_test(
             Cond::VC,
-            &vec![
+            &[
                 0b0000, 0b0010, 0b0100, 0b0110, 0b1000, 0b1010, 0b1100, 0b1110,
             ],
         );
@@ -248,7 +469,7 @@ mod tests {
             Cond::HI,
             // C & ~Z
             // NZCV
-            &vec![0b0010, 0b0011, 0b1010, 0b1011],
+            &[0b0010, 0b0011, 0b1010, 0b1011],
         );
     }
 
@@ -257,7 +478,7 @@ mod tests {
         add_with_cond_test(
             Cond::LS,
             // NZCV
-            &vec![
+            &[
                 0b0000, 0b0001, 0b0100, 0b0101, 0b0110, 0b0111, 0b1000, 0b1001, 0b1100, 0b1101,
                 0b1110, 0b1111,
             ],
@@ -270,7 +491,7 @@ mod tests {
             Cond::GE,
             // N == V
             // NZCV
-            &vec![
+            &[
                 0b0000, 0b0010, 0b0100, 0b0110, 0b1001, 0b1011, 0b1101, 0b1111,
             ],
         );
@@ -282,7 +503,7 @@ mod tests {
             Cond::LT,
             // N != V
             // NZCV
-            &vec![
+            &[
                 0b0001, 0b0011, 0b0101, 0b0111, 0b1000, 0b1010, 0b1100, 0b1110,
             ],
         );
@@ -294,7 +515,7 @@ mod tests {
             Cond::GT,
             // Z == 0 and N == V
             // NZCV
-            &vec![0b0000, 0b0010, 0b1001, 0b1011],
+            &[0b0000, 0b0010, 0b1001, 0b1011],
         );
     }
 
@@ -304,7 +525,7 @@ mod tests {
             Cond::LE,
             // Z == 1 or N != V
             // NZCV
-            &vec![
+            &[
                 0b0001, 0b0011, 0b0100, 0b0101, 0b0110, 0b0111, 0b1000, 0b1010, 0b1100, 0b1101,
                 0b1110, 0b1111,
             ],
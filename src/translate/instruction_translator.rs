@@ -1,13 +1,20 @@
+use super::uop::{lower, AluOp, Temp, UOp};
 use super::TranslationError;
-use crate::ir::{
-    Cond, ExtraOperand, ExtraValue, Instruction, Op, Operand, Register, Shift, ShiftOp,
+use crate::disasm::{Arch, InstrSet};
+use crate::ir::{Cond, Instruction, Register, ShiftOp, FLAG_C, FLAG_N, FLAG_V, FLAG_Z};
+use crate::vm::CPSR_T;
+use cranelift::prelude::{
+    types::{I32, I64},
+    InstBuilder, IntCC, Value,
 };
-use cranelift::prelude::{types::I32, InstBuilder, IntCC, Value};
 use cranelift_frontend::{FunctionBuilder, Variable};
 
 /// Maybe this will persist between block translations and store the output functions?
 pub struct TranslationState {
     pub register_vars: Vec<Variable>,
+    pub arch: Arch,
+    /// The instruction set of the block being translated
+    pub instr_set: InstrSet,
 }
 
 impl TranslationState {
@@ -23,8 +30,11 @@ pub fn translate_instruction(
 ) -> Result<(), TranslationError> {
     match instr.cond {
         Cond::AL => {
-            translate_op(instr, &state, builder)?;
+            translate_op(instr, state, builder)?;
         }
+        // Never executed, as on ARMv4T. From ARMv5TE it selects the unconditional instructions,
+        // which are decoded with AL instead
+        Cond::NV => {}
         _ => {
             let instr_block = builder.create_block();
             let next_block = builder.create_block();
@@ -157,96 +167,241 @@ pub fn translate_cond(
 
             builder.ins().bor(v5, v8)
         }
-        Cond::AL => {
-            panic!("no translation needed for AL cond")
+        Cond::AL | Cond::NV => {
+            panic!("no translation needed for AL or NV cond")
         }
     }
 }
 
+/// Emits the micro-ops the instruction is lowered to
 pub fn translate_op(
     instr: &Instruction,
     state: &TranslationState,
     builder: &mut FunctionBuilder,
 ) -> Result<(), TranslationError> {
-    let invalid = TranslationError::Invalid(instr.clone());
-    match instr.op {
-        Op::ADD => {
-            let dest = instr.operands[0];
-            let op1 = instr.operands[1];
-            let op2 = instr.operands[2];
-            match (dest, op1) {
-                (Operand::Reg(r1), Operand::Reg(r2)) => {
-                    let base = builder.use_var(state.get_var(r2));
-                    let add = match op2 {
-                        Operand::Reg(reg) => {
-                            let arg = builder.use_var(state.get_var(reg));
-                            let shifted = match instr.extra {
-                                None => arg,
-                                Some(ExtraOperand::Shift(shift)) => {
-                                    translate_shift(arg, shift, state, builder)
-                                }
-                                Some(ExtraOperand::Offset(_)) => {
-                                    return Err(invalid);
-                                }
-                            };
-                            shifted
-                        }
-                        Operand::Imm(imm) => builder.ins().iconst(I32, imm as i64),
-                        Operand::Addr(_) => return Err(invalid),
-                    };
-                    let result = builder.ins().iadd(base, add);
-                    builder.def_var(state.get_var(r1), result);
-                }
-                (_, _) => {
-                    return Err(invalid);
-                }
-            };
-        }
-        Op::MOV => match (instr.operands[0], instr.operands[1]) {
-            (Operand::Reg(dest), Operand::Reg(src)) => {
-                let base = builder.use_var(state.get_var(src));
-                let result = match instr.extra {
-                    Some(extra) => match extra {
-                        ExtraOperand::Shift(shift) => translate_shift(base, shift, state, builder),
-                        _ => {
-                            return Err(invalid);
-                        }
-                    },
-                    None => base,
+    let lowered = lower(instr, state.arch, state.instr_set)?;
+    let mut temps: Vec<Option<Value>> = vec![None; lowered.num_temps];
+    for uop in lowered.uops {
+        let get = |temp: Temp| temps[temp.0 as usize].expect("temporary used before it is defined");
+        match uop {
+            UOp::ReadReg { dst, reg } => {
+                temps[dst.0 as usize] = Some(builder.use_var(state.get_var(reg)));
+            }
+            UOp::WriteReg { reg, src } => builder.def_var(state.get_var(reg), get(src)),
+            UOp::Const { dst, imm } => {
+                temps[dst.0 as usize] = Some(builder.ins().iconst(I32, imm as i64));
+            }
+            UOp::ReadCarry { dst } => {
+                let flags = builder.use_var(state.get_var(Register::FLAGS));
+                let carry = builder.ins().ushr_imm(flags, 29);
+                temps[dst.0 as usize] = Some(builder.ins().band_imm(carry, 1));
+            }
+            UOp::Shift { dst, op, value, amount } => {
+                let result = translate_shift(op, get(value), get(amount), builder);
+                temps[dst.0 as usize] = Some(result);
+            }
+            UOp::ShiftCarry { dst, op, value, amount, carry_in } => {
+                let result =
+                    translate_shift_carry(op, get(value), get(amount), get(carry_in), builder);
+                temps[dst.0 as usize] = Some(result);
+            }
+            UOp::Alu { dst, op, a, b } => {
+                let (a, b) = (get(a), get(b));
+                let result = match op {
+                    AluOp::Add => builder.ins().iadd(a, b),
+                    AluOp::Sub => builder.ins().isub(a, b),
+                    AluOp::And => builder.ins().band(a, b),
+                    AluOp::Or => builder.ins().bor(a, b),
+                    AluOp::Xor => builder.ins().bxor(a, b),
+                    AluOp::Mul => builder.ins().imul(a, b),
                 };
-                builder.def_var(state.get_var(dest), result);
+                temps[dst.0 as usize] = Some(result);
             }
-            (Operand::Reg(dest), Operand::Imm(imm)) => {
-                let result = builder.ins().iconst(I32, imm as i64);
-                builder.def_var(state.get_var(dest), result);
+            UOp::Not { dst, src } => temps[dst.0 as usize] = Some(builder.ins().bnot(get(src))),
+            UOp::AddWithCarry { dst, carry_out, overflow, a, b, carry_in } => {
+                let (a, b) = (get(a), get(b));
+                let wide_a = builder.ins().uextend(I64, a);
+                let wide_b = builder.ins().uextend(I64, b);
+                let wide_c = builder.ins().uextend(I64, get(carry_in));
+                let sum = builder.ins().iadd(wide_a, wide_b);
+                let sum = builder.ins().iadd(sum, wide_c);
+                let result = builder.ins().ireduce(I32, sum);
+                let carry = builder.ins().ushr_imm(sum, 32);
+                // Overflow if both inputs have a different sign to the result
+                let a_sign = builder.ins().bxor(a, result);
+                let b_sign = builder.ins().bxor(b, result);
+                let signs = builder.ins().band(a_sign, b_sign);
+                temps[dst.0 as usize] = Some(result);
+                temps[carry_out.0 as usize] = Some(builder.ins().ireduce(I32, carry));
+                temps[overflow.0 as usize] = Some(builder.ins().ushr_imm(signs, 31));
             }
-            _ => {
-                return Err(TranslationError::Invalid(instr.clone()));
+            UOp::MulLong { lo, hi, a, b, signed } => {
+                let (a, b) = match signed {
+                    true => {
+                        (builder.ins().sextend(I64, get(a)), builder.ins().sextend(I64, get(b)))
+                    }
+                    false => {
+                        (builder.ins().uextend(I64, get(a)), builder.ins().uextend(I64, get(b)))
+                    }
+                };
+                let product = builder.ins().imul(a, b);
+                let (lo_value, hi_value) = split_i64(product, builder);
+                temps[lo.0 as usize] = Some(lo_value);
+                temps[hi.0 as usize] = Some(hi_value);
+            }
+            UOp::AddLong { lo, hi, a_lo, a_hi, b_lo, b_hi } => {
+                let a = concat_i64(get(a_lo), get(a_hi), builder);
+                let b = concat_i64(get(b_lo), get(b_hi), builder);
+                let sum = builder.ins().iadd(a, b);
+                let (lo_value, hi_value) = split_i64(sum, builder);
+                temps[lo.0 as usize] = Some(lo_value);
+                temps[hi.0 as usize] = Some(hi_value);
+            }
+            UOp::SetNZ { n, z } => {
+                let n = builder.ins().band_imm(get(n), FLAG_N as i64);
+                let z = builder.ins().icmp_imm(IntCC::Equal, get(z), 0);
+                let z = builder.ins().uextend(I32, z);
+                let z = builder.ins().ishl_imm(z, 30);
+                let nz = builder.ins().bor(n, z);
+                translate_set_flags(FLAG_N | FLAG_Z, nz, state, builder);
+            }
+            UOp::SetC { src } => {
+                let c = builder.ins().band_imm(get(src), 1);
+                let c = builder.ins().ishl_imm(c, 29);
+                translate_set_flags(FLAG_C, c, state, builder);
+            }
+            UOp::SetV { src } => {
+                let v = builder.ins().band_imm(get(src), 1);
+                let v = builder.ins().ishl_imm(v, 28);
+                translate_set_flags(FLAG_V, v, state, builder);
+            }
+            // Translated code has no access to guest memory yet
+            UOp::Load { .. } | UOp::Store { .. } => {
+                return Err(TranslationError::Unimplemented(*instr));
+            }
+            UOp::Branch { target, exchange } => {
+                let mut target = get(target);
+                if exchange {
+                    let thumb = builder.ins().band_imm(target, 1);
+                    let thumb = builder.ins().ishl_imm(thumb, 5);
+                    translate_set_flags(CPSR_T, thumb, state, builder);
+                    target = builder.ins().band_imm(target, !1);
+                }
+                builder.def_var(state.get_var(Register::PC), target);
             }
-        },
-        _ => {
-            todo!();
         }
     }
     Ok(())
 }
 
-/// Applies a shift, returning the new shifted value
-pub fn translate_shift(
-    base: Value,
-    shift: Shift,
+/// Joins two words into a 64-bit value. Cranelift can't lower iconcat on every target
+fn concat_i64(lo: Value, hi: Value, builder: &mut FunctionBuilder) -> Value {
+    let lo = builder.ins().uextend(I64, lo);
+    let hi = builder.ins().uextend(I64, hi);
+    let hi = builder.ins().ishl_imm(hi, 32);
+    builder.ins().bor(hi, lo)
+}
+
+/// Splits a 64-bit value into its low and high words
+fn split_i64(value: Value, builder: &mut FunctionBuilder) -> (Value, Value) {
+    let lo = builder.ins().ireduce(I32, value);
+    let hi = builder.ins().ushr_imm(value, 32);
+    (lo, builder.ins().ireduce(I32, hi))
+}
+
+/// Replaces the bits of CPSR selected by `mask` with those of `value`, which must be 0 outside
+/// the mask
+fn translate_set_flags(
+    mask: u32,
+    value: Value,
     state: &TranslationState,
     builder: &mut FunctionBuilder,
+) {
+    let flags_var = state.get_var(Register::FLAGS);
+    let flags = builder.use_var(flags_var);
+    let flags = builder.ins().band_imm(flags, !mask as i64);
+    let flags = builder.ins().bor(flags, value);
+    builder.def_var(flags_var, flags);
+}
+
+/// Emits [UOp::Shift]. Cranelift shifts by the amount modulo 32, so larger amounts are handled
+/// separately
+pub fn translate_shift(
+    op: ShiftOp,
+    value: Value,
+    amount: Value,
+    builder: &mut FunctionBuilder,
 ) -> Value {
-    let amt = match shift.value {
-        ExtraValue::Reg(reg) => builder.use_var(state.get_var(reg)),
-        ExtraValue::Imm(imm) => builder.ins().iconst(I32, imm as i64),
-    };
-    match shift.op {
-        ShiftOp::LSL => builder.ins().ishl(base, amt),
-        ShiftOp::LSR => builder.ins().ushr(base, amt),
-        ShiftOp::ASR => builder.ins().sshr(base, amt),
-        ShiftOp::ROR => builder.ins().rotr(base, amt),
-        ShiftOp::RRX => builder.ins().rotr_imm(base, 1),
+    let amount = builder.ins().band_imm(amount, 0xFF);
+    match op {
+        ShiftOp::LSL | ShiftOp::LSR => {
+            let shifted = match op {
+                ShiftOp::LSL => builder.ins().ishl(value, amount),
+                _ => builder.ins().ushr(value, amount),
+            };
+            let out_of_range =
+                builder
+                    .ins()
+                    .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, amount, 32);
+            let zero = builder.ins().iconst(I32, 0);
+            builder.ins().select(out_of_range, zero, shifted)
+        }
+        ShiftOp::ASR => {
+            let max = builder.ins().iconst(I32, 31);
+            let amount = builder.ins().umin(amount, max);
+            builder.ins().sshr(value, amount)
+        }
+        ShiftOp::ROR => builder.ins().rotr(value, amount),
+        ShiftOp::RRX => panic!("RRX is not a micro-op shift"),
     }
 }
+
+/// Emits [UOp::ShiftCarry], by shifting the value widened to 64 bits so that the last bit
+/// shifted out lands at a fixed position
+pub fn translate_shift_carry(
+    op: ShiftOp,
+    value: Value,
+    amount: Value,
+    carry_in: Value,
+    builder: &mut FunctionBuilder,
+) -> Value {
+    let amount = builder.ins().band_imm(amount, 0xFF);
+    let wide = builder.ins().uextend(I64, value);
+    let carry = match op {
+        ShiftOp::LSL | ShiftOp::LSR => {
+            let bit = match op {
+                ShiftOp::LSL => {
+                    let shifted = builder.ins().ishl(wide, amount);
+                    builder.ins().ushr_imm(shifted, 32)
+                }
+                _ => {
+                    let wide = builder.ins().ishl_imm(wide, 1);
+                    builder.ins().ushr(wide, amount)
+                }
+            };
+            let bit = builder.ins().ireduce(I32, bit);
+            let bit = builder.ins().band_imm(bit, 1);
+            let out_of_range = builder
+                .ins()
+                .icmp_imm(IntCC::UnsignedGreaterThan, amount, 32);
+            let zero = builder.ins().iconst(I32, 0);
+            builder.ins().select(out_of_range, zero, bit)
+        }
+        ShiftOp::ASR => {
+            // Shifting by 32 or more leaves the sign bit in the carry
+            let max = builder.ins().iconst(I32, 32);
+            let amount = builder.ins().umin(amount, max);
+            let wide = builder.ins().ishl_imm(wide, 1);
+            let bit = builder.ins().ushr(wide, amount);
+            let bit = builder.ins().ireduce(I32, bit);
+            builder.ins().band_imm(bit, 1)
+        }
+        ShiftOp::ROR => {
+            let rotated = builder.ins().rotr(value, amount);
+            builder.ins().ushr_imm(rotated, 31)
+        }
+        ShiftOp::RRX => panic!("RRX is not a micro-op shift"),
+    };
+    let unshifted = builder.ins().icmp_imm(IntCC::Equal, amount, 0);
+    builder.ins().select(unshifted, carry_in, carry)
+}
@@ -0,0 +1,431 @@
+use super::uop::{lower, AluOp, MemSize, Temp, UOp};
+use super::TranslationError;
+use crate::disasm::Arch;
+use crate::ir::{Cond, Instruction, Register, ShiftOp, FLAG_C, FLAG_N, FLAG_V, FLAG_Z};
+use crate::vm::{VMState, CPSR_T};
+
+/// Guest memory accessed by loads and stores. Accesses are little-endian, and aligned to their
+/// size
+pub trait Memory {
+    fn read_u8(&self, addr: u32) -> u8;
+    fn read_u16(&self, addr: u32) -> u16;
+    fn read_u32(&self, addr: u32) -> u32;
+    fn write_u8(&mut self, addr: u32, value: u8);
+    fn write_u16(&mut self, addr: u32, value: u16);
+    fn write_u32(&mut self, addr: u32, value: u32);
+}
+
+/// Executes instructions one at a time by interpreting their micro-ops, on the same state as the
+/// translated code
+pub struct Interpreter<M> {
+    pub state: VMState,
+    pub memory: M,
+    pub arch: Arch,
+    temps: Vec<u32>,
+}
+
+/// Returns true if the condition passes with the given value of the FLAGS register
+pub fn cond_passed(cond: Cond, flags: u32) -> bool {
+    let (n, z, c, v) =
+        (flags & FLAG_N != 0, flags & FLAG_Z != 0, flags & FLAG_C != 0, flags & FLAG_V != 0);
+    match cond {
+        Cond::EQ => z,
+        Cond::NE => !z,
+        Cond::CS => c,
+        Cond::CC => !c,
+        Cond::MI => n,
+        Cond::PL => !n,
+        Cond::VS => v,
+        Cond::VC => !v,
+        Cond::HI => c && !z,
+        Cond::LS => !c || z,
+        Cond::GE => n == v,
+        Cond::LT => n != v,
+        Cond::GT => !z && n == v,
+        Cond::LE => z || n != v,
+        Cond::AL => true,
+        // Never passes, as on ARMv4T. From ARMv5TE it selects the unconditional instructions,
+        // which are decoded with AL instead
+        Cond::NV => false,
+    }
+}
+
+/// Result of [UOp::Shift]
+pub fn shift(op: ShiftOp, value: u32, amount: u32) -> u32 {
+    let amount = amount & 0xFF;
+    match op {
+        ShiftOp::LSL if amount < 32 => value << amount,
+        ShiftOp::LSR if amount < 32 => value >> amount,
+        ShiftOp::LSL | ShiftOp::LSR => 0,
+        ShiftOp::ASR => ((value as i32) >> amount.min(31)) as u32,
+        ShiftOp::ROR => value.rotate_right(amount),
+        ShiftOp::RRX => panic!("RRX is not a micro-op shift"),
+    }
+}
+
+/// Result of [UOp::ShiftCarry]
+pub fn shift_carry(op: ShiftOp, value: u32, amount: u32, carry_in: u32) -> u32 {
+    let amount = amount & 0xFF;
+    let wide = value as u64;
+    match op {
+        _ if amount == 0 => carry_in,
+        ShiftOp::LSL | ShiftOp::LSR if amount > 32 => 0,
+        ShiftOp::LSL => ((wide << amount) >> 32) as u32 & 1,
+        ShiftOp::LSR | ShiftOp::ASR => ((wide << 1) >> amount.min(32)) as u32 & 1,
+        ShiftOp::ROR => value.rotate_right(amount) >> 31,
+        ShiftOp::RRX => panic!("RRX is not a micro-op shift"),
+    }
+}
+
+impl<M: Memory> Interpreter<M> {
+    pub fn new(memory: M, arch: Arch) -> Self {
+        Self { state: VMState::new(), memory, arch, temps: vec![] }
+    }
+
+    fn set_flag(&mut self, flag: u32, set: bool) {
+        let flags = &mut self.state.regs[Register::FLAGS as usize];
+        *flags = if set { *flags | flag } else { *flags & !flag };
+    }
+
+    /// Execute a single instruction, if its condition passes
+    pub fn execute(&mut self, instr: &Instruction) -> Result<(), TranslationError> {
+        if !cond_passed(instr.cond, self.state.regs[Register::FLAGS as usize]) {
+            return Ok(());
+        }
+        let lowered = lower(instr, self.arch, self.state.instr_set())?;
+        self.temps.clear();
+        self.temps.resize(lowered.num_temps, 0);
+        for uop in lowered.uops {
+            self.execute_uop(uop);
+        }
+        Ok(())
+    }
+
+    /// Execute each instruction of a block in turn
+    pub fn run(&mut self, code: &[Instruction]) -> Result<(), TranslationError> {
+        code.iter().try_for_each(|instr| self.execute(instr))
+    }
+
+    fn execute_uop(&mut self, uop: UOp) {
+        let t = |temp: Temp| temp.0 as usize;
+        let temps = &mut self.temps;
+        match uop {
+            UOp::ReadReg { dst, reg } => temps[t(dst)] = self.state.regs[reg as usize],
+            UOp::WriteReg { reg, src } => self.state.regs[reg as usize] = temps[t(src)],
+            UOp::Const { dst, imm } => temps[t(dst)] = imm,
+            UOp::ReadCarry { dst } => {
+                temps[t(dst)] = (self.state.regs[Register::FLAGS as usize] & FLAG_C != 0) as u32
+            }
+            UOp::Shift { dst, op, value, amount } => {
+                temps[t(dst)] = shift(op, temps[t(value)], temps[t(amount)])
+            }
+            UOp::ShiftCarry { dst, op, value, amount, carry_in } => {
+                temps[t(dst)] =
+                    shift_carry(op, temps[t(value)], temps[t(amount)], temps[t(carry_in)])
+            }
+            UOp::Alu { dst, op, a, b } => {
+                let (a, b) = (temps[t(a)], temps[t(b)]);
+                temps[t(dst)] = match op {
+                    AluOp::Add => a.wrapping_add(b),
+                    AluOp::Sub => a.wrapping_sub(b),
+                    AluOp::And => a & b,
+                    AluOp::Or => a | b,
+                    AluOp::Xor => a ^ b,
+                    AluOp::Mul => a.wrapping_mul(b),
+                };
+            }
+            UOp::Not { dst, src } => temps[t(dst)] = !temps[t(src)],
+            UOp::AddWithCarry { dst, carry_out, overflow, a, b, carry_in } => {
+                let (a, b) = (temps[t(a)], temps[t(b)]);
+                let sum = a as u64 + b as u64 + temps[t(carry_in)] as u64;
+                let result = sum as u32;
+                temps[t(dst)] = result;
+                temps[t(carry_out)] = (sum >> 32) as u32;
+                temps[t(overflow)] = ((a ^ result) & (b ^ result)) >> 31;
+            }
+            UOp::MulLong { lo, hi, a, b, signed } => {
+                let (a, b) = (temps[t(a)], temps[t(b)]);
+                let product = match signed {
+                    true => (a as i32 as i64).wrapping_mul(b as i32 as i64) as u64,
+                    false => a as u64 * b as u64,
+                };
+                temps[t(lo)] = product as u32;
+                temps[t(hi)] = (product >> 32) as u32;
+            }
+            UOp::AddLong { lo, hi, a_lo, a_hi, b_lo, b_hi } => {
+                let a = (temps[t(a_hi)] as u64) << 32 | temps[t(a_lo)] as u64;
+                let b = (temps[t(b_hi)] as u64) << 32 | temps[t(b_lo)] as u64;
+                let sum = a.wrapping_add(b);
+                temps[t(lo)] = sum as u32;
+                temps[t(hi)] = (sum >> 32) as u32;
+            }
+            UOp::SetNZ { n, z } => {
+                let (n, z) = (temps[t(n)] >> 31 == 1, temps[t(z)] == 0);
+                self.set_flag(FLAG_N, n);
+                self.set_flag(FLAG_Z, z);
+            }
+            UOp::SetC { src } => {
+                let c = temps[t(src)] & 1 == 1;
+                self.set_flag(FLAG_C, c);
+            }
+            UOp::SetV { src } => {
+                let v = temps[t(src)] & 1 == 1;
+                self.set_flag(FLAG_V, v);
+            }
+            UOp::Load { dst, addr, size, signed } => {
+                let addr = temps[t(addr)];
+                temps[t(dst)] = match (size, signed) {
+                    (MemSize::Byte, false) => self.memory.read_u8(addr) as u32,
+                    (MemSize::Byte, true) => self.memory.read_u8(addr) as i8 as u32,
+                    (MemSize::Half, false) => self.memory.read_u16(addr & !1) as u32,
+                    (MemSize::Half, true) => self.memory.read_u16(addr & !1) as i16 as u32,
+                    (MemSize::Word, _) => {
+                        self.memory.read_u32(addr & !3).rotate_right(8 * (addr & 3))
+                    }
+                };
+            }
+            UOp::Store { src, addr, size } => {
+                let (value, addr) = (temps[t(src)], temps[t(addr)]);
+                match size {
+                    MemSize::Byte => self.memory.write_u8(addr, value as u8),
+                    MemSize::Half => self.memory.write_u16(addr & !1, value as u16),
+                    MemSize::Word => self.memory.write_u32(addr & !3, value),
+                }
+            }
+            UOp::Branch { target, exchange } => {
+                let mut target = temps[t(target)];
+                if exchange {
+                    self.set_flag(CPSR_T, target & 1 == 1);
+                    target &= !1;
+                }
+                self.state.regs[Register::PC as usize] = target;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shift, shift_carry, Interpreter, Memory};
+    use crate::disasm::{Arch, InstrSet};
+    use crate::ir::{parsing::instruction, Instruction, ShiftOp, FLAG_C, FLAG_N, FLAG_V, FLAG_Z};
+    use crate::translate::block_translator::BlockTranslator;
+    use crate::vm::{Mode, CPSR_T};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::{mem, ptr};
+
+    type Func = unsafe extern "C" fn(*mut [u32; 17]) -> i32;
+
+    /// 256 bytes of RAM at address 0
+    struct Ram([u8; 256]);
+
+    impl Memory for Ram {
+        fn read_u8(&self, addr: u32) -> u8 {
+            self.0[addr as usize]
+        }
+        fn read_u16(&self, addr: u32) -> u16 {
+            u16::from_le_bytes([self.read_u8(addr), self.read_u8(addr + 1)])
+        }
+        fn read_u32(&self, addr: u32) -> u32 {
+            (self.read_u16(addr) as u32) | (self.read_u16(addr + 2) as u32) << 16
+        }
+        fn write_u8(&mut self, addr: u32, value: u8) {
+            self.0[addr as usize] = value;
+        }
+        fn write_u16(&mut self, addr: u32, value: u16) {
+            self.write_u8(addr, value as u8);
+            self.write_u8(addr + 1, (value >> 8) as u8);
+        }
+        fn write_u32(&mut self, addr: u32, value: u32) {
+            self.write_u16(addr, value as u16);
+            self.write_u16(addr + 2, (value >> 16) as u16);
+        }
+    }
+
+    fn parse_block(asm: &[&str]) -> Vec<Instruction> {
+        asm.iter()
+            .map(|line| instruction(line).unwrap().1)
+            .collect()
+    }
+
+    #[test]
+    fn test_shift() {
+        use ShiftOp::*;
+        let value = 0x8000_0001;
+        assert_eq!(shift(LSL, value, 1), 2);
+        assert_eq!(shift(LSL, value, 0x101), 2);
+        assert_eq!(shift(LSL, value, 32), 0);
+        assert_eq!(shift(LSR, value, 31), 1);
+        assert_eq!(shift(ASR, value, 31), 0xFFFF_FFFF);
+        assert_eq!(shift(ASR, value, 200), 0xFFFF_FFFF);
+        assert_eq!(shift(ROR, value, 33), 0xC000_0000);
+
+        assert_eq!(shift_carry(LSL, value, 0, 0), 0);
+        assert_eq!(shift_carry(LSL, value, 1, 0), 1);
+        assert_eq!(shift_carry(LSL, value, 32, 0), 1);
+        assert_eq!(shift_carry(LSL, value, 33, 1), 0);
+        assert_eq!(shift_carry(LSR, value, 1, 0), 1);
+        assert_eq!(shift_carry(LSR, value, 32, 0), 1);
+        assert_eq!(shift_carry(LSR, value, 33, 0), 0);
+        assert_eq!(shift_carry(ASR, value, 100, 0), 1);
+        assert_eq!(shift_carry(ROR, value, 32, 0), 1);
+        assert_eq!(shift_carry(ROR, value, 2, 1), 0);
+    }
+
+    #[test]
+    fn test_interpreter() {
+        let code = parse_block(&[
+            "MOV r0, #64",
+            "MOV r1, #52",
+            "ADD r1, r1, #4608",
+            "STR r1, [r0, #4]!",
+            "STRB r1, [r0], #-1",
+            "LDRH r2, [r0, #1]",
+            "LDRSB r3, [r0]",
+            "LDR r4, [r0, #2]",
+            "MOVS r5, r0, RRX",
+            "BXCS r1",
+        ]);
+        let mut interpreter = Interpreter::new(Ram([0xFF; 256]), Arch::ARMv5TE);
+        interpreter.run(&code).unwrap();
+        let regs = &interpreter.state.regs;
+        assert_eq!(regs[0], 0x43);
+        assert_eq!(regs[1], 0x1234);
+        assert_eq!(regs[2], 0x1234);
+        assert_eq!(regs[3], 0xFFFF_FFFF);
+        // Unaligned words are rotated
+        assert_eq!(regs[4], 0x3400_0012);
+        assert_eq!(regs[5], 0x21);
+        assert_eq!(regs[16] & (FLAG_N | FLAG_Z | FLAG_C | FLAG_V), FLAG_C);
+        assert_eq!(regs[15], 0x1234);
+        assert_eq!(&interpreter.memory.0[0x43..0x48], &[0xFF, 0x34, 0x12, 0x00, 0x00]);
+
+        // NV never passes
+        interpreter.run(&parse_block(&["MOVNV r0, #1"])).unwrap();
+        assert_eq!(interpreter.state.regs[0], 0x43);
+
+        // Branches are relative to PC, which should hold the address of the instruction plus 8
+        let mut interpreter = Interpreter::new(Ram([0; 256]), Arch::ARMv5TE);
+        interpreter.state.regs[15] = 0x0200_0008;
+        interpreter.run(&parse_block(&["BL #256"])).unwrap();
+        assert_eq!(interpreter.state.regs[14], 0x0200_0004);
+        assert_eq!(interpreter.state.regs[15], 0x0200_0108);
+
+        // Interworking branches switch to THUMB if bit 0 of the target is set
+        interpreter.state.regs[0] = 0x0300_0001;
+        interpreter.run(&parse_block(&["BX r0"])).unwrap();
+        assert_eq!(interpreter.state.regs[15], 0x0300_0000);
+        assert_eq!(interpreter.state.instr_set(), InstrSet::THUMB);
+
+        // BLX (immediate) always does, with bit 1 of the target from the H bit
+        interpreter.state.regs[15] = 0x0200_0008;
+        interpreter
+            .state
+            .set_cpsr(interpreter.state.cpsr() & !CPSR_T);
+        interpreter.run(&parse_block(&["BLX #258"])).unwrap();
+        assert_eq!(interpreter.state.regs[14], 0x0200_0004);
+        assert_eq!(interpreter.state.regs[15], 0x0200_010A);
+        assert_eq!(interpreter.state.instr_set(), InstrSet::THUMB);
+
+        // In THUMB, PC reads as the address plus 4, and the link returns to THUMB
+        interpreter.state.regs[15] = 0x0200_0004;
+        interpreter.run(&parse_block(&["BL #256"])).unwrap();
+        assert_eq!(interpreter.state.regs[14], 0x0200_0005);
+        assert_eq!(interpreter.state.regs[15], 0x0200_0104);
+        interpreter.state.regs[0] = 0x0300_0001;
+        interpreter.state.regs[15] = 0x0200_0004;
+        interpreter.run(&parse_block(&["BLX r0"])).unwrap();
+        assert_eq!(interpreter.state.regs[14], 0x0200_0003);
+        assert_eq!(interpreter.state.regs[15], 0x0300_0000);
+
+        // BLX (immediate) switches to ARM, relative to the word aligned PC
+        interpreter.state.regs[15] = 0x0200_0006;
+        interpreter.run(&parse_block(&["BLX #256"])).unwrap();
+        assert_eq!(interpreter.state.regs[14], 0x0200_0007);
+        assert_eq!(interpreter.state.regs[15], 0x0200_0104);
+        assert_eq!(interpreter.state.instr_set(), InstrSet::ARM);
+    }
+
+    /// Run a block of `instr_set` code with the JIT and the interpreter from the same random state
+    fn check_against_jit(code: &[Instruction], instr_set: InstrSet, rng: &mut StdRng) {
+        let mut translator = BlockTranslator::new(Arch::ARMv5TE);
+        let func_ptr = translator.translate(code, instr_set).unwrap();
+        for _ in 0..32 {
+            let mut regs: [u32; 17] = rng.gen();
+            // Pick shift amounts which cover the edge cases
+            regs[3] = [0, 1, 4, 31, 32, 33, 0x120, 0xFF][rng.gen_range(0..8)];
+            regs[15] = rng.gen::<u32>() & !0b1;
+            regs[16] = (regs[16] & (FLAG_N | FLAG_Z | FLAG_C | FLAG_V)) | Mode::USR as u32;
+            if instr_set == InstrSet::THUMB {
+                regs[16] |= CPSR_T;
+            }
+            let mut interpreter = Interpreter::new(Ram([0; 256]), Arch::ARMv5TE);
+            interpreter.state.regs = regs;
+            interpreter.run(code).unwrap();
+            unsafe {
+                let func: Func = mem::transmute(func_ptr);
+                func(ptr::addr_of_mut!(regs));
+            }
+            assert_eq!(interpreter.state.regs, regs, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn test_matches_jit() {
+        use InstrSet::*;
+        let mut rng = StdRng::seed_from_u64(0);
+        for op2 in [
+            "#4278190080",
+            "#17",
+            "r2",
+            "r2, LSL r3",
+            "r2, LSR r3",
+            "r2, ASR r3",
+            "r2, ROR r3",
+            "r2, RRX",
+            "r2, ASR #32",
+        ] {
+            let ops = [
+                "ANDS", "EORS", "SUBS", "RSBS", "ADDS", "ADCS", "SBCS", "RSCS", "ORRS", "BICS",
+                "ADDMI", "SBC",
+            ];
+            for op in ops {
+                check_against_jit(
+                    &parse_block(&[&format!("{} r0, r1, {}", op, op2)]),
+                    ARM,
+                    &mut rng,
+                );
+            }
+            for op in ["MOVS", "MVNS", "TST", "TEQ", "CMP", "CMN"] {
+                check_against_jit(&parse_block(&[&format!("{} r0, {}", op, op2)]), ARM, &mut rng);
+            }
+        }
+        for asm in [
+            "MLAS r0, r1, r2, r4",
+            "SMLALS r0, r1, r2, r4",
+            "UMULLS r0, r1, r2, r4",
+            "LSLS r0, r1, #3",
+            "LSRS r0, r1, #32",
+            "ASRS r0, r1, r2",
+            "RORS r0, r1, r2",
+            "RRXS r0, r1",
+            "LSR r0, r1, r2",
+            "MOVGT pc, r1",
+            "BX r5",
+            "BL #256",
+            "BLX #-258",
+            "BLX r5",
+        ] {
+            check_against_jit(&parse_block(&[asm]), ARM, &mut rng);
+        }
+        for asm in [
+            "BL #256",
+            "BL 0x02000100",
+            "BLX #-256",
+            "BLX 0x02000100",
+            "BLX r5",
+        ] {
+            check_against_jit(&parse_block(&[asm]), THUMB, &mut rng);
+        }
+    }
+}
@@ -0,0 +1,763 @@
+//! Micro-ops, a small target-independent IR which instructions are lowered to before they are
+//! executed. Each micro-op does one thing (read a register, run the barrel shifter, update some
+//! flags...), so the semantics of an instruction are defined once by [lower], and then shared by
+//! the interpreter and the Cranelift backend.
+//!
+//! Micro-ops compute 32-bit values held in temporaries. Each temporary is written by exactly one
+//! micro-op, before any micro-op which reads it.
+use super::TranslationError;
+use crate::disasm::{Arch, InstrSet};
+use crate::ir::{
+    AddrMode, ExtraOperand, ExtraValue, Instruction, Offset, OffsetValue, Op, Operand, Register,
+    Shift, ShiftOp,
+};
+
+/// A temporary value, numbered from 0 in the order they are defined
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Temp(pub u16);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    /// Low 32 bits of the product
+    Mul,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemSize {
+    Byte,
+    Half,
+    Word,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UOp {
+    ReadReg {
+        dst: Temp,
+        reg: Register,
+    },
+    WriteReg {
+        reg: Register,
+        src: Temp,
+    },
+    Const {
+        dst: Temp,
+        imm: u32,
+    },
+    /// Reads the C flag as 0 or 1
+    ReadCarry {
+        dst: Temp,
+    },
+    /// The barrel shifter, shifting `value` by the bottom byte of `amount` as for a shift by
+    /// register: LSL and LSR by 32 or more give 0, and ASR by 32 or more gives 32 copies of the
+    /// sign bit. `op` is never RRX, which is lowered to a pair of shifts
+    Shift {
+        dst: Temp,
+        op: ShiftOp,
+        value: Temp,
+        amount: Temp,
+    },
+    /// The carry out of the barrel shifter for the same shift as [UOp::Shift]: the last bit
+    /// shifted out, or `carry_in` if the amount is 0
+    ShiftCarry {
+        dst: Temp,
+        op: ShiftOp,
+        value: Temp,
+        amount: Temp,
+        carry_in: Temp,
+    },
+    Alu {
+        dst: Temp,
+        op: AluOp,
+        a: Temp,
+        b: Temp,
+    },
+    Not {
+        dst: Temp,
+        src: Temp,
+    },
+    /// `a + b + carry_in`, with the unsigned carry out and signed overflow as 0 or 1. This is the
+    /// AddWithCarry() pseudo-code function, which subtraction is also defined in terms of
+    AddWithCarry {
+        dst: Temp,
+        carry_out: Temp,
+        overflow: Temp,
+        a: Temp,
+        b: Temp,
+        carry_in: Temp,
+    },
+    /// 64-bit product of `a` and `b`, split into its low and high words
+    MulLong {
+        lo: Temp,
+        hi: Temp,
+        a: Temp,
+        b: Temp,
+        signed: bool,
+    },
+    /// 64-bit sum `hi:lo = a_hi:a_lo + b_hi:b_lo`
+    AddLong {
+        lo: Temp,
+        hi: Temp,
+        a_lo: Temp,
+        a_hi: Temp,
+        b_lo: Temp,
+        b_hi: Temp,
+    },
+    /// Sets N to bit 31 of `n`, and Z if `z` is 0. The two differ for 64-bit results
+    SetNZ {
+        n: Temp,
+        z: Temp,
+    },
+    /// Sets C to bit 0 of `src`
+    SetC {
+        src: Temp,
+    },
+    /// Sets V to bit 0 of `src`
+    SetV {
+        src: Temp,
+    },
+    /// Loads from `addr`, zero or sign extending to 32 bits. Unaligned word loads are rotated so
+    /// that the addressed byte is the least significant, as on ARMv4/ARMv5
+    Load {
+        dst: Temp,
+        addr: Temp,
+        size: MemSize,
+        signed: bool,
+    },
+    /// Stores the low bits of `src` to `addr`
+    Store {
+        src: Temp,
+        addr: Temp,
+        size: MemSize,
+    },
+    /// Writes PC. With `exchange`, bit 0 of the target is cleared and written to the T bit of
+    /// CPSR instead, selecting the THUMB or ARM instruction set
+    Branch {
+        target: Temp,
+        exchange: bool,
+    },
+}
+
+/// The micro-ops of a single instruction, which are only executed if its condition passes
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Lowered {
+    pub uops: Vec<UOp>,
+    pub num_temps: usize,
+}
+
+struct Lowering<'a> {
+    instr: &'a Instruction,
+    arch: Arch,
+    instr_set: InstrSet,
+    uops: Vec<UOp>,
+    num_temps: u16,
+}
+
+impl<'a> Lowering<'a> {
+    fn temp(&mut self) -> Temp {
+        self.num_temps += 1;
+        Temp(self.num_temps - 1)
+    }
+
+    fn invalid(&self) -> TranslationError {
+        TranslationError::Invalid(*self.instr)
+    }
+
+    fn read(&mut self, reg: Register) -> Temp {
+        let dst = self.temp();
+        self.uops.push(UOp::ReadReg { dst, reg });
+        dst
+    }
+
+    /// Writes a register, which branches if it is PC
+    fn write(&mut self, reg: Register, src: Temp) {
+        match reg {
+            Register::PC => self.uops.push(UOp::Branch { target: src, exchange: false }),
+            _ => self.uops.push(UOp::WriteReg { reg, src }),
+        }
+    }
+
+    fn imm(&mut self, imm: u32) -> Temp {
+        let dst = self.temp();
+        self.uops.push(UOp::Const { dst, imm });
+        dst
+    }
+
+    fn carry(&mut self) -> Temp {
+        let dst = self.temp();
+        self.uops.push(UOp::ReadCarry { dst });
+        dst
+    }
+
+    fn alu(&mut self, op: AluOp, a: Temp, b: Temp) -> Temp {
+        let dst = self.temp();
+        self.uops.push(UOp::Alu { dst, op, a, b });
+        dst
+    }
+
+    fn add_with_carry(&mut self, a: Temp, b: Temp, carry_in: Temp) -> (Temp, Temp, Temp) {
+        let (dst, carry_out, overflow) = (self.temp(), self.temp(), self.temp());
+        self.uops
+            .push(UOp::AddWithCarry { dst, carry_out, overflow, a, b, carry_in });
+        (dst, carry_out, overflow)
+    }
+
+    fn not(&mut self, src: Temp) -> Temp {
+        let dst = self.temp();
+        self.uops.push(UOp::Not { dst, src });
+        dst
+    }
+
+    fn set_nz(&mut self, result: Temp) {
+        self.uops.push(UOp::SetNZ { n: result, z: result });
+    }
+
+    /// Shifts `value`, also returning the carry out if `with_carry` is set
+    fn shift(&mut self, value: Temp, shift: Shift, with_carry: bool) -> (Temp, Option<Temp>) {
+        if shift.op == ShiftOp::RRX {
+            // Shift right by 1, with the old C flag shifted in at the top
+            let one = self.imm(1);
+            let shifted = self.temp();
+            self.uops
+                .push(UOp::Shift { dst: shifted, op: ShiftOp::LSR, value, amount: one });
+            let carry_in = self.carry();
+            let thirty_one = self.imm(31);
+            let top = self.temp();
+            self.uops.push(UOp::Shift {
+                dst: top,
+                op: ShiftOp::LSL,
+                value: carry_in,
+                amount: thirty_one,
+            });
+            let result = self.alu(AluOp::Or, shifted, top);
+            let carry_out = with_carry.then(|| self.alu(AluOp::And, value, one));
+            return (result, carry_out);
+        }
+        let amount = match shift.value {
+            ExtraValue::Imm(imm) => self.imm(imm),
+            ExtraValue::Reg(reg) => self.read(reg),
+        };
+        let dst = self.temp();
+        self.uops
+            .push(UOp::Shift { dst, op: shift.op, value, amount });
+        let carry_out = with_carry.then(|| {
+            let carry_in = self.carry();
+            let carry_out = self.temp();
+            self.uops.push(UOp::ShiftCarry {
+                dst: carry_out,
+                op: shift.op,
+                value,
+                amount,
+                carry_in,
+            });
+            carry_out
+        });
+        (dst, carry_out)
+    }
+
+    /// The flexible second operand of a data-processing instruction, with the shifter carry out
+    /// if `with_carry` is set
+    fn shifter_operand(
+        &mut self,
+        operand: Operand,
+        extra: Option<ExtraOperand>,
+        with_carry: bool,
+    ) -> Result<(Temp, Option<Temp>), TranslationError> {
+        match (operand, extra) {
+            (Operand::Imm(imm), None) => {
+                let value = self.imm(imm);
+                // The immediate has its canonical encoding, which is only rotated, setting C to its
+                // top bit, when it doesn't fit in 8 bits
+                let carry = with_carry.then(|| match imm {
+                    0..=0xFF => self.carry(),
+                    _ => self.imm(imm >> 31),
+                });
+                Ok((value, carry))
+            }
+            // Any other rotation is explicit, see disasm::arm::decode_rotated_imm
+            (
+                Operand::Imm(imm),
+                Some(ExtraOperand::Shift(Shift { op: ShiftOp::ROR, value: ExtraValue::Imm(rot) })),
+            ) => {
+                let imm = imm.rotate_right(rot);
+                let value = self.imm(imm);
+                let carry = with_carry.then(|| match rot {
+                    0 => self.carry(),
+                    _ => self.imm(imm >> 31),
+                });
+                Ok((value, carry))
+            }
+            (Operand::Reg(reg), None) => {
+                let value = self.read(reg);
+                Ok((value, with_carry.then(|| self.carry())))
+            }
+            (Operand::Reg(reg), Some(ExtraOperand::Shift(shift))) => {
+                let value = self.read(reg);
+                Ok(self.shift(value, shift, with_carry))
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// Splits the operands of a data-processing instruction into Rd, Rn and the shifter operand.
+    /// Rn is also the destination of the two operand form
+    fn data_proc_operands(&self) -> Result<(Register, Register, Operand), TranslationError> {
+        match self.instr.operands[..] {
+            [Operand::Reg(rd), Operand::Reg(rn), op2] => Ok((rd, rn, op2)),
+            [Operand::Reg(rd), op2] => Ok((rd, rd, op2)),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// Writes the result of a data-processing instruction. Setting the flags while writing PC
+    /// returns from an exception, which needs SPSR
+    fn write_result(&mut self, rd: Register, result: Temp) -> Result<(), TranslationError> {
+        if self.instr.set_flags && rd == Register::PC {
+            return Err(TranslationError::Unimplemented(*self.instr));
+        }
+        self.write(rd, result);
+        Ok(())
+    }
+
+    /// The arithmetic data-processing operations, in terms of AddWithCarry() as in the
+    /// pseudo-code: subtraction adds the inverted operand with a carry in of 1, and the reverse
+    /// subtractions swap the operands. Returns the result, carry out and overflow
+    fn arithmetic(&mut self, a: Temp, b: Temp) -> (Temp, Temp, Temp) {
+        let op = self.instr.op;
+        let (a, b) = match op {
+            Op::RSB | Op::RSC => (b, a),
+            _ => (a, b),
+        };
+        let b = match op {
+            Op::SUB | Op::SBC | Op::RSB | Op::RSC | Op::CMP => self.not(b),
+            _ => b,
+        };
+        let carry_in = match op {
+            Op::ADC | Op::SBC | Op::RSC => self.carry(),
+            Op::SUB | Op::RSB | Op::CMP => self.imm(1),
+            _ => self.imm(0),
+        };
+        self.add_with_carry(a, b, carry_in)
+    }
+
+    /// The shift instructions, e.g. "LSL r0, r1, #2", as the MOV of a shifted register they're
+    /// encoded as. Returns Rd, the register and the shift
+    fn shift_operands(&self) -> Result<(Register, Operand, Shift), TranslationError> {
+        let shift_op = match self.instr.op {
+            Op::LSL => ShiftOp::LSL,
+            Op::LSR => ShiftOp::LSR,
+            Op::ASR => ShiftOp::ASR,
+            Op::ROR => ShiftOp::ROR,
+            _ => ShiftOp::RRX,
+        };
+        match (shift_op, &self.instr.operands[..]) {
+            (ShiftOp::RRX, &[Operand::Reg(rd), rm @ Operand::Reg(_)]) => {
+                Ok((rd, rm, Shift::imm(ShiftOp::RRX, 1)))
+            }
+            (ShiftOp::RRX, _) => Err(self.invalid()),
+            (_, &[Operand::Reg(rd), rm @ Operand::Reg(_), Operand::Imm(imm)]) => {
+                Ok((rd, rm, Shift::imm(shift_op, imm)))
+            }
+            (_, &[Operand::Reg(rd), rm @ Operand::Reg(_), Operand::Reg(rs)]) => {
+                Ok((rd, rm, Shift::reg(shift_op, rs)))
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// The data-processing instructions. With the S suffix, the arithmetic operations set all of
+    /// NZCV from the adder, while the logical ones set N and Z from the result and C from the
+    /// shifter, leaving V unchanged
+    fn lower_data_proc(&mut self) -> Result<(), TranslationError> {
+        let op = self.instr.op;
+        let set_flags = self.instr.set_flags;
+        let mut extra = self.instr.extra;
+        let (rd, rn, op2) = match (op, &self.instr.operands[..]) {
+            (Op::MOV | Op::MVN, &[Operand::Reg(rd), op2]) => (Some(rd), None, op2),
+            (Op::TST | Op::TEQ | Op::CMP | Op::CMN, &[Operand::Reg(rn), op2]) => {
+                (None, Some(rn), op2)
+            }
+            (Op::MOV | Op::MVN | Op::TST | Op::TEQ | Op::CMP | Op::CMN, _) => {
+                return Err(self.invalid());
+            }
+            (Op::LSL | Op::LSR | Op::ASR | Op::ROR | Op::RRX, _) => {
+                let (rd, rm, shift) = self.shift_operands()?;
+                if extra.is_some() {
+                    return Err(self.invalid());
+                }
+                extra = Some(shift.into());
+                (Some(rd), None, rm)
+            }
+            _ => {
+                let (rd, rn, op2) = self.data_proc_operands()?;
+                (Some(rd), Some(rn), op2)
+            }
+        };
+        let arithmetic = matches!(
+            op,
+            Op::ADD | Op::ADC | Op::SUB | Op::SBC | Op::RSB | Op::RSC | Op::CMP | Op::CMN
+        );
+        let a = rn.map(|rn| self.read(rn));
+        let (b, shifter_carry) = self.shifter_operand(op2, extra, set_flags && !arithmetic)?;
+
+        let mut adder_flags = None;
+        let result = match (op, a) {
+            (Op::MOV | Op::LSL | Op::LSR | Op::ASR | Op::ROR | Op::RRX, _) => b,
+            (Op::MVN, _) => self.not(b),
+            (Op::AND | Op::TST, Some(a)) => self.alu(AluOp::And, a, b),
+            (Op::EOR | Op::TEQ, Some(a)) => self.alu(AluOp::Xor, a, b),
+            (Op::ORR, Some(a)) => self.alu(AluOp::Or, a, b),
+            (Op::BIC, Some(a)) => {
+                let not_b = self.not(b);
+                self.alu(AluOp::And, a, not_b)
+            }
+            // Without the flags, the simple cases don't need the adder
+            (Op::ADD, Some(a)) if !set_flags => self.alu(AluOp::Add, a, b),
+            (Op::SUB, Some(a)) if !set_flags => self.alu(AluOp::Sub, a, b),
+            (Op::RSB, Some(a)) if !set_flags => self.alu(AluOp::Sub, b, a),
+            (_, Some(a)) if arithmetic => {
+                let (result, carry, overflow) = self.arithmetic(a, b);
+                adder_flags = Some((carry, overflow));
+                result
+            }
+            _ => return Err(self.invalid()),
+        };
+        if let Some(rd) = rd {
+            self.write_result(rd, result)?;
+        }
+        if set_flags {
+            self.set_nz(result);
+            match (adder_flags, shifter_carry) {
+                (Some((carry, overflow)), _) => {
+                    self.uops.push(UOp::SetC { src: carry });
+                    self.uops.push(UOp::SetV { src: overflow });
+                }
+                (None, Some(carry)) => self.uops.push(UOp::SetC { src: carry }),
+                (None, None) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// MUL and MLA, which keep the low 32 bits of the product
+    fn lower_multiply(&mut self) -> Result<(), TranslationError> {
+        let (rd, rm, rs, rn) = match (self.instr.op, &self.instr.operands[..]) {
+            (Op::MUL, &[Operand::Reg(rd), Operand::Reg(rm), Operand::Reg(rs)]) => {
+                (rd, rm, rs, None)
+            }
+            (
+                Op::MLA,
+                &[Operand::Reg(rd), Operand::Reg(rm), Operand::Reg(rs), Operand::Reg(rn)],
+            ) => (rd, rm, rs, Some(rn)),
+            _ => return Err(self.invalid()),
+        };
+        let (a, b) = (self.read(rm), self.read(rs));
+        let mut result = self.alu(AluOp::Mul, a, b);
+        if let Some(rn) = rn {
+            let acc = self.read(rn);
+            result = self.alu(AluOp::Add, result, acc);
+        }
+        self.write(rd, result);
+        if self.instr.set_flags {
+            self.set_nz(result);
+        }
+        Ok(())
+    }
+
+    /// UMULL/UMLAL/SMULL/SMLAL, which produce a 64-bit result in RdLo and RdHi
+    fn lower_long_multiply(&mut self) -> Result<(), TranslationError> {
+        let (rd_lo, rd_hi, rm, rs) = match self.instr.operands[..] {
+            [Operand::Reg(lo), Operand::Reg(hi), Operand::Reg(rm), Operand::Reg(rs)] => {
+                (lo, hi, rm, rs)
+            }
+            _ => return Err(self.invalid()),
+        };
+        let (a, b) = (self.read(rm), self.read(rs));
+        let (mut lo, mut hi) = (self.temp(), self.temp());
+        let signed = matches!(self.instr.op, Op::SMULL | Op::SMLAL);
+        self.uops.push(UOp::MulLong { lo, hi, a, b, signed });
+        if let Op::UMLAL | Op::SMLAL = self.instr.op {
+            // The accumulate value is RdHi:RdLo
+            let (b_lo, b_hi) = (self.read(rd_lo), self.read(rd_hi));
+            let (a_lo, a_hi) = (lo, hi);
+            (lo, hi) = (self.temp(), self.temp());
+            self.uops
+                .push(UOp::AddLong { lo, hi, a_lo, a_hi, b_lo, b_hi });
+        }
+        self.write(rd_lo, lo);
+        self.write(rd_hi, hi);
+        if self.instr.set_flags {
+            let z = self.alu(AluOp::Or, lo, hi);
+            self.uops.push(UOp::SetNZ { n: hi, z });
+        }
+        Ok(())
+    }
+
+    /// Returns the address to transfer, and the updated base if it is written back
+    fn address(
+        &mut self,
+        operand: Operand,
+    ) -> Result<(Temp, Option<(Register, Temp)>), TranslationError> {
+        let (addr, offset) = match (operand, self.instr.extra) {
+            (Operand::Target(target), None) => return Ok((self.imm(target), None)),
+            (Operand::Addr(addr), None) => (addr, Offset::imm(0, true)),
+            (Operand::Addr(addr), Some(ExtraOperand::Offset(offset))) => (addr, offset),
+            _ => return Err(self.invalid()),
+        };
+        let base = self.read(addr.base);
+        let offset_value = match offset.value {
+            OffsetValue::Imm(imm) => self.imm(imm),
+            OffsetValue::Reg { reg, shift: None } => self.read(reg),
+            OffsetValue::Reg { reg, shift: Some(shift) } => {
+                let value = self.read(reg);
+                self.shift(value, shift.into(), false).0
+            }
+        };
+        let op = if offset.add { AluOp::Add } else { AluOp::Sub };
+        let offset_addr = self.alu(op, base, offset_value);
+        Ok(match addr.mode {
+            AddrMode::Offset => (offset_addr, None),
+            AddrMode::PreIndex => (offset_addr, Some((addr.base, offset_addr))),
+            AddrMode::PostIndex => (base, Some((addr.base, offset_addr))),
+        })
+    }
+
+    /// LDR/STR and the byte, halfword and signed variants
+    fn lower_load_store(&mut self) -> Result<(), TranslationError> {
+        let (rt, addr) = match self.instr.operands[..] {
+            [Operand::Reg(rt), addr] => (rt, addr),
+            _ => return Err(self.invalid()),
+        };
+        let (size, signed) = match self.instr.op {
+            Op::LDR | Op::STR => (MemSize::Word, false),
+            Op::LDRB | Op::STRB => (MemSize::Byte, false),
+            Op::LDRH | Op::STRH => (MemSize::Half, false),
+            Op::LDRSB => (MemSize::Byte, true),
+            _ => (MemSize::Half, true),
+        };
+        let (addr, writeback) = self.address(addr)?;
+        if self.instr.reads_memory() {
+            let dst = self.temp();
+            self.uops.push(UOp::Load { dst, addr, size, signed });
+            if let Some((base, value)) = writeback {
+                self.write(base, value);
+            }
+            // From ARMv5TE, a load to PC interworks as BX does
+            match rt {
+                Register::PC if self.arch >= Arch::ARMv5TE => {
+                    self.uops.push(UOp::Branch { target: dst, exchange: true })
+                }
+                _ => self.write(rt, dst),
+            }
+        } else {
+            let src = self.read(rt);
+            self.uops.push(UOp::Store { src, addr, size });
+            if let Some((base, value)) = writeback {
+                self.write(base, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// B, BL, BX and BLX (register). Branch targets are either resolved, or an offset from PC
+    fn lower_branch(&mut self) -> Result<(), TranslationError> {
+        let op = self.instr.op;
+        let thumb = self.instr_set == InstrSet::THUMB;
+        let (target, exchange) = match (op, &self.instr.operands[..]) {
+            (Op::B | Op::BL | Op::BLX, &[Operand::Target(target)]) => (self.imm(target), false),
+            (Op::B | Op::BL | Op::BLX, &[Operand::Imm(offset)]) => {
+                let mut pc = self.read(Register::PC);
+                // BLX (immediate) from THUMB is relative to the word aligned PC
+                if thumb && op == Op::BLX {
+                    let mask = self.imm(!0b11);
+                    pc = self.alu(AluOp::And, pc, mask);
+                }
+                let offset = self.imm(offset);
+                (self.alu(AluOp::Add, pc, offset), false)
+            }
+            (Op::BX | Op::BLX, &[Operand::Reg(rm)]) => (self.read(rm), true),
+            _ => return Err(self.invalid()),
+        };
+        // BLX (immediate) always switches instruction set: to THUMB from ARM, and to ARM from
+        // THUMB, where the target is already word aligned
+        let (target, exchange) = match (op, exchange) {
+            (Op::BLX, false) if !thumb => {
+                let one = self.imm(1);
+                (self.alu(AluOp::Or, target, one), true)
+            }
+            (Op::BLX, false) => (target, true),
+            _ => (target, exchange),
+        };
+        if let Op::BL | Op::BLX = self.instr.op {
+            // PC reads as the address of the instruction plus 8 in ARM, and plus 4 in THUMB. The
+            // THUMB BL/BLX (immediate) pair is 4 bytes long and BLX (register) 2, and its link
+            // has bit 0 set to return to THUMB
+            let pc = self.read(Register::PC);
+            let link = match (thumb, &self.instr.operands[..]) {
+                (false, _) => {
+                    let four = self.imm(4);
+                    self.alu(AluOp::Sub, pc, four)
+                }
+                (true, &[Operand::Reg(_)]) => {
+                    let one = self.imm(1);
+                    let two = self.imm(2);
+                    let next = self.alu(AluOp::Sub, pc, two);
+                    self.alu(AluOp::Or, next, one)
+                }
+                (true, _) => {
+                    let one = self.imm(1);
+                    self.alu(AluOp::Or, pc, one)
+                }
+            };
+            self.write(Register::LR, link);
+        }
+        self.uops.push(UOp::Branch { target, exchange });
+        Ok(())
+    }
+}
+
+/// Lower an instruction from `instr_set` code to micro-ops for `arch`, ignoring its condition.
+/// Reading PC gives whatever value the CPU state holds for it, which should be the address of the
+/// instruction plus 8 in ARM, or plus 4 in THUMB
+pub fn lower(
+    instr: &Instruction,
+    arch: Arch,
+    instr_set: InstrSet,
+) -> Result<Lowered, TranslationError> {
+    let mut lowering = Lowering { instr, arch, instr_set, uops: vec![], num_temps: 0 };
+    match instr.op {
+        Op::AND
+        | Op::EOR
+        | Op::SUB
+        | Op::RSB
+        | Op::ADD
+        | Op::ADC
+        | Op::SBC
+        | Op::RSC
+        | Op::TST
+        | Op::TEQ
+        | Op::CMP
+        | Op::CMN
+        | Op::ORR
+        | Op::MOV
+        | Op::LSL
+        | Op::LSR
+        | Op::ASR
+        | Op::ROR
+        | Op::RRX
+        | Op::BIC
+        | Op::MVN => lowering.lower_data_proc()?,
+        Op::MUL | Op::MLA => lowering.lower_multiply()?,
+        Op::UMULL | Op::UMLAL | Op::SMULL | Op::SMLAL => lowering.lower_long_multiply()?,
+        Op::LDR | Op::LDRB | Op::LDRH | Op::LDRSB | Op::LDRSH | Op::STR | Op::STRB | Op::STRH => {
+            lowering.lower_load_store()?
+        }
+        Op::B | Op::BL | Op::BX | Op::BLX => lowering.lower_branch()?,
+        _ => return Err(TranslationError::Unimplemented(*instr)),
+    }
+    Ok(Lowered { uops: lowering.uops, num_temps: lowering.num_temps as usize })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lower, AluOp, MemSize, Temp, UOp};
+    use crate::disasm::{disassemble_arm, Arch::*, InstrSet::*};
+    use crate::ir::{parsing::instruction, Register::*, ShiftOp};
+    use crate::translate::TranslationError;
+
+    fn lower_asm(asm: &str) -> Vec<UOp> {
+        lower(&instruction(asm).unwrap().1, ARMv5TE, ARM)
+            .unwrap()
+            .uops
+    }
+
+    #[test]
+    fn test_lower() {
+        let t = Temp;
+        assert_eq!(
+            lower_asm("ADD r0, r1, r2, LSL #3"),
+            vec![
+                UOp::ReadReg { dst: t(0), reg: R1 },
+                UOp::ReadReg { dst: t(1), reg: R2 },
+                UOp::Const { dst: t(2), imm: 3 },
+                UOp::Shift { dst: t(3), op: ShiftOp::LSL, value: t(1), amount: t(2) },
+                UOp::Alu { dst: t(4), op: AluOp::Add, a: t(0), b: t(3) },
+                UOp::WriteReg { reg: R0, src: t(4) },
+            ]
+        );
+        // The shifter carry is only needed by logical instructions which set the flags
+        assert_eq!(
+            lower_asm("MOVS r0, r1, ROR r2"),
+            vec![
+                UOp::ReadReg { dst: t(0), reg: R1 },
+                UOp::ReadReg { dst: t(1), reg: R2 },
+                UOp::Shift { dst: t(2), op: ShiftOp::ROR, value: t(0), amount: t(1) },
+                UOp::ReadCarry { dst: t(3) },
+                UOp::ShiftCarry {
+                    dst: t(4),
+                    op: ShiftOp::ROR,
+                    value: t(0),
+                    amount: t(1),
+                    carry_in: t(3)
+                },
+                UOp::WriteReg { reg: R0, src: t(2) },
+                UOp::SetNZ { n: t(2), z: t(2) },
+                UOp::SetC { src: t(4) },
+            ]
+        );
+        assert_eq!(
+            lower_asm("LDRB r0, [r1], -r2"),
+            vec![
+                UOp::ReadReg { dst: t(0), reg: R1 },
+                UOp::ReadReg { dst: t(1), reg: R2 },
+                UOp::Alu { dst: t(2), op: AluOp::Sub, a: t(0), b: t(1) },
+                UOp::Load { dst: t(3), addr: t(0), size: MemSize::Byte, signed: false },
+                UOp::WriteReg { reg: R1, src: t(2) },
+                UOp::WriteReg { reg: R0, src: t(3) },
+            ]
+        );
+        assert!(matches!(lower_asm("MOV pc, lr")[1], UOp::Branch { exchange: false, .. }));
+        assert!(matches!(lower_asm("BX lr")[1], UOp::Branch { exchange: true, .. }));
+        // Loads to PC only interwork from ARMv5TE
+        let ldr_pc = instruction("LDR pc, [sp], #4").unwrap().1;
+        let last = |arch| lower(&ldr_pc, arch, ARM).unwrap().uops.last().copied();
+        assert!(matches!(last(ARMv5TE), Some(UOp::Branch { exchange: true, .. })));
+        assert!(matches!(last(ARMv4T), Some(UOp::Branch { exchange: false, .. })));
+
+        let lowered =
+            lower(&instruction("UMLALS r0, r1, r2, r3").unwrap().1, ARMv5TE, ARM).unwrap();
+        assert_eq!(lowered.num_temps, 9);
+        assert_eq!(lowered.uops.last(), Some(&UOp::SetNZ { n: t(7), z: t(8) }));
+
+        let svc = instruction("SVC #0").unwrap().1;
+        assert!(matches!(lower(&svc, ARMv5TE, ARM), Err(TranslationError::Unimplemented(_))));
+        let movs_pc = instruction("MOVS pc, lr").unwrap().1;
+        assert!(matches!(lower(&movs_pc, ARMv5TE, ARM), Err(TranslationError::Unimplemented(_))));
+    }
+
+    #[test]
+    fn test_rotated_imm_carry() {
+        // movs r0, #1, encoded as 4 rotated right by 2, which sets C to bit 31 of the result
+        let movs = disassemble_arm(0xE3B0_0104, ARMv5TE).unwrap();
+        let uops = lower(&movs, ARMv5TE, ARM).unwrap().uops;
+        let t = Temp;
+        assert_eq!(
+            uops,
+            vec![
+                UOp::Const { dst: t(0), imm: 1 },
+                UOp::Const { dst: t(1), imm: 0 },
+                UOp::WriteReg { reg: R0, src: t(0) },
+                UOp::SetNZ { n: t(0), z: t(0) },
+                UOp::SetC { src: t(1) },
+            ]
+        );
+        // The canonical, unrotated encoding leaves C alone
+        let movs = disassemble_arm(0xE3B0_0001, ARMv5TE).unwrap();
+        let uops = lower(&movs, ARMv5TE, ARM).unwrap().uops;
+        assert!(uops.iter().any(|uop| matches!(uop, UOp::ReadCarry { .. })));
+    }
+}
@@ -1,10 +1,361 @@
-use std::mem::size_of;
+use std::mem;
 
+use crate::disasm::InstrSet;
 use crate::ir::Register;
 
-#[repr(C, packed)]
+/// Bits of CPSR/SPSR below the condition flags
+pub const CPSR_MODE_MASK: u32 = 0x1F;
+pub const CPSR_T: u32 = 1 << 5;
+pub const CPSR_F: u32 = 1 << 6;
+pub const CPSR_I: u32 = 1 << 7;
+
+/// Processor modes, with the values of the CPSR mode bits
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    USR = 0x10,
+    FIQ = 0x11,
+    IRQ = 0x12,
+    SVC = 0x13,
+    ABT = 0x17,
+    UND = 0x1B,
+    SYS = 0x1F,
+}
+
+impl Mode {
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        let mode = match bits & CPSR_MODE_MASK {
+            0x10 => Self::USR,
+            0x11 => Self::FIQ,
+            0x12 => Self::IRQ,
+            0x13 => Self::SVC,
+            0x17 => Self::ABT,
+            0x1B => Self::UND,
+            0x1F => Self::SYS,
+            _ => return None,
+        };
+        Some(mode)
+    }
+
+    /// Index of the mode's copies of R13, R14 and SPSR. USR and SYS share the registers, and have
+    /// no SPSR
+    fn bank(self) -> usize {
+        match self {
+            Self::USR | Self::SYS => 0,
+            Self::FIQ => 1,
+            Self::IRQ => 2,
+            Self::SVC => 3,
+            Self::ABT => 4,
+            Self::UND => 5,
+        }
+    }
+
+    pub fn has_spsr(self) -> bool {
+        self.bank() != 0
+    }
+
+    pub fn is_privileged(self) -> bool {
+        self != Self::USR
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Exception {
+    Reset,
+    Undefined,
+    SoftwareInterrupt,
+    PrefetchAbort,
+    DataAbort,
+    IRQ,
+    FIQ,
+}
+
+impl Exception {
+    /// Offset of the exception vector from the vector base
+    pub fn vector(self) -> u32 {
+        match self {
+            Self::Reset => 0x00,
+            Self::Undefined => 0x04,
+            Self::SoftwareInterrupt => 0x08,
+            Self::PrefetchAbort => 0x0C,
+            Self::DataAbort => 0x10,
+            Self::IRQ => 0x18,
+            Self::FIQ => 0x1C,
+        }
+    }
+
+    /// The mode the exception is taken in
+    pub fn mode(self) -> Mode {
+        match self {
+            Self::Reset | Self::SoftwareInterrupt => Mode::SVC,
+            Self::Undefined => Mode::UND,
+            Self::PrefetchAbort | Self::DataAbort => Mode::ABT,
+            Self::IRQ => Mode::IRQ,
+            Self::FIQ => Mode::FIQ,
+        }
+    }
+}
+
+/// State of an ARM CPU, including the registers banked by each processor mode.
+///
+/// The registers of the current mode are kept in `regs`, which translated code accesses
+/// directly, so a block runs with whichever bank was active when it was called. The other banks
+/// are swapped in and out when the mode changes
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct VMState {
-    regs: [u32; 17],
+    /// R0-R15 of the current mode, followed by CPSR, indexed by [Register]. CPSR's condition
+    /// flags are the FLAGS register
+    pub regs: [u32; 17],
+    /// R8-R12 of every mode but FIQ, which are only up to date while in FIQ mode
+    usr_r8_r12: [u32; 5],
+    /// FIQ's R8-R12, which are only up to date while in another mode
+    fiq_r8_r12: [u32; 5],
+    /// R13 and R14 of each bank, which are only up to date while the bank isn't active
+    sp_lr: [[u32; 2]; 6],
+    /// SPSR of each bank. The USR/SYS bank has no SPSR, so its entry is unused
+    spsr: [u32; 6],
+    /// Base address of the exception vectors, 0 or 0xFFFF0000
+    pub vector_base: u32,
+    /// The mode whose banked registers are in `regs`. This is the mode in CPSR unless CPSR was
+    /// written directly, e.g. through `regs` or by translated code, with different mode bits
+    bank_mode: Mode,
+}
+
+impl Default for VMState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl VMState {}
+impl VMState {
+    /// The state after reset: ARM code in SVC mode, with interrupts disabled
+    pub fn new() -> Self {
+        let mut regs = [0; 17];
+        regs[Register::FLAGS as usize] = Mode::SVC as u32 | CPSR_I | CPSR_F;
+        Self {
+            regs,
+            usr_r8_r12: [0; 5],
+            fiq_r8_r12: [0; 5],
+            sp_lr: [[0; 2]; 6],
+            spsr: [0; 6],
+            vector_base: 0,
+            bank_mode: Mode::SVC,
+        }
+    }
+
+    /// Offset of a register of the current mode from the start of the state
+    pub fn reg_offset(reg: Register) -> i32 {
+        (mem::offset_of!(Self, regs) + reg as usize * mem::size_of::<u32>()) as i32
+    }
+
+    pub fn reg(&self, reg: Register) -> u32 {
+        self.regs[reg as usize]
+    }
+
+    pub fn set_reg(&mut self, reg: Register, value: u32) {
+        self.regs[reg as usize] = value;
+    }
+
+    pub fn cpsr(&self) -> u32 {
+        self.regs[Register::FLAGS as usize]
+    }
+
+    /// The current mode. [VMState::set_cpsr] won't write invalid mode bits, but CPSR can also be
+    /// written directly, so if it holds invalid ones this is the mode whose registers are active
+    pub fn mode(&self) -> Mode {
+        Mode::from_bits(self.cpsr()).unwrap_or(self.bank_mode)
+    }
+
+    /// The instruction set selected by the T bit
+    pub fn instr_set(&self) -> InstrSet {
+        match self.cpsr() & CPSR_T {
+            0 => InstrSet::ARM,
+            _ => InstrSet::THUMB,
+        }
+    }
+
+    /// Writes CPSR, switching to the bank of the new mode. Writing invalid mode bits is
+    /// UNPREDICTABLE, and leaves the mode unchanged
+    pub fn set_cpsr(&mut self, value: u32) {
+        let value = match Mode::from_bits(value) {
+            Some(mode) => {
+                self.switch_mode(mode);
+                value
+            }
+            None => (value & !CPSR_MODE_MASK) | (self.cpsr() & CPSR_MODE_MASK),
+        };
+        self.regs[Register::FLAGS as usize] = value;
+    }
+
+    /// Changes the mode bits of CPSR, swapping the banked registers of the old mode for those of
+    /// the new one
+    pub fn switch_mode(&mut self, mode: Mode) {
+        let old = self.bank_mode;
+        let (r8_r12, r13_r14) = self.regs[8..15].split_at_mut(5);
+        if old == Mode::FIQ {
+            self.fiq_r8_r12.copy_from_slice(r8_r12);
+        } else {
+            self.usr_r8_r12.copy_from_slice(r8_r12);
+        }
+        self.sp_lr[old.bank()].copy_from_slice(r13_r14);
+
+        if mode == Mode::FIQ {
+            r8_r12.copy_from_slice(&self.fiq_r8_r12);
+        } else {
+            r8_r12.copy_from_slice(&self.usr_r8_r12);
+        }
+        r13_r14.copy_from_slice(&self.sp_lr[mode.bank()]);
+        self.bank_mode = mode;
+
+        let cpsr = &mut self.regs[Register::FLAGS as usize];
+        *cpsr = (*cpsr & !CPSR_MODE_MASK) | mode as u32;
+    }
+
+    /// A register as seen from the given mode, whether or not it is the current one. The "^"
+    /// forms of LDM/STM use this to access the USR mode registers
+    pub fn banked_reg(&self, mode: Mode, reg: Register) -> u32 {
+        let current = self.bank_mode;
+        match reg as usize {
+            8..=12 if (mode == Mode::FIQ) != (current == Mode::FIQ) => match mode {
+                Mode::FIQ => self.fiq_r8_r12[reg as usize - 8],
+                _ => self.usr_r8_r12[reg as usize - 8],
+            },
+            13 | 14 if mode.bank() != current.bank() => self.sp_lr[mode.bank()][reg as usize - 13],
+            _ => self.regs[reg as usize],
+        }
+    }
+
+    pub fn set_banked_reg(&mut self, mode: Mode, reg: Register, value: u32) {
+        let current = self.bank_mode;
+        match reg as usize {
+            8..=12 if (mode == Mode::FIQ) != (current == Mode::FIQ) => match mode {
+                Mode::FIQ => self.fiq_r8_r12[reg as usize - 8] = value,
+                _ => self.usr_r8_r12[reg as usize - 8] = value,
+            },
+            13 | 14 if mode.bank() != current.bank() => {
+                self.sp_lr[mode.bank()][reg as usize - 13] = value
+            }
+            _ => self.regs[reg as usize] = value,
+        }
+    }
+
+    /// SPSR of the current mode, or None in USR and SYS modes which don't have one
+    pub fn spsr(&self) -> Option<u32> {
+        let mode = self.bank_mode;
+        mode.has_spsr().then(|| self.spsr[mode.bank()])
+    }
+
+    /// Writes SPSR of the current mode. This is UNPREDICTABLE in USR and SYS modes, and ignored
+    pub fn set_spsr(&mut self, value: u32) {
+        let mode = self.bank_mode;
+        if mode.has_spsr() {
+            self.spsr[mode.bank()] = value;
+        }
+    }
+
+    /// Takes an exception: saves CPSR to the SPSR of the exception's mode, switches to it, and
+    /// jumps to the vector in ARM state with IRQs (and for FIQ and reset, FIQs) disabled.
+    /// `return_addr` is written to LR
+    pub fn enter_exception(&mut self, exception: Exception, return_addr: u32) {
+        let cpsr = self.cpsr();
+        let mode = exception.mode();
+        self.switch_mode(mode);
+        self.spsr[mode.bank()] = cpsr;
+        let mut disabled = CPSR_I;
+        if let Exception::Reset | Exception::FIQ = exception {
+            disabled |= CPSR_F;
+        }
+        let cpsr = &mut self.regs[Register::FLAGS as usize];
+        *cpsr = (*cpsr & !CPSR_T) | disabled;
+        self.regs[Register::LR as usize] = return_addr;
+        self.regs[Register::PC as usize] = self.vector_base.wrapping_add(exception.vector());
+    }
+
+    /// Copies SPSR to CPSR, as when returning from an exception. Does nothing in USR and SYS modes
+    pub fn restore_cpsr(&mut self) {
+        if let Some(spsr) = self.spsr() {
+            self.set_cpsr(spsr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Exception, Mode, VMState, CPSR_F, CPSR_I, CPSR_T};
+    use crate::disasm::InstrSet;
+    use crate::ir::{Register::*, FLAG_Z};
+
+    #[test]
+    fn test_banked_regs() {
+        let mut state = VMState::new();
+        assert_eq!(state.mode(), Mode::SVC);
+        state.regs[..16].fill(1);
+        state.set_cpsr(Mode::USR as u32);
+
+        // Only R13 and R14 are banked between SVC and USR
+        assert_eq!(state.reg(R12), 1);
+        assert_eq!(state.reg(SP), 0);
+        state.set_reg(SP, 0x100);
+        state.set_reg(R8, 0x200);
+
+        // FIQ mode also banks R8-R12
+        state.switch_mode(Mode::FIQ);
+        assert_eq!((state.reg(R7), state.reg(R8), state.reg(SP)), (1, 0, 0));
+        state.set_reg(R8, 0x300);
+        assert_eq!(state.banked_reg(Mode::USR, R8), 0x200);
+        assert_eq!(state.banked_reg(Mode::SVC, SP), 1);
+        state.set_banked_reg(Mode::SYS, LR, 0x400);
+
+        // SYS shares the USR registers
+        state.switch_mode(Mode::SYS);
+        assert_eq!((state.reg(R8), state.reg(SP), state.reg(LR)), (0x200, 0x100, 0x400));
+        assert_eq!(state.banked_reg(Mode::FIQ, R8), 0x300);
+        state.switch_mode(Mode::SVC);
+        assert_eq!((state.reg(R8), state.reg(SP)), (0x200, 1));
+
+        // An invalid mode is ignored
+        state.set_cpsr(FLAG_Z);
+        assert_eq!(state.mode(), Mode::SVC);
+        assert_eq!(state.cpsr(), FLAG_Z | Mode::SVC as u32);
+
+        // Writing CPSR directly can leave invalid mode bits, and the SVC registers stay active
+        state.set_reg(SP, 0x500);
+        state.set_reg(FLAGS, FLAG_Z);
+        assert_eq!(state.mode(), Mode::SVC);
+        state.switch_mode(Mode::USR);
+        assert_eq!((state.reg(SP), state.cpsr()), (0x100, FLAG_Z | Mode::USR as u32));
+        assert_eq!(state.banked_reg(Mode::SVC, SP), 0x500);
+        state.set_reg(FLAGS, 0);
+        state.set_cpsr(Mode::SVC as u32);
+        assert_eq!(state.reg(SP), 0x500);
+        assert_eq!(state.spsr(), Some(0));
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let mut state = VMState::new();
+        let user_cpsr = FLAG_Z | CPSR_T | Mode::USR as u32;
+        state.set_cpsr(user_cpsr);
+        assert_eq!(state.instr_set(), InstrSet::THUMB);
+        assert_eq!(state.spsr(), None);
+        state.set_reg(LR, 0x1234);
+        state.vector_base = 0xFFFF_0000;
+
+        state.enter_exception(Exception::IRQ, 0x0200_0004);
+        assert_eq!(state.mode(), Mode::IRQ);
+        assert_eq!(state.cpsr(), FLAG_Z | CPSR_I | Mode::IRQ as u32);
+        assert_eq!(state.spsr(), Some(user_cpsr));
+        assert_eq!((state.reg(LR), state.reg(PC)), (0x0200_0004, 0xFFFF_0018));
+
+        // A nested FIQ saves the IRQ mode state
+        state.enter_exception(Exception::FIQ, 0);
+        assert_eq!(state.cpsr() & (CPSR_I | CPSR_F), CPSR_I | CPSR_F);
+        assert_eq!(state.spsr(), Some(FLAG_Z | CPSR_I | Mode::IRQ as u32));
+        state.restore_cpsr();
+        assert_eq!(state.mode(), Mode::IRQ);
+        assert_eq!(state.reg(LR), 0x0200_0004);
+        state.restore_cpsr();
+        assert_eq!(state.cpsr(), user_cpsr);
+        assert_eq!(state.reg(LR), 0x1234);
+    }
+}
@@ -7,7 +7,8 @@ use std::process::{Command, Stdio};
 use std::str::FromStr;
 
 use itertools::Itertools;
-use ndsjit::disasm::disassemble_arm;
+use ndsjit::disasm::{disassemble_arm, disassemble_arm_at, Arch};
+use ndsjit::ir::Op;
 use rand::{seq::SliceRandom, thread_rng, Rng};
 use rstest::rstest;
 
@@ -72,6 +73,7 @@ impl AsmGenerator {
         self
     }
 
+    #[allow(dead_code)]
     fn no_cond(&mut self) -> &mut Self {
         self.cond = false;
         self
@@ -161,13 +163,12 @@ impl AsmGenerator {
                 }
                 write!(line, " {reg}").unwrap();
 
-                if self.end_addr.is_some() && i + 1 == self.end_addr.unwrap() {
-                    let excl =
-                        if self.num_regs == self.end_addr.unwrap() && self.imm_value.is_none() {
-                            ["", "!"].choose(&mut rng).unwrap()
-                        } else {
-                            ""
-                        };
+                if let Some(end_addr) = self.end_addr.filter(|&end| i + 1 == end) {
+                    let excl = if self.num_regs == end_addr && self.imm_value.is_none() {
+                        ["", "!"].choose(&mut rng).unwrap()
+                    } else {
+                        ""
+                    };
                     write!(line, " ]{excl}").unwrap();
                 }
                 if i + 1 != self.num_regs {
@@ -182,15 +183,14 @@ impl AsmGenerator {
                     write!(line, ", {shift} {r}").unwrap();
                 } else {
                     let shift = Self::gen_random_shift();
-                    if shift != "" {
+                    if !shift.is_empty() {
                         write!(line, ", {shift}").unwrap();
                     }
                 }
             } else if self.modified_imm_value {
                 let imm = Self::gen_modified_imm_value();
                 write!(line, ", #{imm}").unwrap();
-            } else if self.imm_value.is_some() {
-                let bounds = self.imm_value.unwrap();
+            } else if let Some(bounds) = self.imm_value {
                 let imm: u32 = rng.gen_range(bounds.0..bounds.1);
                 write!(line, ", #{imm}").unwrap();
                 if self.end_addr.is_some() && (self.end_addr.unwrap() > self.num_regs) {
@@ -257,7 +257,7 @@ fn gas_assemble_input(input: String) -> String {
     let mut stdin = asm_proc.stdin.take().expect("failed to get stdin");
     std::thread::spawn(move || {
         stdin
-            .write_all(&input.as_bytes())
+            .write_all(input.as_bytes())
             .expect("failed to write to stdin")
     });
 
@@ -271,7 +271,7 @@ fn gas_assemble_input(input: String) -> String {
     for line in err_str.lines() {
         println!("{}", line);
     }
-    if err_str.len() > 0 {
+    if !err_str.is_empty() {
         panic!("Failed to assemble input");
     }
 
@@ -282,11 +282,14 @@ fn disassemble_and_compare(gas_output: &str) {
     for line in gas_output.lines() {
         match AsmLine::from_str(line) {
             Ok(asm_line) => {
-                assert_eq!(
-                    disassemble_arm(asm_line.encoding),
-                    Ok(asm_line.instr),
-                    "Disassembling {line}",
-                )
+                // Branch targets are written as absolute addresses
+                let decoded = match asm_line.instr.op {
+                    Op::B | Op::BL => {
+                        disassemble_arm_at(asm_line.addr, asm_line.encoding, Arch::ARMv4T)
+                    }
+                    _ => disassemble_arm(asm_line.encoding, Arch::ARMv4T),
+                };
+                assert_eq!(decoded, Ok(asm_line.instr), "Disassembling {line}")
             }
             Err(err) => {
                 if let ParseError::Failure { msg } = &err {
@@ -304,10 +307,10 @@ fn disassembler_test_case(input: &str) {
     let output = gas_assemble_input(input.to_string());
 
     let mut f = std::fs::File::create("test_in.s").unwrap();
-    f.write_all(&input.as_bytes()).unwrap();
+    f.write_all(input.as_bytes()).unwrap();
 
     let mut f = std::fs::File::create("test_out.s").unwrap();
-    f.write_all(&output.as_bytes()).unwrap();
+    f.write_all(output.as_bytes()).unwrap();
 
     disassemble_and_compare(&output);
 }
@@ -396,15 +399,17 @@ fn test_disasm_branch() {
         .no_pc()
         .register()
         .generate();
-    let b = AsmGenerator::new("B")
-        .no_s_suffix()
-        .immediate_value(0, 1 << 24)
-        .generate();
-    let bl = AsmGenerator::new("BL")
-        .no_s_suffix()
-        .immediate_value(0, 1 << 24)
-        .generate();
-    let input = bx + &b + &bl;
+    // Targets are absolute addresses, within range of the instructions near address 0
+    let mut rng = thread_rng();
+    let mut branches = String::new();
+    for op in ["B", "BL"] {
+        for _ in 0..16 {
+            let cond = COND_OPTS.choose(&mut rng).unwrap();
+            let target: u32 = rng.gen_range(0..(1 << 23)) * 4;
+            writeln!(&mut branches, "{op}{cond} {target:#010x}").unwrap();
+        }
+    }
+    let input = bx + &branches;
     disassembler_test_case(&input);
 }
 
@@ -471,10 +476,145 @@ fn test_disasm_load_store_post_index_only(#[values("LDRT", "STRT")] op: &str) {
             comb[0], comb[1], comb[2]
         )
         .unwrap();
-        if shift != "" {
+        if !shift.is_empty() {
             write!(&mut input, ", {shift}").unwrap();
         }
-        writeln!(&mut input, "").unwrap();
+        writeln!(&mut input).unwrap();
+    }
+    disassembler_test_case(&input);
+}
+
+/// Formats a randomly chosen, non-empty register list which excludes `exclude`
+fn gen_random_reg_list(min_len: u32, exclude: Option<usize>) -> String {
+    let mut rng = thread_rng();
+    loop {
+        let mask: u16 = rng.gen();
+        let regs: Vec<&str> = (0..16)
+            .filter(|&i| (mask >> i) & 1 == 1 && Some(i) != exclude)
+            .map(|i| REG_OPTS[i])
+            .collect();
+        if regs.len() >= min_len as usize {
+            return format!("{{{}}}", regs.join(", "));
+        }
+    }
+}
+
+#[rstest]
+fn test_disasm_block_data_transfer(
+    #[values("LDMIA", "LDMIB", "LDMDA", "LDMDB", "STMIA", "STMIB", "STMDA", "STMDB")] op: &str,
+) {
+    let mut rng = thread_rng();
+    let mut input = String::new();
+    // SP is excluded as a base since some forms disassemble to PUSH/POP
+    for (base, reg) in REG_OPTS[..13].iter().enumerate() {
+        for excl in ["", "!"] {
+            let cond = COND_OPTS.choose(&mut rng).unwrap();
+            // Writeback with the base register in the list is UNPREDICTABLE
+            let list = gen_random_reg_list(1, Some(base));
+            writeln!(&mut input, "{op}{cond} {reg}{excl}, {list}").unwrap();
+        }
+    }
+    disassembler_test_case(&input);
+}
+
+#[rstest]
+fn test_disasm_push_pop(#[values("PUSH", "POP")] op: &str) {
+    let mut rng = thread_rng();
+    let mut input = String::new();
+    for _ in 0..32 {
+        let cond = COND_OPTS.choose(&mut rng).unwrap();
+        // A single register is encoded as STR/LDR instead
+        let list = gen_random_reg_list(2, Some(13));
+        writeln!(&mut input, "{op}{cond} {list}").unwrap();
+    }
+    disassembler_test_case(&input);
+}
+
+#[rstest]
+fn test_disasm_status_reg(#[values("CPSR", "SPSR")] psr: &str) {
+    let mut rng = thread_rng();
+    let mut input = String::new();
+    for reg in REG_OPTS[..15].iter() {
+        let cond = COND_OPTS.choose(&mut rng).unwrap();
+        writeln!(&mut input, "MRS{cond} {reg}, {psr}").unwrap();
+
+        // Any non-empty combination of fields
+        let mask = rng.gen_range(1..16);
+        let fields: String = ['c', 'x', 's', 'f']
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| (mask >> i) & 1 == 1)
+            .map(|(_, f)| f)
+            .collect();
+        writeln!(&mut input, "MSR{cond} {psr}_{fields}, {reg}").unwrap();
+        let imm = AsmGenerator::gen_modified_imm_value();
+        writeln!(&mut input, "MSR{cond} {psr}_{fields}, #{imm}").unwrap();
+    }
+    disassembler_test_case(&input);
+}
+
+#[rstest]
+fn test_disasm_coprocessor(#[values("MCR", "MRC", "CDP", "LDC", "STC", "LDCL", "STCL")] op: &str) {
+    let mut rng = thread_rng();
+    let mut input = String::new();
+    for reg in REG_OPTS[..15].iter() {
+        let cond = COND_OPTS.choose(&mut rng).unwrap();
+        let coproc = rng.gen_range(0..16);
+        let [crd, crn, crm] = [0; 3].map(|_| rng.gen_range(0..16));
+        let opc2 = rng.gen_range(0..8);
+        match op {
+            "MCR" | "MRC" => {
+                let opc1 = rng.gen_range(0..8);
+                writeln!(&mut input, "{op}{cond} p{coproc}, {opc1}, {reg}, c{crn}, c{crm}, {opc2}")
+            }
+            "CDP" => {
+                let opc1 = rng.gen_range(0..16);
+                writeln!(&mut input, "{op}{cond} p{coproc}, {opc1}, c{crd}, c{crn}, c{crm}, {opc2}")
+            }
+            _ => {
+                let offset = rng.gen_range(0..256) * 4;
+                let option = rng.gen_range(0..256);
+                let sign = ["", "-"].choose(&mut rng).unwrap();
+                writeln!(&mut input, "{op}{cond} p{coproc}, c{crd}, [{reg}, #{sign}{offset}]")
+                    .unwrap();
+                writeln!(&mut input, "{op}{cond} p{coproc}, c{crd}, [{reg}, #{sign}{offset}]!")
+                    .unwrap();
+                writeln!(&mut input, "{op}{cond} p{coproc}, c{crd}, [{reg}], #{sign}{offset}")
+                    .unwrap();
+                writeln!(&mut input, "{op}{cond} p{coproc}, c{crd}, [{reg}], {{{option}}}")
+            }
+        }
+        .unwrap();
+    }
+    disassembler_test_case(&input);
+}
+
+#[rstest]
+fn test_disasm_multiply(
+    #[values("MUL", "MLA", "UMULL", "UMLAL", "SMULL", "SMLAL", "SWP", "SWPB")] op: &str,
+) {
+    let mut rng = thread_rng();
+    let mut input = String::new();
+    for _ in 0..32 {
+        let cond = COND_OPTS.choose(&mut rng).unwrap();
+        // Distinct registers avoid the UNPREDICTABLE overlapping forms
+        let regs: Vec<_> = REG_OPTS[..15].choose_multiple(&mut rng, 4).collect();
+        let s = match op {
+            "SWP" | "SWPB" => "",
+            _ => S_OPTS.choose(&mut rng).unwrap(),
+        };
+        match op {
+            "MUL" => writeln!(&mut input, "{op}{cond}{s} {}, {}, {}", regs[0], regs[1], regs[2]),
+            "SWP" | "SWPB" => {
+                writeln!(&mut input, "{op}{cond} {}, {}, [{}]", regs[0], regs[1], regs[2])
+            }
+            _ => writeln!(
+                &mut input,
+                "{op}{cond}{s} {}, {}, {}, {}",
+                regs[0], regs[1], regs[2], regs[3]
+            ),
+        }
+        .unwrap();
     }
     disassembler_test_case(&input);
 }
@@ -1,9 +1,17 @@
+#![allow(non_snake_case)]
 use ndsjit::{
-    ir::parsing::instruction, ir::Instruction, translate::block_translator::BlockTranslator,
+    disasm::{Arch, InstrSet},
+    ir::parsing::{instruction, parse_program},
+    ir::Instruction,
+    translate::block_translator::BlockTranslator,
 };
 use std::{mem, ptr};
 
 type Func = unsafe extern "C" fn(*mut [u32; 17]) -> i32;
+const V: u32 = 1 << 28;
+const C: u32 = 1 << 29;
+const Z: u32 = 1 << 30;
+const N: u32 = 1 << 31;
 
 const PROG: &str = "
     mov r11, #1234
@@ -12,7 +20,6 @@ const PROG: &str = "
 
 #[test]
 fn test_MOV() {
-    let mut translator = BlockTranslator::new();
     let mut code = vec![];
     for line in PROG.trim().lines() {
         println!("{}", line.trim());
@@ -20,38 +27,103 @@ fn test_MOV() {
         dbg!(&instr);
         code.push(instr);
     }
-    let func_ptr = translator.translate(&code).unwrap();
-    let mut regs = [0u32; 17];
+    let regs = run(&code, [0; 17]);
+    assert_eq!(regs[2], 1234);
+    assert_eq!(regs[11], 1234);
+}
+
+/// Translates and runs `code`, starting from `regs`, and returns the registers it leaves
+fn run(code: &[Instruction], mut regs: [u32; 17]) -> [u32; 17] {
+    let mut translator = BlockTranslator::new(Arch::ARMv5TE);
+    let func_ptr = translator.translate(code, InstrSet::ARM).unwrap();
     unsafe {
         let func: Func = mem::transmute(func_ptr);
         func(ptr::addr_of_mut!(regs));
     }
-    assert_eq!(regs[2], 1234);
-    assert_eq!(regs[11], 1234);
+    regs
 }
 
+/// Parses one of the programs under tests/test_programs, which starts at address 0
 fn parse_asm_file(filepath: &str) -> Vec<Instruction> {
     let src = std::fs::read_to_string(filepath).unwrap();
-    let mut code = vec![];
-    for line in src.trim().lines() {
-        if line.starts_with("#") || line.trim().len() == 0 {
-            continue;
-        }
-        let (_, instr) = instruction(line).unwrap();
-        code.push(instr);
-    }
-    code
+    let program =
+        parse_program(&src, 0, Arch::ARMv5TE).unwrap_or_else(|e| panic!("{}: {}", filepath, e));
+    program.instructions().map(|(_, _, instr)| *instr).collect()
+}
+
+/// Runs one of the programs under tests/test_programs, starting with every register and flag 0
+fn run_asm_file(filepath: &str) -> [u32; 17] {
+    run(&parse_asm_file(filepath), [0; 17])
 }
 
 #[test]
 fn test_data_proc() {
-    let code = parse_asm_file("tests/test_programs/data_proc.asm");
-    let mut translator = BlockTranslator::new();
-    let func_ptr = translator.translate(&code).unwrap();
-    let mut regs = [0u32; 17];
-    unsafe {
-        let func: Func = mem::transmute(func_ptr);
-        func(ptr::addr_of_mut!(regs));
-    }
+    let regs = run_asm_file("tests/test_programs/data_proc.asm");
     assert_eq!(regs, [20, 25, 120, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
 }
+
+#[test]
+fn test_multiply() {
+    let regs = run_asm_file("tests/test_programs/multiply.asm");
+    assert_eq!(
+        regs,
+        [
+            7, 6, 42, 259, 0x80000000, 3, 0x80000009, 1, 0x8000002A, 0xFFFFFFFE, 0x80000000, 0, 0,
+            0, 0, 0, N
+        ]
+    );
+}
+
+#[test]
+fn test_multiply_flags() {
+    // A zero 64-bit result sets Z and clears N, while C and V are unchanged
+    let (_, instr) = instruction("umulls r0, r1, r2, r3").unwrap();
+    let mut regs = [0u32; 17];
+    regs[2] = 0x10000;
+    regs[16] = N | C | V;
+    let regs = run(&[instr], regs);
+    assert_eq!(regs[16], Z | C | V);
+}
+
+#[test]
+fn test_branch() {
+    let regs = run_asm_file("tests/test_programs/branch.asm");
+    assert_eq!(regs[..3], [5, 6, 6]);
+    // The branch is taken, to the label following the data
+    assert_eq!(regs[15], 0x1C);
+}
+
+#[test]
+fn test_arithmetic() {
+    let regs = run_asm_file("tests/test_programs/arithmetic.asm");
+    assert_eq!(regs[..13], [0xFFFFFFFF, 1, 1, 2, 0, 4, 2, 0, 1, 0xFFFFFFFE, 1, 0x41, 0xFFFFFFFC]);
+    // From the CMN
+    assert_eq!(regs[16], Z | C);
+}
+
+#[test]
+fn test_logical() {
+    let regs = run_asm_file("tests/test_programs/logical.asm");
+    assert_eq!(
+        regs[..13],
+        [
+            0xFF00, 0x0FF0, 0x0F00, 0xFFF0, 0xF0F0, 0xF000, 0xFFFFF00F, 0xFFFF, 0x0F0F, 8, 1,
+            0xFE000000, 0xF000
+        ]
+    );
+    // N and Z from the BICS, and C from the MOVS before it
+    assert_eq!(regs[16], C);
+}
+
+#[test]
+fn test_shifts() {
+    let regs = run_asm_file("tests/test_programs/shifts.asm");
+    assert_eq!(
+        regs[..13],
+        [
+            0xFFFFFF80, 4, 0xFFFFF800, 0xF, 0xFFFFFFF8, 0x80FFFFFF, 0x0FFFFFF8, 0xFFFFFFF8,
+            0x0FFFFFF8, 0, 0x80000002, 0xFFFFFFFF, 0
+        ]
+    );
+    assert_eq!(regs[16], Z | C);
+}
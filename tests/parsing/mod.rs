@@ -60,7 +60,7 @@ impl Display for ParseError {
 
 impl Error for ParseError {}
 
-fn asm_line(i: &str) -> ParseResult<AsmLine> {
+fn asm_line(i: &str) -> ParseResult<'_, AsmLine> {
     let mut hex = map_res(hex_digit1, |s| u32::from_str_radix(s, 16));
     let (i, _) = multispace0(i)?;
     let (i, ind) = match_u32(i)?;
@@ -81,7 +81,7 @@ impl FromStr for AsmLine {
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
         // Assembler output includes a lot of empty strings, and page headers
-        if input.len() == 0 || input.trim().starts_with("ARM GAS") {
+        if input.is_empty() || input.trim().starts_with("ARM GAS") {
             return Err(ParseError::NotParsed);
         }
         let (_, res) = asm_line(input).map_err(|e| ParseError::from_nom_err(input, e))?;
@@ -110,7 +110,11 @@ mod tests {
                 instr: Instruction {
                     cond: Cond::GE,
                     op: Op::AND,
-                    operands: vec![Operand::Reg(SP), Operand::Reg(LR), Operand::Reg(R4)],
+                    operands: ndsjit::operands![
+                        Operand::Reg(SP),
+                        Operand::Reg(LR),
+                        Operand::Reg(R4)
+                    ],
                     set_flags: false,
                     extra: Some(Shift::reg(LSL, R6).into())
                 }
@@ -0,0 +1,23 @@
+@ 64-bit arithmetic, chaining the carry between the halves
+        mvn r0, #0              @ r1:r0 = 0x00000001_FFFFFFFF
+        mov r1, #1
+        mov r2, #1              @ r3:r2 = 0x00000002_00000001
+        mov r3, #2
+        adds r4, r0, r2         @ r5:r4 = r1:r0 + r3:r2
+        adc r5, r1, r3
+        subs r6, r2, r0         @ r7:r6 = r3:r2 - r1:r0
+        sbc r7, r3, r1
+        rsbs r8, r0, #0         @ r9:r8 = -(r1:r0)
+        rsc r9, r1, #0
+        cmp r4, #0
+        moveq r10, #1
+        movne r10, #2
+        cmn r0, #1              @ sets Z and C
+        addcs r11, r10, #0x40
+        sub r12, r5, r3, lsl r3
+
+@ r5:r4 = 0x00000004_00000000
+@ r7:r6 = 0x00000000_00000002
+@ r9:r8 = 0xFFFFFFFE_00000001
+@ r10 = 1, r11 = 0x41
+@ r12 = 4 - (2 << 2) = -4
@@ -0,0 +1,7 @@
+@ A conditional branch over some data
+        mov r0, #5
+        add r1, r0, #1
+        movs r2, r1
+        bne done            @ taken, as r2 isn't 0
+table:  .word 1, 2, 3
+done:
@@ -0,0 +1,22 @@
+@ Logical operations, and the carry out of the shifter
+        mov r0, #0xFF00
+        mov r1, #0x0FF0
+        mov r9, #8
+        and r2, r0, r1
+        orr r3, r0, r1
+        eor r4, r0, r1
+        bic r5, r0, r1
+        mvn r6, r1
+        orr r7, r0, r1, lsr #4
+        eor r8, r1, r0, ror r9
+        tst r0, #0xFF           @ sets Z
+        moveq r10, #1
+        teq r0, r1              @ clears Z
+        moveq r10, #2
+        movs r11, r0, lsl #17   @ shifts bit 15 out into C
+        bics r12, r4, #0xF0     @ leaves C alone, as the immediate isn't rotated
+
+@ r2 = 0x0F00, r3 = 0xFFF0, r4 = 0xF0F0, r5 = 0xF000
+@ r6 = 0xFFFFF00F, r7 = 0xFFFF, r8 = 0x0FF0 ^ 0xFF = 0x0F0F
+@ r10 = 1
+@ r11 = 0xFE000000, r12 = 0xF000
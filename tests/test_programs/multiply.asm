@@ -0,0 +1,17 @@
+mov r0, #7
+mov r1, #6
+mul r2, r0, r1
+mla r3, r2, r1, r0
+mov r4, #2147483648
+mov r5, #3
+umull r6, r7, r4, r5
+smull r8, r9, r4, r5
+umlal r6, r7, r5, r5
+smlal r8, r9, r0, r1
+muls r10, r4, r5
+
+# r2 = 42
+# r3 = 42 * 6 + 7 = 259
+# r7:r6 = 0x80000000 * 3 + 9 = 0x1_80000009
+# r9:r8 = -0x80000000 * 3 + 42 = 0xFFFFFFFE_8000002A
+# r10 = 0x80000000, which sets N
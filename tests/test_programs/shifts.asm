@@ -0,0 +1,18 @@
+@ The shift instructions, which are encoded as MOV with a shifted register
+        mvn r0, #0x7F           @ 0xFFFFFF80
+        mov r1, #4
+        lsl r2, r0, #4
+        lsr r3, r0, #28
+        asr r4, r0, #4
+        ror r5, r0, #8
+        lsr r6, r0, r1
+        asr r7, r0, r1
+        ror r8, r0, r1
+        lsls r9, r1, #30        @ shifts bit 2 out into C, leaving 0
+        rrx r10, r1
+        asrs r11, r0, #32
+        lsrs r12, r1, #3        @ C is bit 2 again
+
+@ r2 = 0xFFFFF800, r3 = 0xF, r4 = 0xFFFFFFF8, r5 = 0x80FFFFFF
+@ r6 = 0x0FFFFFF8, r7 = 0xFFFFFFF8, r8 = 0x0FFFFFF8
+@ r9 = 0, r10 = 0x80000002, r11 = 0xFFFFFFFF, r12 = 0